@@ -0,0 +1,494 @@
+use crate::component::{AnyComponent, AnyValue};
+use crate::entity::Entities;
+use crate::system::{Rows, Slot};
+use crate::{Access, Component, Entity};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::mem;
+
+/// Object safe part of the `Vec` holding the components of one type of an `Archetype`,
+/// so that the columns of different component types can be stored together.
+pub(crate) trait ArchetypeColumn: Any + Debug + Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// Creates an empty column of the same component type.
+    fn new_empty(&self) -> Box<dyn ArchetypeColumn>;
+    fn get(&self, row: usize) -> Option<&AnyValue>;
+    fn push(&mut self, component: Box<dyn AnyComponent>);
+    fn replace(&mut self, row: usize, component: Box<dyn AnyComponent>) -> Box<dyn AnyComponent>;
+    fn swap_remove(&mut self, row: usize) -> Box<dyn AnyComponent>;
+    /// Moves the component in the row to the end of a column of the same type, without
+    /// boxing it.
+    fn move_row(&mut self, row: usize, to: &mut dyn ArchetypeColumn);
+    fn slots(&mut self) -> Vec<Option<Slot<'_>>>;
+}
+
+fn unbox<C: Component>(component: Box<dyn AnyComponent>) -> C {
+    match component.into_any().downcast::<C>() {
+        Ok(component) => *component,
+        Err(_) => panic!("component stored in the column of another type"),
+    }
+}
+
+fn boxed<C: Component>(component: C) -> Box<dyn AnyComponent> {
+    Box::new(component)
+}
+
+impl<C: Component> ArchetypeColumn for Vec<C> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn new_empty(&self) -> Box<dyn ArchetypeColumn> {
+        Box::new(Vec::<C>::new())
+    }
+
+    fn get(&self, row: usize) -> Option<&AnyValue> {
+        let component: &AnyValue = self.as_slice().get(row)?;
+        Some(component)
+    }
+
+    fn push(&mut self, component: Box<dyn AnyComponent>) {
+        Vec::push(self, unbox(component));
+    }
+
+    fn replace(&mut self, row: usize, component: Box<dyn AnyComponent>) -> Box<dyn AnyComponent> {
+        boxed(mem::replace(&mut self[row], unbox(component)))
+    }
+
+    fn swap_remove(&mut self, row: usize) -> Box<dyn AnyComponent> {
+        boxed(Vec::swap_remove(self, row))
+    }
+
+    fn move_row(&mut self, row: usize, to: &mut dyn ArchetypeColumn) {
+        match to.as_any_mut().downcast_mut::<Vec<C>>() {
+            Some(to) => to.push(Vec::swap_remove(self, row)),
+            None => panic!("component moved to the column of another type"),
+        }
+    }
+
+    fn slots(&mut self) -> Vec<Option<Slot<'_>>> {
+        self.iter_mut().map(|c| Some(Slot::Free(c))).collect()
+    }
+}
+
+/// A table of the entities which have exactly the same component types. Row `i` of
+/// every column belongs to the entity `entities[i]`.
+#[derive(Debug)]
+struct Archetype {
+    types: Vec<TypeId>,
+    columns: HashMap<TypeId, Box<dyn ArchetypeColumn>>,
+    entities: Vec<u32>,
+}
+
+impl Archetype {
+    fn contains(&self, type_id: TypeId) -> bool {
+        self.types.binary_search(&type_id).is_ok()
+    }
+
+    fn column<C: Component>(&self) -> Option<&Vec<C>> {
+        self.columns
+            .get(&TypeId::of::<C>())?
+            .as_any()
+            .downcast_ref::<Vec<C>>()
+    }
+
+    fn column_mut<C: Component>(&mut self) -> Option<&mut Vec<C>> {
+        self.columns
+            .get_mut(&TypeId::of::<C>())?
+            .as_any_mut()
+            .downcast_mut::<Vec<C>>()
+    }
+}
+
+/// Returns mutable references to two different archetypes.
+fn pair_mut(archetypes: &mut [Archetype], a: usize, b: usize) -> (&mut Archetype, &mut Archetype) {
+    if a < b {
+        let (left, right) = archetypes.split_at_mut(b);
+        (&mut left[a], &mut right[0])
+    } else {
+        let (left, right) = archetypes.split_at_mut(a);
+        (&mut right[0], &mut left[b])
+    }
+}
+
+/// The components of the entities of a `World`, with an `Archetype` per set of component
+/// types. Adding or removing a component moves the entity to another archetype, which is
+/// remembered so that the next entity making the same move doesn't have to look it up.
+#[derive(Debug)]
+pub(crate) struct Archetypes {
+    archetypes: Vec<Archetype>,
+    by_types: HashMap<Vec<TypeId>, usize>,
+    /// The archetype reached by adding a type to an archetype without it, or removing it
+    /// from an archetype with it.
+    edges: HashMap<(usize, TypeId), usize>,
+    /// The archetype and row of each entity index.
+    locations: Vec<Option<(usize, usize)>>,
+}
+
+impl Default for Archetypes {
+    fn default() -> Self {
+        let empty = Archetype {
+            types: Vec::new(),
+            columns: HashMap::new(),
+            entities: Vec::new(),
+        };
+        let mut by_types = HashMap::new();
+        by_types.insert(Vec::new(), 0);
+        Archetypes {
+            archetypes: vec![empty],
+            by_types,
+            edges: HashMap::new(),
+            locations: Vec::new(),
+        }
+    }
+}
+
+impl Archetypes {
+    fn location(&self, index: u32) -> Option<(usize, usize)> {
+        self.locations.get(index as usize).cloned().flatten()
+    }
+
+    fn set_location(&mut self, index: u32, location: Option<(usize, usize)>) {
+        if self.locations.len() <= index as usize {
+            self.locations.resize(index as usize + 1, None);
+        }
+        self.locations[index as usize] = location;
+    }
+
+    /// Returns the location of an entity, putting it in the archetype without components
+    /// if it isn't stored yet.
+    fn locate(&mut self, index: u32) -> (usize, usize) {
+        match self.location(index) {
+            Some(location) => location,
+            None => {
+                let empty = &mut self.archetypes[0];
+                empty.entities.push(index);
+                let location = (0, empty.entities.len() - 1);
+                self.set_location(index, Some(location));
+                location
+            }
+        }
+    }
+
+    /// Returns the archetype with the component types of `types`, creating it with
+    /// columns made by `column` if there isn't one yet.
+    fn archetype(
+        &mut self,
+        mut types: Vec<TypeId>,
+        mut column: impl FnMut(TypeId) -> Box<dyn ArchetypeColumn>,
+    ) -> usize {
+        types.sort();
+        if let Some(archetype) = self.by_types.get(&types) {
+            return *archetype;
+        }
+        let archetype = Archetype {
+            columns: types.iter().map(|t| (*t, column(*t))).collect(),
+            types: types.clone(),
+            entities: Vec::new(),
+        };
+        self.archetypes.push(archetype);
+        self.by_types.insert(types, self.archetypes.len() - 1);
+        self.archetypes.len() - 1
+    }
+
+    /// Returns the archetype reached by adding the type to, or removing it from, an
+    /// archetype. `column` makes the column of the type if it is added.
+    fn toggle(
+        &mut self,
+        from: usize,
+        type_id: TypeId,
+        column: impl FnOnce() -> Box<dyn ArchetypeColumn>,
+    ) -> usize {
+        if let Some(to) = self.edges.get(&(from, type_id)) {
+            return *to;
+        }
+        let source = &self.archetypes[from];
+        let mut types = source.types.clone();
+        match types.iter().position(|t| *t == type_id) {
+            Some(position) => {
+                types.remove(position);
+            }
+            None => types.push(type_id),
+        }
+        let mut empties: HashMap<TypeId, Box<dyn ArchetypeColumn>> = source
+            .columns
+            .iter()
+            .map(|(t, c)| (*t, c.new_empty()))
+            .collect();
+        let mut column = Some(column);
+        let to = self.archetype(types, |t| match empties.remove(&t) {
+            Some(empty) => empty,
+            None => match column.take() {
+                Some(column) => column(),
+                None => unreachable!(),
+            },
+        });
+        self.edges.insert((from, type_id), to);
+        self.edges.insert((to, type_id), from);
+        to
+    }
+
+    /// Moves an entity from its archetype to another one. Components of types which the
+    /// other archetype doesn't have are returned.
+    fn move_entity(
+        &mut self,
+        index: u32,
+        (from, row): (usize, usize),
+        to: usize,
+    ) -> Vec<(TypeId, Box<dyn AnyComponent>)> {
+        let (source, target) = pair_mut(&mut self.archetypes, from, to);
+        let mut left = Vec::new();
+        for (type_id, column) in source.columns.iter_mut() {
+            match target.columns.get_mut(type_id) {
+                Some(to) => column.move_row(row, &mut **to),
+                None => left.push((*type_id, column.swap_remove(row))),
+            }
+        }
+        source.entities.swap_remove(row);
+        let moved = source.entities.get(row).cloned();
+        target.entities.push(index);
+        let new_row = target.entities.len() - 1;
+        if let Some(moved) = moved {
+            self.set_location(moved, Some((from, row)));
+        }
+        self.set_location(index, Some((to, new_row)));
+        left
+    }
+
+    pub(crate) fn get<C: Component>(&self, index: u32) -> Option<&C> {
+        let (archetype, row) = self.location(index)?;
+        self.archetypes[archetype]
+            .column::<C>()?
+            .as_slice()
+            .get(row)
+    }
+
+    pub(crate) fn get_mut<C: Component>(&mut self, index: u32) -> Option<&mut C> {
+        let (archetype, row) = self.location(index)?;
+        self.archetypes[archetype].column_mut::<C>()?.get_mut(row)
+    }
+
+    pub(crate) fn insert<C: Component>(&mut self, index: u32, component: C) -> Option<C> {
+        let (from, row) = self.locate(index);
+        if let Some(column) = self.archetypes[from].column_mut::<C>() {
+            return Some(mem::replace(&mut column[row], component));
+        }
+        let to = self.toggle(from, TypeId::of::<C>(), || Box::new(Vec::<C>::new()));
+        self.move_entity(index, (from, row), to);
+        self.archetypes[to].column_mut::<C>()?.push(component);
+        None
+    }
+
+    pub(crate) fn remove<C: Component>(&mut self, index: u32) -> Option<C> {
+        self.remove_boxed(index, TypeId::of::<C>()).map(unbox)
+    }
+
+    pub(crate) fn insert_boxed(
+        &mut self,
+        index: u32,
+        type_id: TypeId,
+        component: Box<dyn AnyComponent>,
+    ) -> Option<Box<dyn AnyComponent>> {
+        let (from, row) = self.locate(index);
+        if let Some(column) = self.archetypes[from].columns.get_mut(&type_id) {
+            return Some(column.replace(row, component));
+        }
+        let to = self.toggle(from, type_id, || component.new_archetype_column());
+        self.move_entity(index, (from, row), to);
+        if let Some(column) = self.archetypes[to].columns.get_mut(&type_id) {
+            column.push(component);
+        }
+        None
+    }
+
+    pub(crate) fn remove_boxed(
+        &mut self,
+        index: u32,
+        type_id: TypeId,
+    ) -> Option<Box<dyn AnyComponent>> {
+        let (from, row) = self.location(index)?;
+        if !self.archetypes[from].contains(type_id) {
+            return None;
+        }
+        let to = self.toggle(from, type_id, || unreachable!());
+        let mut left = self.move_entity(index, (from, row), to);
+        left.pop().map(|(_, component)| component)
+    }
+
+    /// Moves the components of an `Entity` into the archetype of its component types.
+    pub(crate) fn insert_entity(&mut self, index: u32, entity: Entity) {
+        self.remove_entity(index);
+        let types = entity.components.keys().cloned().collect();
+        let components = &entity.components;
+        let archetype = self.archetype(types, |t| components[&t].new_archetype_column());
+        let archetype_ref = &mut self.archetypes[archetype];
+        for (type_id, component) in entity.components {
+            if let Some(column) = archetype_ref.columns.get_mut(&type_id) {
+                column.push(component);
+            }
+        }
+        archetype_ref.entities.push(index);
+        let row = archetype_ref.entities.len() - 1;
+        self.set_location(index, Some((archetype, row)));
+    }
+
+    /// Moves every component of the entity with the index out of its archetype.
+    pub(crate) fn remove_entity(&mut self, index: u32) -> Entity {
+        let mut entity = Entity::default();
+        let (archetype, row) = match self.location(index) {
+            Some(location) => location,
+            None => return entity,
+        };
+        let archetype_ref = &mut self.archetypes[archetype];
+        for (type_id, column) in archetype_ref.columns.iter_mut() {
+            entity.components.insert(*type_id, column.swap_remove(row));
+        }
+        archetype_ref.entities.swap_remove(row);
+        if let Some(moved) = archetype_ref.entities.get(row).cloned() {
+            self.set_location(moved, Some((archetype, row)));
+        }
+        self.set_location(index, None);
+        entity
+    }
+
+    /// Iterates over the components of the entity with the index.
+    pub(crate) fn components(&self, index: u32) -> Vec<(TypeId, &AnyValue)> {
+        let (archetype, row) = match self.location(index) {
+            Some(location) => location,
+            None => return Vec::new(),
+        };
+        self.archetypes[archetype]
+            .columns
+            .iter()
+            .filter_map(|(type_id, column)| column.get(row).map(|c| (*type_id, c)))
+            .collect()
+    }
+
+    /// Lends out the components the `Access` uses of the living entities, with a run of
+    /// rows per archetype. Archetypes `matches` rules out from the types they have are
+    /// skipped.
+    pub(crate) fn borrow(
+        &mut self,
+        entities: &Entities,
+        access: &Access,
+        matches: fn(&dyn Fn(TypeId) -> bool) -> bool,
+    ) -> Vec<Rows<'_>> {
+        let mut borrowed = Vec::new();
+        for archetype in self.archetypes.iter_mut() {
+            if archetype.entities.is_empty() || !matches(&|t| archetype.contains(t)) {
+                continue;
+            }
+            let eids: Vec<_> = archetype
+                .entities
+                .iter()
+                .map(|index| entities.eid(*index))
+                .collect();
+            let mut rows = Rows::new(eids.iter().flatten().cloned().collect());
+            let dead = rows.eids.len() < eids.len();
+            for (type_id, column) in archetype.columns.iter_mut() {
+                if !access.uses_component(type_id) {
+                    continue;
+                }
+                let mut slots = column.slots();
+                if dead {
+                    let mut alive = eids.iter().map(Option::is_some);
+                    slots.retain(|_| alive.next().unwrap_or(false));
+                }
+                rows.add_column(*type_id, slots);
+            }
+            borrowed.push(rows);
+        }
+        borrowed
+    }
+}
+
+#[cfg(test)]
+mod test_archetype {
+
+    use crate::archetype::Archetypes;
+    use crate::entity::Entities;
+    use crate::system::Lend;
+    use crate::{Access, Component, Entity, Read, SystemData};
+    use std::any::TypeId;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos(u32);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Vel(u32);
+
+    impl Component for Pos {}
+    impl Component for Vel {}
+
+    #[test]
+    fn test_entities_move_between_archetypes() {
+        let mut archetypes = Archetypes::default();
+        for i in 0..4 {
+            let mut entity = Entity::default();
+            entity.add_component(Pos(i));
+            archetypes.insert_entity(i, entity);
+        }
+        assert_eq!(archetypes.archetypes.len(), 2);
+
+        // Moving entity 1 swaps entity 3 into its row.
+        assert_eq!(archetypes.insert(1, Vel(10)), None);
+        assert_eq!(archetypes.insert(1, Vel(11)), Some(Vel(10)));
+        assert_eq!(archetypes.archetypes.len(), 3);
+        assert_eq!(archetypes.get::<Pos>(3), Some(&Pos(3)));
+        assert_eq!(archetypes.get::<Pos>(1), Some(&Pos(1)));
+        assert_eq!(archetypes.get::<Vel>(1), Some(&Vel(11)));
+        assert_eq!(archetypes.archetypes[1].entities, vec![0, 3, 2]);
+
+        // The move is remembered, and moving back ends up in the first archetype.
+        archetypes.insert(2, Vel(20));
+        assert_eq!(archetypes.archetypes.len(), 3);
+        assert_eq!(archetypes.remove::<Pos>(2), Some(Pos(2)));
+        assert_eq!(archetypes.remove::<Pos>(2), None);
+        assert_eq!(archetypes.remove::<Vel>(1), Some(Vel(11)));
+        assert_eq!(archetypes.archetypes[1].entities, vec![0, 3, 1]);
+        assert_eq!(archetypes.get::<Vel>(2), Some(&Vel(20)));
+        assert_eq!(archetypes.components(2).len(), 1);
+
+        let entity = archetypes.remove_entity(0);
+        assert_eq!(entity.get_component::<Pos>(), Some(&Pos(0)));
+        assert_eq!(archetypes.get::<Pos>(1), Some(&Pos(1)));
+        assert_eq!(archetypes.get::<Pos>(3), Some(&Pos(3)));
+        assert_eq!(archetypes.get::<Pos>(0), None);
+        assert!(archetypes.components(0).is_empty());
+        assert!(archetypes.remove_boxed(0, TypeId::of::<Pos>()).is_none());
+    }
+
+    #[test]
+    fn test_borrow_rows_per_archetype() {
+        let mut entities = Entities::default();
+        let mut archetypes = Archetypes::default();
+        for i in 0..6 {
+            let eid = entities.insert();
+            archetypes.insert(eid.index(), Pos(i));
+            if i % 2 == 0 {
+                archetypes.insert(eid.index(), Vel(i));
+            }
+        }
+
+        type Fast = Read<Vel>;
+        let borrowed = archetypes.borrow(&entities, &Access::of::<Fast>(), Fast::matches);
+        assert_eq!(borrowed.len(), 1);
+        assert_eq!(borrowed[0].eids.len(), 3);
+        assert!((0..3).all(|row| borrowed[0].has(row, TypeId::of::<Vel>())));
+        assert!((0..3).all(|row| !borrowed[0].has(row, TypeId::of::<Pos>())));
+
+        type Still = Read<Pos>;
+        let borrowed = archetypes.borrow(&entities, &Access::of::<Still>(), Still::matches);
+        let rows: Vec<usize> = borrowed.iter().map(|rows| rows.eids.len()).collect();
+        assert_eq!(rows, vec![3, 3]);
+        assert!(borrowed
+            .iter()
+            .all(|rows| !rows.has(0, TypeId::of::<Vel>())));
+    }
+}
@@ -0,0 +1,363 @@
+use crate::{Access, Component, Fetch, SystemData};
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+/// The ticks a component was added at and last changed at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ComponentTicks {
+    pub(crate) added: u64,
+    pub(crate) changed: u64,
+}
+
+/// The `ComponentTicks` of every stored component, by component type and entity index.
+/// Kept apart from the components so that every storage `Backend` shares it.
+#[derive(Debug, Default)]
+pub(crate) struct ChangeTicks {
+    ticks: HashMap<TypeId, Vec<Option<ComponentTicks>>>,
+}
+
+impl ChangeTicks {
+    pub(crate) fn get(&self, type_id: TypeId, index: u32) -> Option<ComponentTicks> {
+        self.ticks
+            .get(&type_id)?
+            .get(index as usize)
+            .cloned()
+            .flatten()
+    }
+
+    /// Records that a component was added at the tick, or that it changed if the entity
+    /// already had one.
+    pub(crate) fn insert(&mut self, type_id: TypeId, index: u32, tick: u64) {
+        let ticks = self.ticks.entry(type_id).or_default();
+        if ticks.len() <= index as usize {
+            ticks.resize(index as usize + 1, None);
+        }
+        let entry = &mut ticks[index as usize];
+        match entry {
+            Some(entry) => entry.changed = tick,
+            None => {
+                *entry = Some(ComponentTicks {
+                    added: tick,
+                    changed: tick,
+                })
+            }
+        }
+    }
+
+    /// Records that a component changed at the tick.
+    pub(crate) fn change(&mut self, type_id: TypeId, index: u32, tick: u64) {
+        if let Some(Some(entry)) = self
+            .ticks
+            .get_mut(&type_id)
+            .and_then(|ticks| ticks.get_mut(index as usize))
+        {
+            entry.changed = tick;
+        }
+    }
+
+    pub(crate) fn remove(&mut self, type_id: TypeId, index: u32) {
+        if let Some(entry) = self
+            .ticks
+            .get_mut(&type_id)
+            .and_then(|ticks| ticks.get_mut(index as usize))
+        {
+            *entry = None;
+        }
+    }
+
+    pub(crate) fn remove_entity(&mut self, index: u32) {
+        for ticks in self.ticks.values_mut() {
+            if let Some(entry) = ticks.get_mut(index as usize) {
+                *entry = None;
+            }
+        }
+    }
+
+    /// Iterates over the type, entity index and ticks of every stored component.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (TypeId, u32, ComponentTicks)> + '_ {
+        self.ticks.iter().flat_map(|(type_id, ticks)| {
+            ticks
+                .iter()
+                .enumerate()
+                .filter_map(move |(i, t)| t.map(|t| (*type_id, i as u32, t)))
+        })
+    }
+}
+
+/// Filter which only matches entities whose component C changed since the `System` last
+/// ran, including by being added. A component changes when it is borrowed through
+/// `Write<C>`, added to an entity or replaced by a differing value with
+/// `World::set_component_for_entity`. Systems don't see their own changes. The filter
+/// counts as reading C, so it runs after the systems which write C.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Changed, Component, Eid, System, World};
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Health(u8);
+/// impl Component for Health {}
+///
+/// #[derive(Default)]
+/// struct Hurt(Vec<Eid>);
+/// impl System for Hurt {
+///     type Data = (Eid, Changed<Health>);
+///     fn run(&mut self, (eid, _): (Eid, ())) {
+///         self.0.push(eid);
+///     }
+/// }
+///
+/// let mut world = World::default();
+/// let a = world.create_entity().with(Health(3)).build();
+/// let b = world.create_entity().with(Health(3)).build();
+///
+/// let mut hurt = Hurt::default();
+/// world.dispatch_system(&mut hurt);
+/// assert_eq!(hurt.0, vec![a, b]);
+///
+/// world.set_component_for_entity(&a, Health(3)).unwrap();
+/// world.set_component_for_entity(&b, Health(2)).unwrap();
+/// let mut hurt = Hurt::default();
+/// world.dispatch_system(&mut hurt);
+/// assert_eq!(hurt.0, vec![b]);
+/// ```
+#[derive(Debug)]
+pub struct Changed<C>(PhantomData<C>);
+
+impl<C> SystemData for Changed<C>
+where
+    C: Component,
+{
+    type Item<'a> = ();
+
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+        if fetch.changed::<C>() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn access(access: &mut Access) {
+        access.add_read::<C>();
+    }
+
+    fn matches(has: &dyn Fn(TypeId) -> bool) -> bool {
+        has(TypeId::of::<C>())
+    }
+}
+
+/// Filter which only matches entities whose component C was added since the `System`
+/// last ran.
+#[derive(Debug)]
+pub struct Added<C>(PhantomData<C>);
+
+impl<C> SystemData for Added<C>
+where
+    C: Component,
+{
+    type Item<'a> = ();
+
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+        if fetch.added::<C>() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn access(access: &mut Access) {
+        access.add_read::<C>();
+    }
+
+    fn matches(has: &dyn Fn(TypeId) -> bool) -> bool {
+        has(TypeId::of::<C>())
+    }
+}
+
+#[cfg(test)]
+mod test_change {
+
+    use crate::{Added, Backend, Changed, Component, Eid, Read, Schedule, System, World, Write};
+    use std::any::TypeId;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos(f64);
+    impl Component for Pos {}
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Vel(f64);
+    impl Component for Vel {}
+
+    #[derive(Default)]
+    struct Seen(Vec<Eid>);
+    impl System for Seen {
+        type Data = (Eid, Changed<Pos>);
+        fn run(&mut self, (eid, _): (Eid, ())) {
+            self.0.push(eid);
+        }
+    }
+
+    #[derive(Default)]
+    struct New(Vec<Eid>);
+    impl System for New {
+        type Data = (Eid, Added<Pos>);
+        fn run(&mut self, (eid, _): (Eid, ())) {
+            self.0.push(eid);
+        }
+    }
+
+    struct Move;
+    impl System for Move {
+        type Data = (Write<Pos>, Read<Vel>);
+        fn run(&mut self, (pos, vel): (&mut Pos, &Vel)) {
+            pos.0 += vel.0;
+        }
+    }
+
+    fn seen(world: &mut World) -> Vec<Eid> {
+        let mut seen = Seen::default();
+        world.dispatch_system(&mut seen);
+        seen.0
+    }
+
+    #[test]
+    fn test_writes_are_changes() {
+        for backend in [Backend::SparseSet, Backend::Archetype] {
+            let mut world = World::with_backend(backend);
+            let moving = world.create_entity().with(Pos(0.0)).with(Vel(1.0)).build();
+            let still = world.create_entity().with(Pos(0.0)).build();
+
+            assert_eq!(seen(&mut world), vec![moving, still]);
+            assert_eq!(seen(&mut world), vec![]);
+
+            world.dispatch_system(&mut Move);
+            assert_eq!(seen(&mut world), vec![moving]);
+            assert_eq!(seen(&mut world), vec![]);
+
+            world.add_component_to_entity(&still, Pos(1.0)).unwrap();
+            assert_eq!(seen(&mut world), vec![still]);
+        }
+    }
+
+    #[test]
+    fn test_systems_skip_their_own_changes() {
+        struct MoveChanged(usize);
+        impl System for MoveChanged {
+            type Data = (Write<Pos>, Changed<Pos>);
+            fn run(&mut self, (pos, _): (&mut Pos, ())) {
+                pos.0 += 1.0;
+                self.0 += 1;
+            }
+        }
+
+        let mut world = World::default();
+        world.create_entity().with(Pos(0.0)).build();
+        let mut sys = MoveChanged(0);
+        world.dispatch_system(&mut sys);
+        world.dispatch_system(&mut sys);
+        assert_eq!(sys.0, 1);
+    }
+
+    #[test]
+    fn test_added() {
+        let mut world = World::default();
+        let a = world.create_entity().with(Pos(0.0)).build();
+        let b = world.create_entity().build();
+
+        let mut new = New::default();
+        world.dispatch_system(&mut new);
+        assert_eq!(new.0, vec![a]);
+
+        world.add_component_to_entity(&a, Pos(1.0)).unwrap();
+        world.add_component_to_entity(&b, Pos(1.0)).unwrap();
+        let mut new = New::default();
+        world.dispatch_system(&mut new);
+        assert_eq!(new.0, vec![b]);
+
+        world.remove_component_from_entity::<Pos>(&a).unwrap();
+        world.add_component_to_entity(&a, Pos(1.0)).unwrap();
+        let mut new = New::default();
+        world.dispatch_system(&mut new);
+        assert_eq!(new.0, vec![a]);
+    }
+
+    #[test]
+    fn test_changes_since() {
+        let mut world = World::default();
+        let a = world.create_entity().with(Pos(0.0)).with(Vel(1.0)).build();
+        let b = world.create_entity().with(Pos(0.0)).build();
+
+        let tick = world.increment_change_tick();
+        assert_eq!(world.changes_since(tick).count(), 0);
+        assert!(!world.set_component_for_entity(&b, Pos(0.0)).unwrap());
+        assert_eq!(world.changes_since(tick).count(), 0);
+
+        world.dispatch_system(&mut Move);
+        let changes: Vec<_> = world.changes_since(tick).collect();
+        assert_eq!(changes, vec![(a, TypeId::of::<Pos>())]);
+
+        world.destroy_entity(&a).unwrap();
+        assert_eq!(world.changes_since(0).count(), 1);
+    }
+
+    #[test]
+    fn test_instances_of_one_type() {
+        let mut world = World::default();
+        let a = world.create_entity().with(Pos(0.0)).with(Vel(1.0)).build();
+        let mut schedule = Schedule::default();
+        schedule
+            .add_system("first", Seen::default())
+            .build()
+            .unwrap();
+        schedule
+            .add_system("second", Seen::default())
+            .build()
+            .unwrap();
+        schedule.run(&mut world).unwrap();
+
+        world.dispatch_system(&mut Move);
+        let mut first = Seen::default();
+        let mut second = Seen::default();
+        world.dispatch_labeled_system("first", &mut first);
+        world.dispatch_labeled_system("second", &mut second);
+        assert_eq!((first.0, second.0), (vec![a], vec![a]));
+
+        world.dispatch_system(&mut Move);
+        schedule.run_parallel(&mut world).unwrap();
+        world.dispatch_system(&mut Move);
+        let mut first = Seen::default();
+        world.dispatch_labeled_system("first", &mut first);
+        assert_eq!(first.0, vec![a]);
+    }
+
+    #[test]
+    fn test_parallel_schedule() {
+        struct Brake;
+        impl System for Brake {
+            type Data = Write<Vel>;
+            fn run(&mut self, vel: &mut Vel) {
+                vel.0 *= 0.5;
+            }
+        }
+
+        let mut world = World::default();
+        let moving = world.create_entity().with(Pos(0.0)).with(Vel(1.0)).build();
+        world.create_entity().with(Pos(0.0)).build();
+
+        let mut schedule = Schedule::default();
+        schedule
+            .add_system("seen", Seen::default())
+            .build()
+            .unwrap();
+        schedule.add_system("brake", Brake).build().unwrap();
+        assert_eq!(schedule.batches().unwrap(), vec![vec!["seen", "brake"]]);
+
+        let tick = world.increment_change_tick();
+        schedule.run_parallel(&mut world).unwrap();
+        let changes: Vec<_> = world.changes_since(tick).collect();
+        assert_eq!(changes, vec![(moving, TypeId::of::<Vel>())]);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::component::AnyComponent;
+use crate::{Access, Component, Eid, Entity, Fetch, SystemData};
+use std::any::TypeId;
+
+/// A change to the `World` which is queued by a `System`.
+#[derive(Debug)]
+pub(crate) enum Command {
+    Spawn(Entity),
+    Despawn(Eid),
+    Insert(Eid, TypeId, Box<dyn AnyComponent>),
+    Remove(Eid, TypeId),
+}
+
+/// A buffer of changes to the `World` which systems can't make while they hold borrows
+/// of its entities. The commands are applied in the order they were queued once the
+/// `System` finishes, and commands on entities which no longer exist are ignored.
+/// Add `Commands` to `System::Data` to queue commands from a `System`.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Commands, Component, Eid, Read, System, World};
+///
+/// #[derive(Debug, Clone, Copy)]
+/// struct Health(u8);
+/// #[derive(Debug, Clone, Copy)]
+/// struct Corpse;
+/// impl Component for Health {}
+/// impl Component for Corpse {}
+///
+/// struct Reaper;
+/// impl System for Reaper {
+///     type Data = (Eid, Read<Health>, Commands);
+///     fn run(&mut self, (eid, health, commands): (Eid, &Health, &mut Commands)) {
+///         if health.0 == 0 {
+///             commands.despawn(eid);
+///             commands.spawn().with(Corpse);
+///         }
+///     }
+/// }
+///
+/// let mut world = World::default();
+/// let alive = world.create_entity().with(Health(3)).build();
+/// let dead = world.create_entity().with(Health(0)).build();
+/// world.dispatch_system(&mut Reaper);
+///
+/// assert!(world.is_alive(&alive));
+/// assert!(!world.is_alive(&dead));
+/// ```
+#[derive(Debug, Default)]
+pub struct Commands {
+    pub(crate) commands: Vec<Command>,
+}
+
+impl Commands {
+    /// Returns the number of queued commands.
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Returns true if no commands are queued.
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Queues the creation of an `Entity`. Components are added to it with the returned
+    /// `SpawnBuilder`.
+    pub fn spawn(&mut self) -> SpawnBuilder<'_> {
+        self.commands.push(Command::Spawn(Entity::default()));
+        match self.commands.last_mut() {
+            Some(Command::Spawn(entity)) => SpawnBuilder { entity },
+            _ => unreachable!(),
+        }
+    }
+
+    /// Queues the destruction of an `Entity`.
+    pub fn despawn(&mut self, entity: Eid) {
+        self.commands.push(Command::Despawn(entity));
+    }
+
+    /// Queues adding a component to an `Entity`, replacing the component of type C it
+    /// already has.
+    pub fn insert<C: Component>(&mut self, entity: Eid, component: C) {
+        self.commands.push(Command::Insert(
+            entity,
+            TypeId::of::<C>(),
+            Box::new(component),
+        ));
+    }
+
+    /// Queues removing the component C from an `Entity`.
+    pub fn remove<C: Component>(&mut self, entity: Eid) {
+        self.commands
+            .push(Command::Remove(entity, TypeId::of::<C>()));
+    }
+}
+
+/// A helper struct to add components to an `Entity` queued with `Commands::spawn`.
+#[derive(Debug)]
+pub struct SpawnBuilder<'a> {
+    entity: &'a mut Entity,
+}
+
+impl<'a> SpawnBuilder<'a> {
+    /// Adds a component to the `Entity` being spawned.
+    pub fn with<C: Component>(self, component: C) -> Self {
+        self.entity.add_component(component);
+        self
+    }
+}
+
+impl SystemData for Commands {
+    type Item<'a> = &'a mut Commands;
+
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+        fetch.commands()
+    }
+
+    fn access(access: &mut Access) {
+        access.add_commands();
+    }
+}
+
+#[cfg(test)]
+mod test_commands {
+
+    use crate::{Commands, Component, Eid, Read, System, With, World};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos {
+        x: f64,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Bullet;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Gun;
+
+    impl Component for Pos {}
+    impl Component for Bullet {}
+    impl Component for Gun {}
+
+    struct Shoot;
+
+    impl System for Shoot {
+        type Data = (Read<Pos>, With<Gun>, Commands);
+
+        fn run(&mut self, (pos, _, commands): (&Pos, (), &mut Commands)) {
+            commands.spawn().with(Bullet).with(*pos);
+        }
+    }
+
+    #[test]
+    fn test_spawn_from_system() {
+        let mut world = World::default();
+        world.register_component::<Pos>();
+        world.create_entity().with(Pos { x: 1.0 }).with(Gun).build();
+        world.create_entity().with(Pos { x: 2.0 }).with(Gun).build();
+        world.create_entity().with(Pos { x: 3.0 }).build();
+
+        world.dispatch_system(&mut Shoot);
+
+        // Bullets are spawned in the order the guns were visited.
+        let snapshot = world.snapshot(0);
+        let eids: Vec<Eid> = snapshot.entities().collect();
+        assert_eq!(eids.len(), 5);
+        assert_eq!(
+            snapshot.get_component::<Pos>(eids[3]),
+            Some(&Pos { x: 1.0 })
+        );
+        assert_eq!(
+            snapshot.get_component::<Pos>(eids[4]),
+            Some(&Pos { x: 2.0 })
+        );
+        assert!(world.get_component_for_entity::<Bullet>(&eids[3]).is_ok());
+    }
+
+    #[test]
+    fn test_commands_apply_in_order() {
+        let mut world = World::default();
+        let e1 = world.create_entity().with(Pos { x: 1.0 }).build();
+        let e2 = world.create_entity().with(Pos { x: 2.0 }).build();
+
+        let mut commands = Commands::default();
+        commands.remove::<Pos>(e1);
+        commands.insert(e1, Pos { x: 5.0 });
+        commands.insert(e2, Pos { x: 5.0 });
+        commands.remove::<Pos>(e2);
+        commands.despawn(e2);
+        commands.insert(e2, Bullet);
+        assert_eq!(commands.len(), 6);
+        world.apply_commands(commands);
+
+        assert_eq!(
+            world.get_component_for_entity::<Pos>(&e1),
+            Ok(&Pos { x: 5.0 })
+        );
+        assert!(!world.is_alive(&e2));
+    }
+
+    #[test]
+    fn test_commands_skip_stale_entities() {
+        let mut world = World::default();
+        let old = world.create_entity().with(Pos { x: 1.0 }).build();
+        world.destroy_entity(&old).unwrap();
+        let new = world.create_entity().with(Pos { x: 2.0 }).build();
+
+        let mut commands = Commands::default();
+        commands.insert(old, Bullet);
+        commands.despawn(old);
+        world.apply_commands(commands);
+
+        assert!(world.is_alive(&new));
+        assert!(world.get_component_for_entity::<Bullet>(&new).is_err());
+    }
+}
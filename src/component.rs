@@ -1,3 +1,71 @@
+use crate::archetype::ArchetypeColumn;
+use crate::storage::{Column, SparseSet};
+use std::any::Any;
 use std::fmt::Debug;
-/// Trait requirements for all Components.
-pub trait Component: 'static + Clone + Debug + Sized {}
+
+/// Trait requirements for all Components. Components are `Send` and `Sync` so that
+/// systems which don't conflict can borrow them from different threads.
+pub trait Component: 'static + Clone + Debug + Send + Sync + Sized {}
+
+/// A type erased component or resource as it is stored in the `World`.
+pub(crate) type AnyValue = dyn Any + Send + Sync;
+
+/// Object safe view of a `Component` which keeps the ability to be cloned and debug
+/// printed once its concrete type has been erased.
+pub(crate) trait AnyComponent: Any + Debug + Send + Sync {
+    /// Returns the component as `&dyn Any` so that it can be downcast.
+    fn as_any(&self) -> &dyn Any;
+    /// Returns the component as `&mut dyn Any` so that it can be downcast.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// Returns the boxed component as `Box<dyn Any>` so that it can be downcast.
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+    /// Clones the component into a new box.
+    fn clone_boxed(&self) -> Box<dyn AnyComponent>;
+    /// Creates an empty `Column` which can store components of this type.
+    fn new_column(&self) -> Box<dyn Column>;
+    /// Creates an empty column of an archetype which can store components of this type.
+    fn new_archetype_column(&self) -> Box<dyn ArchetypeColumn>;
+}
+
+impl<C> AnyComponent for C
+where
+    C: Component,
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+
+    fn clone_boxed(&self) -> Box<dyn AnyComponent> {
+        Box::new(self.clone())
+    }
+
+    fn new_column(&self) -> Box<dyn Column> {
+        Box::new(SparseSet::<C>::default())
+    }
+
+    fn new_archetype_column(&self) -> Box<dyn ArchetypeColumn> {
+        Box::new(Vec::<C>::new())
+    }
+}
+
+impl Clone for Box<dyn AnyComponent> {
+    fn clone(&self) -> Self {
+        (**self).clone_boxed()
+    }
+}
+
+/// Function used to copy a type erased component out of the `World`.
+pub(crate) type SnapshotFn = fn(&dyn Any) -> Option<Box<dyn AnyComponent>>;
+
+/// Copies a component of type C out of a type erased reference.
+pub(crate) fn snapshot_component<C: Component>(c: &dyn Any) -> Option<Box<dyn AnyComponent>> {
+    c.downcast_ref::<C>().map(AnyComponent::clone_boxed)
+}
@@ -0,0 +1,503 @@
+use crate::component::AnyComponent;
+use crate::snapshot::{decode_component, read_component_info, SnapshotEntity};
+use crate::{
+    BitReader, BitWriter, ComponentId, ComponentInfo, ComponentRegistry, DecodeError, Eid, Encode,
+    EncodeError, Snapshot,
+};
+use std::collections::BTreeSet;
+
+/// Encodes a single registered component on its own so it can be compared.
+fn encode_alone(
+    info: &ComponentInfo,
+    component: &dyn AnyComponent,
+) -> Result<(Vec<u8>, usize), EncodeError> {
+    let encode = info.encode.ok_or(EncodeError::NotEncodable(info.id()))?;
+    let mut writer = BitWriter::new();
+    encode(component, &mut writer);
+    let bits = writer.bit_len();
+    Ok((writer.into_bytes(), bits))
+}
+
+/// Returns true if two components of a registered type are encoded the same. Components
+/// which can't be encoded are never the same.
+pub(crate) fn same_encoding(
+    info: &ComponentInfo,
+    a: &dyn AnyComponent,
+    b: &dyn AnyComponent,
+) -> bool {
+    match (encode_alone(info, a), encode_alone(info, b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// The components of an entity which changed between two snapshots.
+struct EntityDelta<'a> {
+    changed: Vec<(&'a ComponentInfo, &'a dyn AnyComponent)>,
+    removed: Vec<ComponentId>,
+}
+
+impl<'a> EntityDelta<'a> {
+    /// Finds the components of `target` which differ from `baseline` and the components
+    /// of `baseline` which `target` doesn't have anymore. Returns None if there are no
+    /// differences.
+    fn new(
+        baseline: &'a SnapshotEntity,
+        target: &'a SnapshotEntity,
+        registry: &'a ComponentRegistry,
+    ) -> Result<Option<Self>, EncodeError> {
+        let mut changed = Vec::new();
+        for (type_id, component) in target.components.iter() {
+            let info = registry
+                .get_by_type(*type_id)
+                .ok_or(EncodeError::UnregisteredComponent)?;
+            let unchanged = match baseline.components.get(type_id) {
+                Some(old) => encode_alone(info, &**old)? == encode_alone(info, &**component)?,
+                None => false,
+            };
+            if !unchanged {
+                changed.push((info, &**component));
+            }
+        }
+
+        let mut removed = Vec::new();
+        for type_id in baseline.components.keys() {
+            if !target.components.contains_key(type_id) {
+                let info = registry
+                    .get_by_type(*type_id)
+                    .ok_or(EncodeError::UnregisteredComponent)?;
+                removed.push(info.id());
+            }
+        }
+
+        if changed.is_empty() && removed.is_empty() {
+            return Ok(None);
+        }
+        changed.sort_by_key(|(info, _)| info.id());
+        removed.sort();
+        Ok(Some(EntityDelta { changed, removed }))
+    }
+
+    fn encode(&self, writer: &mut BitWriter) -> Result<(), EncodeError> {
+        writer.write_varint(self.changed.len() as u64);
+        for (info, component) in self.changed.iter() {
+            let encode = info.encode.ok_or(EncodeError::NotEncodable(info.id()))?;
+            writer.write_varint(u64::from(info.id().0));
+            encode(*component, writer);
+        }
+        writer.write_varint(self.removed.len() as u64);
+        for id in self.removed.iter() {
+            writer.write_varint(u64::from(id.0));
+        }
+        Ok(())
+    }
+}
+
+fn decode_entity_delta(
+    entity: &mut SnapshotEntity,
+    registry: &ComponentRegistry,
+    reader: &mut BitReader,
+) -> Result<(), DecodeError> {
+    let mut changed = Vec::new();
+    for _ in 0..reader.read_len()? {
+        let (type_id, component) = decode_component(registry, reader)?;
+        if changed.contains(&type_id) {
+            return Err(DecodeError::InvalidValue);
+        }
+        changed.push(type_id);
+        entity.components.insert(type_id, component);
+    }
+    for _ in 0..reader.read_len()? {
+        let type_id = read_component_info(registry, reader)?.type_id();
+        if entity.components.remove(&type_id).is_none() {
+            return Err(DecodeError::InvalidValue);
+        }
+    }
+    Ok(())
+}
+
+impl Snapshot {
+    /// Writes the `Snapshot` as a delta against a baseline `Snapshot` which the receiver
+    /// is known to have, usually the last one it acknowledged. Entities which didn't
+    /// change are skipped, entities which exist in both are sent as the components which
+    /// changed or were removed, and entities which were created or destroyed since the
+    /// baseline are marked explicitly.
+    ///
+    /// # Example
+    /// ```
+    /// extern crate ecsnap;
+    /// use ecsnap::{BitReader, BitWriter, Component, DecodeError, Encode, Snapshot, World};
+    ///
+    /// #[derive(Debug, Clone, Copy, PartialEq)]
+    /// struct Pos {
+    ///     x: f32,
+    ///     y: f32,
+    /// }
+    /// impl Component for Pos {}
+    ///
+    /// impl Encode for Pos {
+    ///     fn encode(&self, writer: &mut BitWriter) {
+    ///         self.x.encode(writer);
+    ///         self.y.encode(writer);
+    ///     }
+    ///     fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+    ///         Ok(Pos {
+    ///             x: f32::decode(reader)?,
+    ///             y: f32::decode(reader)?,
+    ///         })
+    ///     }
+    /// }
+    ///
+    /// let mut world = World::default();
+    /// world.registry_mut().register::<Pos>().encoded().build().unwrap();
+    /// let e1 = world.create_entity().with(Pos { x: 0.0, y: 0.0 }).build();
+    /// let baseline = world.snapshot(1);
+    ///
+    /// let e2 = world.create_entity().with(Pos { x: 1.0, y: 0.0 }).build();
+    /// let target = world.snapshot(2);
+    ///
+    /// let delta = target.to_delta_bytes(&baseline, world.registry()).unwrap();
+    /// assert!(delta.len() < target.to_bytes(world.registry()).unwrap().len());
+    ///
+    /// assert_eq!(Snapshot::delta_baseline(&delta), Ok(1));
+    /// let rebuilt = Snapshot::from_delta_bytes(&baseline, world.registry(), &delta).unwrap();
+    /// assert_eq!(rebuilt.tick(), 2);
+    /// assert_eq!(rebuilt.get_component::<Pos>(e1), Some(&Pos { x: 0.0, y: 0.0 }));
+    /// assert_eq!(rebuilt.get_component::<Pos>(e2), Some(&Pos { x: 1.0, y: 0.0 }));
+    /// ```
+    pub fn encode_delta(
+        &self,
+        baseline: &Snapshot,
+        registry: &ComponentRegistry,
+        writer: &mut BitWriter,
+    ) -> Result<(), EncodeError> {
+        let destroyed: Vec<_> = baseline
+            .entities()
+            .filter(|eid| !self.contains(*eid))
+            .collect();
+
+        let mut created = Vec::new();
+        let mut changed = Vec::new();
+        for (eid, entity) in self.entities.iter() {
+            match baseline.entities.get(eid) {
+                Some(old) => {
+                    if let Some(delta) = EntityDelta::new(old, entity, registry)? {
+                        changed.push((*eid, delta));
+                    }
+                }
+                None => created.push((*eid, entity)),
+            }
+        }
+
+        writer.write_varint(baseline.tick());
+        writer.write_varint(self.tick());
+        writer.write_varint(destroyed.len() as u64);
+        for eid in destroyed {
+            eid.encode(writer);
+        }
+        writer.write_varint(created.len() as u64);
+        for (eid, entity) in created {
+            eid.encode(writer);
+            entity.encode(registry, writer)?;
+        }
+        writer.write_varint(changed.len() as u64);
+        for (eid, delta) in changed {
+            eid.encode(writer);
+            delta.encode(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the tick of the baseline a delta written by `Snapshot::encode_delta` was
+    /// made against, so the receiver can look up which `Snapshot` to decode it with.
+    pub fn delta_baseline(bytes: &[u8]) -> Result<u64, DecodeError> {
+        BitReader::new(bytes).read_varint()
+    }
+
+    /// Rebuilds a `Snapshot` from the baseline it was delta encoded against and the
+    /// delta written by `Snapshot::encode_delta`. Returns `DecodeError::InvalidValue` if
+    /// the delta wasn't made against `baseline`.
+    pub fn decode_delta(
+        baseline: &Snapshot,
+        registry: &ComponentRegistry,
+        reader: &mut BitReader,
+    ) -> Result<Snapshot, DecodeError> {
+        if reader.read_varint()? != baseline.tick() {
+            return Err(DecodeError::InvalidValue);
+        }
+        let mut snapshot = Snapshot::new(reader.read_varint()?);
+        snapshot.entities = baseline.entities.clone();
+
+        for _ in 0..reader.read_len()? {
+            let eid = Eid::decode(reader)?;
+            if snapshot.entities.remove(&eid).is_none() {
+                return Err(DecodeError::InvalidValue);
+            }
+        }
+        for _ in 0..reader.read_len()? {
+            let eid = Eid::decode(reader)?;
+            let entity = SnapshotEntity::decode(registry, reader)?;
+            if snapshot.entities.insert(eid, entity).is_some() {
+                return Err(DecodeError::InvalidValue);
+            }
+        }
+        let mut changed = BTreeSet::new();
+        for _ in 0..reader.read_len()? {
+            let eid = Eid::decode(reader)?;
+            if !changed.insert(eid) {
+                return Err(DecodeError::InvalidValue);
+            }
+            let entity = snapshot
+                .entities
+                .get_mut(&eid)
+                .ok_or(DecodeError::InvalidValue)?;
+            decode_entity_delta(entity, registry, reader)?;
+        }
+        Ok(snapshot)
+    }
+
+    /// Delta encodes the `Snapshot` against a baseline into a new buffer.
+    pub fn to_delta_bytes(
+        &self,
+        baseline: &Snapshot,
+        registry: &ComponentRegistry,
+    ) -> Result<Vec<u8>, EncodeError> {
+        let mut writer = BitWriter::new();
+        self.encode_delta(baseline, registry, &mut writer)?;
+        Ok(writer.into_bytes())
+    }
+
+    /// Rebuilds a `Snapshot` from a baseline and a buffer written by
+    /// `Snapshot::to_delta_bytes`.
+    pub fn from_delta_bytes(
+        baseline: &Snapshot,
+        registry: &ComponentRegistry,
+        bytes: &[u8],
+    ) -> Result<Snapshot, DecodeError> {
+        Snapshot::decode_delta(baseline, registry, &mut BitReader::new(bytes))
+    }
+}
+
+#[cfg(test)]
+mod test_delta {
+
+    use crate::{
+        BitReader, BitWriter, Component, ComponentRegistry, DecodeError, Encode, Snapshot, World,
+    };
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Health(u8);
+
+    impl Component for Pos {}
+    impl Component for Health {}
+
+    impl Encode for Pos {
+        fn encode(&self, writer: &mut BitWriter) {
+            (self.x, self.y).encode(writer);
+        }
+        fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+            let (x, y) = Encode::decode(reader)?;
+            Ok(Pos { x, y })
+        }
+    }
+
+    impl Encode for Health {
+        fn encode(&self, writer: &mut BitWriter) {
+            self.0.encode(writer);
+        }
+        fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+            Ok(Health(u8::decode(reader)?))
+        }
+    }
+
+    fn world() -> World {
+        let mut world = World::default();
+        world
+            .registry_mut()
+            .register::<Pos>()
+            .encoded()
+            .build()
+            .unwrap();
+        world
+            .registry_mut()
+            .register::<Health>()
+            .encoded()
+            .build()
+            .unwrap();
+        world
+    }
+
+    /// Delta encodes `target` against `baseline`, decodes it and checks that the result
+    /// is identical to `target`. Returns the size of the delta.
+    fn assert_round_trip(
+        baseline: &Snapshot,
+        target: &Snapshot,
+        registry: &ComponentRegistry,
+    ) -> usize {
+        let delta = target.to_delta_bytes(baseline, registry).unwrap();
+        let rebuilt = Snapshot::from_delta_bytes(baseline, registry, &delta).unwrap();
+        assert_eq!(rebuilt.tick(), target.tick());
+        assert_eq!(
+            rebuilt.to_bytes(registry).unwrap(),
+            target.to_bytes(registry).unwrap()
+        );
+        delta.len()
+    }
+
+    #[test]
+    fn test_unchanged_entities_are_skipped() {
+        let mut world = world();
+        for i in 0..50 {
+            world
+                .create_entity()
+                .with(Pos {
+                    x: i as f32,
+                    y: 0.0,
+                })
+                .with(Health(100))
+                .build();
+        }
+        let baseline = world.snapshot(1);
+        let target = world.snapshot(2);
+
+        // Header, tick & three empty counts.
+        assert_eq!(assert_round_trip(&baseline, &target, world.registry()), 5);
+    }
+
+    #[test]
+    fn test_spawns() {
+        let mut world = world();
+        world.create_entity().with(Pos { x: 1.0, y: 1.0 }).build();
+        let baseline = world.snapshot(1);
+
+        let e = world
+            .create_entity()
+            .with(Pos { x: 2.0, y: 2.0 })
+            .with(Health(3))
+            .build();
+        world.create_entity().build();
+        let target = world.snapshot(2);
+
+        assert_round_trip(&baseline, &target, world.registry());
+        let delta = target.to_delta_bytes(&baseline, world.registry()).unwrap();
+        let rebuilt = Snapshot::from_delta_bytes(&baseline, world.registry(), &delta).unwrap();
+        assert_eq!(rebuilt.get_component::<Health>(e), Some(&Health(3)));
+    }
+
+    #[test]
+    fn test_despawns() {
+        let mut world = world();
+        let e1 = world.create_entity().with(Pos { x: 1.0, y: 1.0 }).build();
+        let e2 = world.create_entity().with(Health(1)).build();
+        let baseline = world.snapshot(1);
+
+        world.destroy_entity(&e1).unwrap();
+        let target = world.snapshot(2);
+
+        assert_round_trip(&baseline, &target, world.registry());
+        let delta = target.to_delta_bytes(&baseline, world.registry()).unwrap();
+        let rebuilt = Snapshot::from_delta_bytes(&baseline, world.registry(), &delta).unwrap();
+        assert!(!rebuilt.contains(e1));
+        assert!(rebuilt.contains(e2));
+    }
+
+    #[test]
+    fn test_component_changes() {
+        let mut world = world();
+        let e1 = world.create_entity().with(Pos { x: 1.0, y: 1.0 }).build();
+        let e2 = world
+            .create_entity()
+            .with(Pos { x: 1.0, y: 1.0 })
+            .with(Health(10))
+            .build();
+        let e3 = world.create_entity().with(Health(10)).build();
+        let baseline = world.snapshot(1);
+
+        world.add_component_to_entity(&e1, Health(5)).unwrap();
+        world.remove_component_from_entity::<Health>(&e2).unwrap();
+        world.add_component_to_entity(&e3, Health(9)).unwrap();
+        let target = world.snapshot(2);
+
+        assert_round_trip(&baseline, &target, world.registry());
+        let delta = target.to_delta_bytes(&baseline, world.registry()).unwrap();
+        let rebuilt = Snapshot::from_delta_bytes(&baseline, world.registry(), &delta).unwrap();
+        assert_eq!(rebuilt.get_component::<Health>(e1), Some(&Health(5)));
+        assert_eq!(
+            rebuilt.get_component::<Pos>(e1),
+            Some(&Pos { x: 1.0, y: 1.0 })
+        );
+        assert!(rebuilt.get_component::<Health>(e2).is_none());
+        assert_eq!(rebuilt.get_component::<Health>(e3), Some(&Health(9)));
+    }
+
+    #[test]
+    fn test_wrong_baseline() {
+        let mut world = world();
+        let e = world.create_entity().with(Health(1)).build();
+        let baseline = world.snapshot(1);
+        world.destroy_entity(&e).unwrap();
+        let delta = world
+            .snapshot(2)
+            .to_delta_bytes(&baseline, world.registry())
+            .unwrap();
+
+        let other = Snapshot::new(0);
+        assert_eq!(
+            Snapshot::from_delta_bytes(&other, world.registry(), &delta).unwrap_err(),
+            DecodeError::InvalidValue
+        );
+        for len in 0..delta.len() {
+            assert_eq!(
+                Snapshot::from_delta_bytes(&baseline, world.registry(), &delta[..len]).unwrap_err(),
+                DecodeError::UnexpectedEnd
+            );
+        }
+    }
+
+    #[test]
+    fn test_duplicates_are_rejected() {
+        let mut world = world();
+        let e = world.create_entity().with(Health(1)).build();
+        let baseline = world.snapshot(1);
+        let health = world.registry().id_of::<Health>().unwrap();
+
+        // Writes a delta changing the entity once per entry of `changes`, each time to
+        // the healths given.
+        let delta = |changes: &[&[u8]]| {
+            let mut writer = BitWriter::new();
+            writer.write_varint(1);
+            writer.write_varint(2);
+            writer.write_varint(0);
+            writer.write_varint(0);
+            writer.write_varint(changes.len() as u64);
+            for healths in changes {
+                e.encode(&mut writer);
+                writer.write_varint(healths.len() as u64);
+                for value in healths.iter() {
+                    writer.write_varint(u64::from(health.0));
+                    Health(*value).encode(&mut writer);
+                }
+                writer.write_varint(0);
+            }
+            writer.into_bytes()
+        };
+
+        let rebuilt = Snapshot::from_delta_bytes(&baseline, world.registry(), &delta(&[&[2]]));
+        assert_eq!(
+            rebuilt.unwrap().get_component::<Health>(e),
+            Some(&Health(2))
+        );
+
+        // The same component changed twice, and the same entity changed twice.
+        let twice = Snapshot::from_delta_bytes(&baseline, world.registry(), &delta(&[&[2, 3]]));
+        assert_eq!(twice.unwrap_err(), DecodeError::InvalidValue);
+        let twice = Snapshot::from_delta_bytes(&baseline, world.registry(), &delta(&[&[2], &[3]]));
+        assert_eq!(twice.unwrap_err(), DecodeError::InvalidValue);
+    }
+}
@@ -0,0 +1,385 @@
+use crate::Eid;
+use std::any::Any;
+use std::fmt::Debug;
+use std::time::Duration;
+
+/// Trait for observing the systems a `World` runs, set with `World::set_diagnostics`.
+/// Every method does nothing by default. Systems are known by their label when they run
+/// from a `Schedule` or with `World::dispatch_labeled_system`, and by their type name
+/// otherwise.
+///
+/// `before_entity` and `after_entity` are only called for systems which run on one
+/// thread, with `World::dispatch_system` or alone in a batch of
+/// `Schedule::run_parallel`. A `ParSystem`, and systems sharing a batch, run on several
+/// threads at once and only report to `system_ran`.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Component, Diagnostics, Eid, System, World};
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Pos(f64);
+/// impl Component for Pos {}
+///
+/// #[derive(Debug, Default)]
+/// struct Trace(Vec<String>);
+/// impl Diagnostics for Trace {
+///     fn observes_entities(&self) -> bool {
+///         true
+///     }
+///     fn before_entity(&mut self, system: &'static str, eid: Eid) {
+///         self.0.push(format!("{} {}", system, eid.index()));
+///     }
+/// }
+///
+/// struct Move;
+/// impl System for Move {
+///     type Data = Pos;
+///     fn run(&mut self, pos: &mut Pos) {
+///         pos.0 += 1.0;
+///     }
+/// }
+///
+/// let mut world = World::default();
+/// world.set_diagnostics(Trace::default());
+/// world.create_entity().with(Pos(0.0)).build();
+/// world.dispatch_system(&mut Move);
+///
+/// let trace = world.diagnostics::<Trace>().unwrap();
+/// assert_eq!(trace.0, vec![format!("{} 0", std::any::type_name::<Move>())]);
+/// ```
+pub trait Diagnostics: Any + Debug + Send + Sync {
+    /// Called once a `System` ran with its label, the number of entities it ran on and
+    /// how long it took.
+    fn system_ran(&mut self, _system: &'static str, _matched: usize, _elapsed: Duration) {}
+
+    /// Returns true if `before_entity` and `after_entity` should be called.
+    fn observes_entities(&self) -> bool {
+        false
+    }
+
+    /// Called before a `System` runs on an `Entity`.
+    fn before_entity(&mut self, _system: &'static str, _eid: Eid) {}
+
+    /// Called after a `System` ran on an `Entity`.
+    fn after_entity(&mut self, _system: &'static str, _eid: Eid) {}
+
+    /// Called by `World::end_tick` once every system of a tick ran.
+    fn tick_ended(&mut self) {}
+}
+
+/// `Diagnostics` which ignore everything. Used by a `World` until others are set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoDiagnostics;
+
+impl Diagnostics for NoDiagnostics {}
+
+impl Default for Box<dyn Diagnostics> {
+    fn default() -> Self {
+        Box::new(NoDiagnostics)
+    }
+}
+
+/// How often a `System` ran during a tick, on how many entities and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SystemStats {
+    system: &'static str,
+    runs: u32,
+    matched: usize,
+    elapsed: Duration,
+}
+
+impl SystemStats {
+    /// Returns the label of the `System`, or its type name if it ran without one.
+    pub fn system(&self) -> &'static str {
+        self.system
+    }
+
+    /// Returns how many times the `System` ran.
+    pub fn runs(&self) -> u32 {
+        self.runs
+    }
+
+    /// Returns the number of entities the `System` ran on, summed over its runs.
+    pub fn matched(&self) -> usize {
+        self.matched
+    }
+
+    /// Returns how long the `System` took, summed over its runs.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+/// `Diagnostics` which add up the `SystemStats` of every `System` over a tick, by label.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Component, StatsCollector, System, World};
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Pos(f64);
+/// impl Component for Pos {}
+///
+/// struct Move;
+/// impl System for Move {
+///     type Data = Pos;
+///     fn run(&mut self, pos: &mut Pos) {
+///         pos.0 += 1.0;
+///     }
+/// }
+///
+/// let mut world = World::default();
+/// world.set_diagnostics(StatsCollector::default());
+/// world.create_entity().with(Pos(0.0)).build();
+/// world.create_entity().with(Pos(0.0)).build();
+///
+/// world.dispatch_system(&mut Move);
+/// world.dispatch_system(&mut Move);
+/// world.end_tick();
+///
+/// let stats = world.diagnostics::<StatsCollector>().unwrap();
+/// assert_eq!(stats.ticks(), 1);
+/// assert_eq!(stats.last_tick()[0].runs(), 2);
+/// assert_eq!(stats.last_tick()[0].matched(), 4);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StatsCollector {
+    current: Vec<SystemStats>,
+    last: Vec<SystemStats>,
+    ticks: u64,
+}
+
+impl StatsCollector {
+    /// Returns the stats of the tick in progress, in the order the systems first ran.
+    pub fn current_tick(&self) -> &[SystemStats] {
+        &self.current
+    }
+
+    /// Returns the stats of the last tick which ended, in the order the systems first
+    /// ran.
+    pub fn last_tick(&self) -> &[SystemStats] {
+        &self.last
+    }
+
+    /// Returns the number of ticks which ended.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+}
+
+impl Diagnostics for StatsCollector {
+    fn system_ran(&mut self, system: &'static str, matched: usize, elapsed: Duration) {
+        match self.current.iter_mut().find(|stats| stats.system == system) {
+            Some(stats) => {
+                stats.runs += 1;
+                stats.matched += matched;
+                stats.elapsed += elapsed;
+            }
+            None => self.current.push(SystemStats {
+                system,
+                runs: 1,
+                matched,
+                elapsed,
+            }),
+        }
+    }
+
+    fn tick_ended(&mut self) {
+        self.last = std::mem::take(&mut self.current);
+        self.ticks += 1;
+    }
+}
+
+#[cfg(test)]
+mod test_diagnostics {
+
+    use crate::{
+        Component, Diagnostics, Eid, NoDiagnostics, ParSystem, Schedule, StatsCollector, System,
+        With, World,
+    };
+    use std::any::{type_name, Any};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos(f64);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Vel(f64);
+
+    impl Component for Pos {}
+    impl Component for Vel {}
+
+    struct Move;
+    impl System for Move {
+        type Data = (Pos, Vel);
+        fn run(&mut self, (pos, vel): (&mut Pos, &mut Vel)) {
+            pos.0 += vel.0;
+        }
+    }
+
+    struct Count;
+    impl System for Count {
+        type Data = (Eid, With<Pos>);
+        fn run(&mut self, _: (Eid, ())) {}
+    }
+
+    fn world() -> World {
+        let mut world = World::default();
+        world.create_entity().with(Pos(0.0)).with(Vel(1.0)).build();
+        world.create_entity().with(Pos(0.0)).build();
+        world.create_entity().build();
+        world
+    }
+
+    #[test]
+    fn test_collector() {
+        let mut world = world();
+        world.set_diagnostics(StatsCollector::default());
+        world.dispatch_system(&mut Move);
+        world.dispatch_system(&mut Count);
+        world.dispatch_system(&mut Move);
+
+        let stats = world.diagnostics::<StatsCollector>().unwrap();
+        assert_eq!(stats.ticks(), 0);
+        assert!(stats.last_tick().is_empty());
+        let current: Vec<_> = stats
+            .current_tick()
+            .iter()
+            .map(|s| (s.system(), s.runs(), s.matched()))
+            .collect();
+        assert_eq!(
+            current,
+            vec![(type_name::<Move>(), 2, 2), (type_name::<Count>(), 1, 2)]
+        );
+
+        world.end_tick();
+        world.dispatch_system(&mut Count);
+        world.end_tick();
+        let stats = world.diagnostics::<StatsCollector>().unwrap();
+        assert_eq!(stats.ticks(), 2);
+        assert!(stats.current_tick().is_empty());
+        assert_eq!(stats.last_tick().len(), 1);
+        assert_eq!(stats.last_tick()[0].system(), type_name::<Count>());
+    }
+
+    #[test]
+    fn test_entity_hooks() {
+        #[derive(Debug, Default)]
+        struct Hooks {
+            observe: bool,
+            calls: Vec<(&'static str, u32)>,
+        }
+        impl Diagnostics for Hooks {
+            fn observes_entities(&self) -> bool {
+                self.observe
+            }
+            fn before_entity(&mut self, _system: &'static str, eid: Eid) {
+                self.calls.push(("before", eid.index()));
+            }
+            fn after_entity(&mut self, _system: &'static str, eid: Eid) {
+                self.calls.push(("after", eid.index()));
+            }
+        }
+
+        let mut world = world();
+        world.set_diagnostics(Hooks::default());
+        world.dispatch_system(&mut Count);
+        assert!(world.diagnostics::<Hooks>().unwrap().calls.is_empty());
+
+        world.diagnostics_mut::<Hooks>().unwrap().observe = true;
+        world.dispatch_system(&mut Count);
+        assert_eq!(
+            world.diagnostics::<Hooks>().unwrap().calls,
+            vec![("before", 0), ("after", 0), ("before", 1), ("after", 1)]
+        );
+    }
+
+    #[test]
+    fn test_parallel() {
+        struct ParMove;
+        impl ParSystem for ParMove {
+            type Data = (Pos, Vel);
+            fn run(&self, (pos, vel): (&mut Pos, &mut Vel)) {
+                pos.0 += vel.0;
+            }
+        }
+
+        let mut world = world();
+        for _ in 0..10 {
+            world.create_entity().with(Pos(0.0)).with(Vel(1.0)).build();
+        }
+        world.set_diagnostics(StatsCollector::default());
+        world.par_dispatch_system_in_chunks(&ParMove, 4);
+
+        let mut schedule = Schedule::default();
+        schedule.add_system("move", Move).build().unwrap();
+        schedule.add_system("count", Count).build().unwrap();
+        schedule.run_parallel(&mut world).unwrap();
+        world.end_tick();
+
+        let stats = world.diagnostics::<StatsCollector>().unwrap();
+        let matched: Vec<_> = stats
+            .last_tick()
+            .iter()
+            .map(|s| (s.system(), s.matched()))
+            .collect();
+        assert_eq!(
+            matched,
+            vec![(type_name::<ParMove>(), 11), ("move", 11), ("count", 12),]
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        #[derive(Debug, Default)]
+        struct Hooks(Vec<&'static str>);
+        impl Diagnostics for Hooks {
+            fn observes_entities(&self) -> bool {
+                true
+            }
+            fn before_entity(&mut self, system: &'static str, _eid: Eid) {
+                self.0.push(system);
+            }
+        }
+
+        let mut world = world();
+        world.set_diagnostics(Hooks::default());
+        let mut schedule = Schedule::default();
+        schedule.add_system("first", Count).build().unwrap();
+        schedule.add_system("second", Count).build().unwrap();
+        schedule.run(&mut world).unwrap();
+        assert_eq!(
+            world.diagnostics::<Hooks>().unwrap().0,
+            vec!["first", "first", "second", "second"]
+        );
+
+        // Both systems share a batch, so they run on their own threads.
+        world.diagnostics_mut::<Hooks>().unwrap().0.clear();
+        schedule.run_parallel(&mut world).unwrap();
+        assert!(world.diagnostics::<Hooks>().unwrap().0.is_empty());
+
+        world.set_diagnostics(StatsCollector::default());
+        schedule.run_parallel(&mut world).unwrap();
+        world.dispatch_labeled_system("first", &mut Count);
+        world.end_tick();
+        let stats = world.diagnostics::<StatsCollector>().unwrap();
+        let runs: Vec<_> = stats
+            .last_tick()
+            .iter()
+            .map(|s| (s.system(), s.runs()))
+            .collect();
+        assert_eq!(runs, vec![("first", 2), ("second", 1)]);
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut world = world();
+        assert!(world.diagnostics::<NoDiagnostics>().is_some());
+        let old: Box<dyn Any> = world.set_diagnostics(StatsCollector::default());
+        assert!(old.is::<NoDiagnostics>());
+        assert!(world.diagnostics::<NoDiagnostics>().is_none());
+        assert!(world.diagnostics_mut::<StatsCollector>().is_some());
+    }
+}
@@ -0,0 +1,654 @@
+use crate::ComponentId;
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+
+/// Number of elements a `Vec` whose elements take no bits can be decoded with. The length
+/// of any other `Vec` is bounded by the bits its elements take.
+const MAX_EMPTY_ELEMENTS: usize = 1 << 16;
+
+/// Error returned when decoding data which is truncated or malformed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input ended before the value was fully read.
+    UnexpectedEnd,
+    /// The input contained a bit pattern which isn't a valid value for the type being
+    /// decoded.
+    InvalidValue,
+    /// The input referenced a component id which isn't registered with an encoding.
+    UnknownComponent,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::InvalidValue => write!(f, "invalid value in input"),
+            DecodeError::UnknownComponent => write!(f, "unknown component id in input"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Error returned when a value can't be encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+    /// A component isn't registered in the `ComponentRegistry` used for encoding.
+    UnregisteredComponent,
+    /// A component was registered without an encoding.
+    NotEncodable(ComponentId),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodeError::UnregisteredComponent => write!(f, "component isn't registered"),
+            EncodeError::NotEncodable(id) => {
+                write!(f, "component {} was registered without an encoding", id.0)
+            }
+        }
+    }
+}
+
+impl Error for EncodeError {}
+
+/// Writes values into a buffer one bit at a time. Bits are packed starting from the
+/// least significant bit of each byte.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{BitReader, BitWriter};
+///
+/// let mut writer = BitWriter::new();
+/// writer.write_bool(true);
+/// writer.write_bits(5, 3);
+/// let bytes = writer.into_bytes();
+/// assert_eq!(bytes.len(), 1);
+///
+/// let mut reader = BitReader::new(&bytes);
+/// assert_eq!(reader.read_bool(), Ok(true));
+/// assert_eq!(reader.read_bits(3), Ok(5));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    bit_len: usize,
+}
+
+impl BitWriter {
+    /// Creates an empty `BitWriter`.
+    pub fn new() -> Self {
+        BitWriter::default()
+    }
+
+    /// Returns the number of bits written so far.
+    pub fn bit_len(&self) -> usize {
+        self.bit_len
+    }
+
+    /// Writes the lowest `bits` bits of `value`. `bits` must be at most 64.
+    pub fn write_bits(&mut self, mut value: u64, bits: u32) {
+        assert!(bits <= 64, "can't write more than 64 bits at once");
+        let mut remaining = bits;
+        while remaining > 0 {
+            let offset = (self.bit_len % 8) as u32;
+            if offset == 0 {
+                self.bytes.push(0);
+            }
+            let n = remaining.min(8 - offset);
+            let mask = (1u16 << n) - 1;
+            let last = self.bytes.len() - 1;
+            self.bytes[last] |= (((value as u16) & mask) << offset) as u8;
+            value = value.checked_shr(n).unwrap_or(0);
+            remaining -= n;
+            self.bit_len += n as usize;
+        }
+    }
+
+    /// Writes a single bit.
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_bits(value as u64, 1);
+    }
+
+    /// Writes an unsigned integer using as few bytes as possible. Smaller values take
+    /// fewer bits, which makes this well suited for lengths and counts.
+    pub fn write_varint(&mut self, mut value: u64) {
+        loop {
+            let byte = value & 0x7f;
+            value >>= 7;
+            if value == 0 {
+                self.write_bits(byte, 8);
+                return;
+            }
+            self.write_bits(byte | 0x80, 8);
+        }
+    }
+
+    /// Writes every byte of a slice.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_bits(u64::from(*byte), 8);
+        }
+    }
+
+    /// Finishes writing and returns the buffer. Unused bits of the last byte are zero.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads values written by a `BitWriter` from a byte slice. Reading past the end of the
+/// slice returns `DecodeError::UnexpectedEnd` instead of panicking.
+#[derive(Debug, Clone, Copy)]
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit_pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    /// Creates a `BitReader` which starts reading at the beginning of `bytes`.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, bit_pos: 0 }
+    }
+
+    /// Returns the number of bits which haven't been read yet.
+    pub fn remaining_bits(&self) -> usize {
+        self.bytes.len() * 8 - self.bit_pos
+    }
+
+    /// Reads `bits` bits into the lowest bits of a `u64`. `bits` must be at most 64.
+    pub fn read_bits(&mut self, bits: u32) -> Result<u64, DecodeError> {
+        assert!(bits <= 64, "can't read more than 64 bits at once");
+        if bits as usize > self.remaining_bits() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let mut value = 0u64;
+        let mut read = 0;
+        while read < bits {
+            let offset = (self.bit_pos % 8) as u32;
+            let n = (bits - read).min(8 - offset);
+            let mask = (1u16 << n) - 1;
+            let byte = (u16::from(self.bytes[self.bit_pos / 8]) >> offset) & mask;
+            value |= u64::from(byte) << read;
+            read += n;
+            self.bit_pos += n as usize;
+        }
+        Ok(value)
+    }
+
+    /// Reads a single bit.
+    pub fn read_bool(&mut self) -> Result<bool, DecodeError> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    /// Reads an unsigned integer written by `BitWriter::write_varint`.
+    pub fn read_varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.read_bits(8)?;
+            if shift == 63 && byte > 1 {
+                return Err(DecodeError::InvalidValue);
+            }
+            value |= (byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+            if shift > 63 {
+                return Err(DecodeError::InvalidValue);
+            }
+        }
+    }
+
+    /// Reads a length written with `BitWriter::write_varint`, checking that it fits in a
+    /// `usize`.
+    pub fn read_len(&mut self) -> Result<usize, DecodeError> {
+        let len = self.read_varint()?;
+        if len > usize::MAX as u64 {
+            return Err(DecodeError::InvalidValue);
+        }
+        Ok(len as usize)
+    }
+
+    /// Reads `len` bytes.
+    pub fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>, DecodeError> {
+        if len.saturating_mul(8) > self.remaining_bits() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let mut bytes = Vec::with_capacity(len);
+        for _ in 0..len {
+            bytes.push(self.read_bits(8)? as u8);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Trait for values which can be written to and read back from a packet. Components
+/// which are sent over the network implement `Encode`.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{BitReader, BitWriter, Component, DecodeError, Encode};
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Pos {
+///     x: f64,
+///     y: f64,
+/// }
+/// impl Component for Pos {}
+///
+/// impl Encode for Pos {
+///     fn encode(&self, writer: &mut BitWriter) {
+///         self.x.encode(writer);
+///         self.y.encode(writer);
+///     }
+///     fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+///         Ok(Pos {
+///             x: f64::decode(reader)?,
+///             y: f64::decode(reader)?,
+///         })
+///     }
+/// }
+///
+/// let pos = Pos { x: 1.5, y: -2.0 };
+/// let bytes = pos.to_bytes();
+/// assert_eq!(Pos::from_bytes(&bytes), Ok(pos));
+/// assert!(Pos::from_bytes(&bytes[..4]).is_err());
+/// ```
+pub trait Encode: Sized {
+    /// Writes the value.
+    fn encode(&self, writer: &mut BitWriter);
+    /// Reads a value. Returns an error if the input is truncated or malformed.
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError>;
+
+    /// Encodes the value into a new buffer.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        self.encode(&mut writer);
+        writer.into_bytes()
+    }
+
+    /// Decodes a value from the start of a buffer.
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode(&mut BitReader::new(bytes))
+    }
+}
+
+macro_rules! impl_encode_unsigned {
+    ($($t:ty),*) => {
+        $(
+            impl Encode for $t {
+                fn encode(&self, writer: &mut BitWriter) {
+                    writer.write_bits(u64::from(*self), <$t>::BITS);
+                }
+                fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+                    Ok(reader.read_bits(<$t>::BITS)? as $t)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_encode_signed {
+    ($($t:ty => $u:ty),*) => {
+        $(
+            impl Encode for $t {
+                fn encode(&self, writer: &mut BitWriter) {
+                    (*self as $u).encode(writer);
+                }
+                fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+                    Ok(<$u>::decode(reader)? as $t)
+                }
+            }
+        )*
+    };
+}
+
+impl_encode_unsigned!(u8, u16, u32);
+impl_encode_signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64);
+
+impl Encode for u64 {
+    fn encode(&self, writer: &mut BitWriter) {
+        writer.write_bits(*self, 64);
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        reader.read_bits(64)
+    }
+}
+
+impl Encode for u128 {
+    fn encode(&self, writer: &mut BitWriter) {
+        (*self as u64).encode(writer);
+        ((*self >> 64) as u64).encode(writer);
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        let low = u64::decode(reader)?;
+        let high = u64::decode(reader)?;
+        Ok(u128::from(low) | (u128::from(high) << 64))
+    }
+}
+
+impl Encode for i128 {
+    fn encode(&self, writer: &mut BitWriter) {
+        (*self as u128).encode(writer);
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        Ok(u128::decode(reader)? as i128)
+    }
+}
+
+impl Encode for usize {
+    fn encode(&self, writer: &mut BitWriter) {
+        (*self as u64).encode(writer);
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        let value = u64::decode(reader)?;
+        if value > usize::MAX as u64 {
+            return Err(DecodeError::InvalidValue);
+        }
+        Ok(value as usize)
+    }
+}
+
+impl Encode for isize {
+    fn encode(&self, writer: &mut BitWriter) {
+        (*self as i64).encode(writer);
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        let value = i64::decode(reader)?;
+        if value < isize::MIN as i64 || value > isize::MAX as i64 {
+            return Err(DecodeError::InvalidValue);
+        }
+        Ok(value as isize)
+    }
+}
+
+impl Encode for f32 {
+    fn encode(&self, writer: &mut BitWriter) {
+        self.to_bits().encode(writer);
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        Ok(f32::from_bits(u32::decode(reader)?))
+    }
+}
+
+impl Encode for f64 {
+    fn encode(&self, writer: &mut BitWriter) {
+        self.to_bits().encode(writer);
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        Ok(f64::from_bits(u64::decode(reader)?))
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, writer: &mut BitWriter) {
+        writer.write_bool(*self);
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        reader.read_bool()
+    }
+}
+
+impl Encode for char {
+    fn encode(&self, writer: &mut BitWriter) {
+        u32::from(*self).encode(writer);
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        std::char::from_u32(u32::decode(reader)?).ok_or(DecodeError::InvalidValue)
+    }
+}
+
+impl Encode for () {
+    fn encode(&self, _writer: &mut BitWriter) {}
+    fn decode(_reader: &mut BitReader) -> Result<Self, DecodeError> {
+        Ok(())
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, writer: &mut BitWriter) {
+        writer.write_bool(self.is_some());
+        if let Some(value) = self {
+            value.encode(writer);
+        }
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        if reader.read_bool()? {
+            Ok(Some(T::decode(reader)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encode(&self, writer: &mut BitWriter) {
+        for value in self.iter() {
+            value.encode(writer);
+        }
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        let mut values = Vec::with_capacity(N.min(reader.remaining_bits()));
+        for _ in 0..N {
+            values.push(T::decode(reader)?);
+        }
+        match values.try_into() {
+            Ok(array) => Ok(array),
+            Err(_) => unreachable!(),
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, writer: &mut BitWriter) {
+        writer.write_varint(self.len() as u64);
+        for value in self.iter() {
+            value.encode(writer);
+        }
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        let len = reader.read_len()?;
+        let mut values = Vec::with_capacity(len.min(reader.remaining_bits()));
+        for _ in 0..len {
+            let remaining = reader.remaining_bits();
+            values.push(T::decode(reader)?);
+            if reader.remaining_bits() == remaining && len > MAX_EMPTY_ELEMENTS {
+                return Err(DecodeError::InvalidValue);
+            }
+        }
+        Ok(values)
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, writer: &mut BitWriter) {
+        writer.write_varint(self.len() as u64);
+        writer.write_bytes(self.as_bytes());
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        let len = reader.read_len()?;
+        String::from_utf8(reader.read_bytes(len)?).map_err(|_| DecodeError::InvalidValue)
+    }
+}
+
+macro_rules! impl_encode_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: Encode),+> Encode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode(&self, writer: &mut BitWriter) {
+                let ($($name,)+) = self;
+                $($name.encode(writer);)+
+            }
+            fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+                Ok(($($name::decode(reader)?,)+))
+            }
+        }
+    };
+}
+
+impl_encode_tuple!(A);
+impl_encode_tuple!(A, B);
+impl_encode_tuple!(A, B, C);
+impl_encode_tuple!(A, B, C, D);
+impl_encode_tuple!(A, B, C, D, E);
+impl_encode_tuple!(A, B, C, D, E, F);
+impl_encode_tuple!(A, B, C, D, E, F, G);
+impl_encode_tuple!(A, B, C, D, E, F, G, H);
+impl_encode_tuple!(A, B, C, D, E, F, G, H, I);
+impl_encode_tuple!(A, B, C, D, E, F, G, H, I, J);
+impl_encode_tuple!(A, B, C, D, E, F, G, H, I, J, K);
+impl_encode_tuple!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+#[cfg(test)]
+mod test_encode {
+
+    use crate::{BitReader, BitWriter, DecodeError, Encode};
+    use std::fmt::Debug;
+
+    fn round_trip<T: Encode + PartialEq + Debug>(value: T) {
+        let bytes = value.to_bytes();
+        assert_eq!(T::from_bytes(&bytes), Ok(value));
+    }
+
+    #[test]
+    fn test_bits_are_packed() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b101, 3);
+        writer.write_bits(0x3ff, 10);
+        writer.write_bool(false);
+        writer.write_bits(u64::MAX, 64);
+        assert_eq!(writer.bit_len(), 78);
+
+        let bytes = writer.into_bytes();
+        assert_eq!(bytes.len(), 10);
+
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(reader.read_bits(3), Ok(0b101));
+        assert_eq!(reader.read_bits(10), Ok(0x3ff));
+        assert_eq!(reader.read_bool(), Ok(false));
+        assert_eq!(reader.read_bits(64), Ok(u64::MAX));
+        assert_eq!(reader.remaining_bits(), 2);
+        assert_eq!(reader.read_bits(3), Err(DecodeError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX].iter() {
+            let mut writer = BitWriter::new();
+            writer.write_varint(*value);
+            let bytes = writer.into_bytes();
+            assert_eq!(BitReader::new(&bytes).read_varint(), Ok(*value));
+        }
+
+        let overlong = [0xff; 11];
+        assert_eq!(
+            BitReader::new(&overlong).read_varint(),
+            Err(DecodeError::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn test_primitives() {
+        round_trip(0xabu8);
+        round_trip(0xabcdu16);
+        round_trip(0xdead_beefu32);
+        round_trip(0xdead_beef_cafe_f00du64);
+        round_trip(u128::MAX - 7);
+        round_trip(-5i8);
+        round_trip(-300i16);
+        round_trip(i32::MIN);
+        round_trip(i64::MIN + 1);
+        round_trip(-1i128);
+        round_trip(usize::MAX);
+        round_trip(isize::MIN);
+        round_trip(1.25f32);
+        round_trip(-0.1f64);
+        round_trip(f64::INFINITY);
+        round_trip(true);
+        round_trip(false);
+        round_trip('ß');
+        round_trip(());
+    }
+
+    #[test]
+    fn test_compound() {
+        round_trip([1u16, 2, 3, 4]);
+        round_trip([[1.0f32, 2.0], [3.0, 4.0]]);
+        round_trip(Some(7u32));
+        round_trip(None::<u32>);
+        round_trip((1u8, -2i16, 3.0f64));
+        round_trip((
+            1u8, 2u8, 3u8, 4u8, 5u8, 6u8, 7u8, 8u8, 9u8, 10u8, 11u8, 12u8,
+        ));
+        round_trip(vec![Some(1i32), None, Some(-3)]);
+        round_trip(String::from("snapshot"));
+    }
+
+    #[test]
+    fn test_values_are_bit_packed() {
+        let bytes = (true, false, Some(true), None::<bool>).to_bytes();
+        assert_eq!(bytes.len(), 1);
+        assert_eq!(
+            <(bool, bool, Option<bool>, Option<bool>)>::from_bytes(&bytes),
+            Ok((true, false, Some(true), None))
+        );
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        let bytes = (1u32, 2.0f64).to_bytes();
+        for len in 0..bytes.len() {
+            assert_eq!(
+                <(u32, f64)>::from_bytes(&bytes[..len]),
+                Err(DecodeError::UnexpectedEnd)
+            );
+        }
+
+        let bytes = vec![1u64, 2, 3].to_bytes();
+        assert_eq!(
+            Vec::<u64>::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+
+        // A length prefix claiming far more data than there is mustn't allocate it.
+        let mut writer = BitWriter::new();
+        writer.write_varint(u64::from(u32::MAX));
+        let bytes = writer.into_bytes();
+        assert_eq!(
+            Vec::<u8>::from_bytes(&bytes),
+            Err(DecodeError::UnexpectedEnd)
+        );
+        assert_eq!(String::from_bytes(&bytes), Err(DecodeError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        let bytes = 0xd800u32.to_bytes();
+        assert_eq!(char::from_bytes(&bytes), Err(DecodeError::InvalidValue));
+
+        let mut writer = BitWriter::new();
+        writer.write_varint(2);
+        writer.write_bytes(&[0xc3, 0x28]);
+        let bytes = writer.into_bytes();
+        assert_eq!(String::from_bytes(&bytes), Err(DecodeError::InvalidValue));
+
+        // Elements which take no bits can't bound a huge length by the input left.
+        let mut writer = BitWriter::new();
+        writer.write_varint(1 << 40);
+        let bytes = writer.into_bytes();
+        assert_eq!(
+            Vec::<()>::from_bytes(&bytes),
+            Err(DecodeError::InvalidValue)
+        );
+        assert_eq!(
+            Vec::<[u8; 0]>::from_bytes(&bytes),
+            Err(DecodeError::InvalidValue)
+        );
+        round_trip(vec![(); 1000]);
+    }
+}
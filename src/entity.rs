@@ -1,15 +1,71 @@
-use crate::{Component, System, SystemData, World};
-use std::any::{Any, TypeId};
+use crate::component::AnyComponent;
+use crate::{BitReader, BitWriter, Component, DecodeError, Encode, World};
+use std::any::TypeId;
 use std::collections::HashMap;
 
-/// Type for entity identifier
-pub type Eid = usize;
+/// Generational entity identifier. The index of a destroyed entity is reused by entities
+/// created later, but with a higher generation, so a stale `Eid` never refers to the new
+/// entity.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::World;
+///
+/// let mut world = World::default();
+/// let e1 = world.create_entity().build();
+/// world.destroy_entity(&e1).unwrap();
+///
+/// let e2 = world.create_entity().build();
+/// assert_eq!(e1.index(), e2.index());
+/// assert!(e1.generation() < e2.generation());
+/// assert!(!world.is_alive(&e1));
+/// assert!(world.is_alive(&e2));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Eid {
+    index: u32,
+    generation: u32,
+}
+
+impl Eid {
+    /// Creates an `Eid` from its parts.
+    pub fn new(index: u32, generation: u32) -> Self {
+        Eid { index, generation }
+    }
+
+    /// Returns the index of the slot the entity occupies.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Returns how many times the slot was reused before this entity.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
 
-/// A collection for a series of components.
+impl Encode for Eid {
+    fn encode(&self, writer: &mut BitWriter) {
+        writer.write_varint(u64::from(self.index));
+        writer.write_varint(u64::from(self.generation));
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        let index = reader.read_varint()?;
+        let generation = reader.read_varint()?;
+        if index > u64::from(u32::MAX) || generation > u64::from(u32::MAX) {
+            return Err(DecodeError::InvalidValue);
+        }
+        Ok(Eid::new(index as u32, generation as u32))
+    }
+}
+
+/// A collection for a series of components which isn't part of a `World`, either because
+/// it is still being built or because it was destroyed. The components of the entities
+/// of a `World` are stored per component type instead.
 #[derive(Debug, Default)]
 pub struct Entity {
-    /// A Hashmap used to store the components of the entities.
-    pub components: HashMap<TypeId, Box<dyn Any>>,
+    pub(crate) components: HashMap<TypeId, Box<dyn AnyComponent>>,
 }
 
 impl Entity {
@@ -41,7 +97,7 @@ impl Entity {
             .components
             .insert(TypeId::of::<C>(), Box::new(component))
         {
-            if let Ok(comp) = bx.downcast::<C>() {
+            if let Ok(comp) = bx.into_any().downcast::<C>() {
                 Some(comp)
             } else {
                 panic!();
@@ -75,14 +131,14 @@ impl Entity {
     /// ```
     pub fn get_component<C: Component>(&self) -> Option<&C> {
         if let Some(bx) = self.components.get(&TypeId::of::<C>()) {
-            bx.downcast_ref::<C>()
+            bx.as_any().downcast_ref::<C>()
         } else {
             None
         }
     }
 
     /// Gets a component C for an `Entity`. If the `Entity` has a Component C, a
-    /// reference to the component is returned, otherwise None is returned.
+    /// mutable reference to the component is returned, otherwise None is returned.
     ///
     /// # Example
     /// ```
@@ -99,14 +155,13 @@ impl Entity {
     /// let mut e = Entity::default();
     /// e.add_component(Pos { x: 0.0, y: 0.0 });
     ///
-    /// let pos = e.get_component::<Pos>().unwrap();
-    /// assert_eq!(pos.x, 0.0);
-    /// assert_eq!(pos.y, 0.0);
+    /// e.get_mut_component::<Pos>().unwrap().x = 2.0;
+    /// assert_eq!(e.get_component::<Pos>().unwrap().x, 2.0);
     /// ```
     pub fn get_mut_component<C: Component>(&mut self) -> Option<&mut C> {
         self.components
-            .get_mut(&TypeId::of::<C>())
-            .unwrap()
+            .get_mut(&TypeId::of::<C>())?
+            .as_any_mut()
             .downcast_mut::<C>()
     }
 
@@ -133,7 +188,7 @@ impl Entity {
     /// ```
     pub fn remove_component<C: Component>(&mut self) -> Option<Box<C>> {
         if let Some(bx) = self.components.remove(&TypeId::of::<C>()) {
-            if let Ok(comp) = bx.downcast::<C>() {
+            if let Ok(comp) = bx.into_any().downcast::<C>() {
                 Some(comp)
             } else {
                 panic!();
@@ -142,12 +197,6 @@ impl Entity {
             None
         }
     }
-
-    /// Sets the `SystemData` of this `Entity`. Is called internally in
-    /// `World::dispatch_system`.
-    pub fn set<S: System>(&mut self, data: S::Data) {
-        data.set(self);
-    }
 }
 
 /// A helper struct to construct `Entities` with components.
@@ -233,3 +282,150 @@ impl<'a> EntityBuilder<'a> {
         self.world.insert_entity(self.entity)
     }
 }
+
+/// A slot which holds a living entity or is free to be reused.
+#[derive(Debug, Default)]
+struct Slot {
+    generation: u32,
+    alive: bool,
+}
+
+/// Keeps track of the entities of a `World` which are alive. Slots of destroyed entities
+/// are kept on a free list and reused with their generation bumped. The components of
+/// the entities are stored separately, by the index of their `Eid`.
+#[derive(Debug, Default)]
+pub(crate) struct Entities {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+}
+
+impl Entities {
+    /// Adds an entity, reusing a free slot if there is one.
+    pub(crate) fn insert(&mut self) -> Eid {
+        while let Some(index) = self.free.pop() {
+            // Slots `insert_at` made alive are left on the free list and skipped here.
+            let slot = &mut self.slots[index as usize];
+            if !slot.alive {
+                slot.alive = true;
+                return Eid::new(index, slot.generation);
+            }
+        }
+        let index = self.slots.len() as u32;
+        self.slots.push(Slot {
+            generation: 0,
+            alive: true,
+        });
+        Eid::new(index, 0)
+    }
+
+    /// Makes exactly the `Eid` given alive, replacing whatever occupied its slot. Returns
+    /// true if another entity was replaced. Used to mirror the entities of another
+    /// `World`.
+    pub(crate) fn insert_at(&mut self, eid: Eid) -> bool {
+        let index = eid.index() as usize;
+        while self.slots.len() <= index {
+            self.free.push(self.slots.len() as u32);
+            self.slots.push(Slot::default());
+        }
+        let slot = &mut self.slots[index];
+        let replaced = slot.alive && slot.generation != eid.generation();
+        slot.generation = eid.generation();
+        slot.alive = true;
+        replaced
+    }
+
+    pub(crate) fn contains(&self, eid: &Eid) -> bool {
+        match self.slots.get(eid.index() as usize) {
+            Some(slot) => slot.alive && slot.generation == eid.generation(),
+            None => false,
+        }
+    }
+
+    /// Returns the `Eid` of the living entity with the index.
+    pub(crate) fn eid(&self, index: u32) -> Option<Eid> {
+        match self.slots.get(index as usize) {
+            Some(slot) if slot.alive => Some(Eid::new(index, slot.generation)),
+            _ => None,
+        }
+    }
+
+    /// Removes an entity and frees its slot. Returns false if it wasn't alive.
+    pub(crate) fn remove(&mut self, eid: &Eid) -> bool {
+        if !self.contains(eid) {
+            return false;
+        }
+        let index = eid.index();
+        let slot = &mut self.slots[index as usize];
+        slot.alive = false;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(index);
+        true
+    }
+
+    /// Iterates over the living entities in order of their index.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = Eid> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.alive)
+            .map(|(i, slot)| Eid::new(i as u32, slot.generation))
+    }
+}
+
+#[cfg(test)]
+mod test_entity {
+
+    use crate::entity::Entities;
+    use crate::Eid;
+
+    #[test]
+    fn test_slots_are_reused_with_new_generation() {
+        let mut entities = Entities::default();
+        let e0 = entities.insert();
+        let e1 = entities.insert();
+        assert_eq!((e0, e1), (Eid::new(0, 0), Eid::new(1, 0)));
+
+        assert!(entities.remove(&e0));
+        assert!(!entities.remove(&e0));
+        assert!(!entities.contains(&e0));
+
+        let e2 = entities.insert();
+        assert_eq!(e2, Eid::new(0, 1));
+        assert!(!entities.contains(&e0));
+        assert!(entities.contains(&e2));
+        assert!(!entities.remove(&e0));
+        assert_eq!(entities.iter().collect::<Vec<_>>(), vec![e2, e1]);
+    }
+
+    #[test]
+    fn test_insert_at() {
+        let mut entities = Entities::default();
+        let remote = Eid::new(3, 7);
+        assert!(!entities.insert_at(remote));
+        assert!(entities.contains(&remote));
+        assert!(!entities.contains(&Eid::new(3, 6)));
+
+        // Slots skipped over are free to be used locally.
+        let mut local: Vec<_> = (0..4).map(|_| entities.insert()).collect();
+        local.sort();
+        assert_eq!(
+            local,
+            vec![
+                Eid::new(0, 0),
+                Eid::new(1, 0),
+                Eid::new(2, 0),
+                Eid::new(4, 0)
+            ]
+        );
+
+        assert!(entities.insert_at(Eid::new(3, 8)));
+        assert!(!entities.insert_at(Eid::new(3, 8)));
+        assert!(!entities.contains(&remote));
+
+        // A free slot taken by a remote entity isn't handed out again.
+        assert!(entities.remove(&local[1]));
+        assert!(!entities.insert_at(Eid::new(1, 5)));
+        assert_eq!(entities.insert(), Eid::new(5, 0));
+        assert!(entities.contains(&Eid::new(1, 5)));
+    }
+}
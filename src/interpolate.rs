@@ -0,0 +1,191 @@
+/// Trait for values which can be blended between two states. Components which implement
+/// `Interpolate` and are registered with `ComponentBuilder::interpolated` are blended
+/// when sampling a `SnapshotBuffer`. Components registered without it snap to their
+/// latest known value instead, which suits values like enums that can't be blended.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Component, Interpolate};
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Pos {
+///     x: f64,
+///     y: f64,
+/// }
+/// impl Component for Pos {}
+///
+/// impl Interpolate for Pos {
+///     fn interpolate(&self, other: &Self, t: f64) -> Self {
+///         Pos {
+///             x: self.x.interpolate(&other.x, t),
+///             y: self.y.interpolate(&other.y, t),
+///         }
+///     }
+/// }
+///
+/// let a = Pos { x: 0.0, y: 10.0 };
+/// let b = Pos { x: 4.0, y: 20.0 };
+/// assert_eq!(a.interpolate(&b, 0.25), Pos { x: 1.0, y: 12.5 });
+/// ```
+pub trait Interpolate: Sized {
+    /// Returns the value `t` of the way from `self` (0.0) to `other` (1.0).
+    fn interpolate(&self, other: &Self, t: f64) -> Self;
+}
+
+/// Snap strategy for values which can't be blended: keeps `from` until `to` is reached.
+pub fn snap<T: Clone>(from: &T, to: &T, t: f64) -> T {
+    if t < 1.0 {
+        from.clone()
+    } else {
+        to.clone()
+    }
+}
+
+impl Interpolate for f64 {
+    fn interpolate(&self, other: &Self, t: f64) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for f32 {
+    fn interpolate(&self, other: &Self, t: f64) -> Self {
+        f64::from(*self).interpolate(&f64::from(*other), t) as f32
+    }
+}
+
+macro_rules! impl_interpolate_int {
+    ($($t:ty),*) => {
+        $(
+            impl Interpolate for $t {
+                fn interpolate(&self, other: &Self, t: f64) -> Self {
+                    // Blends the difference in i128 so that 64 bit values far apart don't
+                    // lose precision, and returns the ends exactly.
+                    if t <= 0.0 {
+                        return *self;
+                    }
+                    if t >= 1.0 {
+                        return *other;
+                    }
+                    let from = *self as i128;
+                    let step = ((*other as i128 - from) as f64 * t).round() as i128;
+                    (from + step).clamp(<$t>::MIN as i128, <$t>::MAX as i128) as $t
+                }
+            }
+        )*
+    };
+}
+
+impl_interpolate_int!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl Interpolate for bool {
+    fn interpolate(&self, other: &Self, t: f64) -> Self {
+        snap(self, other, t)
+    }
+}
+
+impl Interpolate for char {
+    fn interpolate(&self, other: &Self, t: f64) -> Self {
+        snap(self, other, t)
+    }
+}
+
+impl Interpolate for () {
+    fn interpolate(&self, _other: &Self, _t: f64) -> Self {}
+}
+
+impl<T: Interpolate + Clone> Interpolate for Option<T> {
+    fn interpolate(&self, other: &Self, t: f64) -> Self {
+        match (self, other) {
+            (Some(a), Some(b)) => Some(a.interpolate(b, t)),
+            _ => snap(self, other, t),
+        }
+    }
+}
+
+impl<T: Interpolate + Clone, const N: usize> Interpolate for [T; N] {
+    fn interpolate(&self, other: &Self, t: f64) -> Self {
+        let mut out = self.clone();
+        for (out, other) in out.iter_mut().zip(other.iter()) {
+            *out = out.interpolate(other, t);
+        }
+        out
+    }
+}
+
+macro_rules! impl_interpolate_tuple {
+    ($($name:ident $index:tt),+) => {
+        impl<$($name: Interpolate),+> Interpolate for ($($name,)+) {
+            fn interpolate(&self, other: &Self, t: f64) -> Self {
+                ($(self.$index.interpolate(&other.$index, t),)+)
+            }
+        }
+    };
+}
+
+impl_interpolate_tuple!(A 0);
+impl_interpolate_tuple!(A 0, B 1);
+impl_interpolate_tuple!(A 0, B 1, C 2);
+impl_interpolate_tuple!(A 0, B 1, C 2, D 3);
+impl_interpolate_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_interpolate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_interpolate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_interpolate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+impl_interpolate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+impl_interpolate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+impl_interpolate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+impl_interpolate_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
+
+#[cfg(test)]
+mod test_interpolate {
+
+    use crate::interpolate::snap;
+    use crate::Interpolate;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum State {
+        Idle,
+        Running,
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!(2.0f64.interpolate(&4.0, 0.5), 3.0);
+        assert_eq!(2.0f32.interpolate(&4.0, 0.25), 2.5);
+        assert_eq!((-1.0f64).interpolate(&1.0, 0.0), -1.0);
+        assert_eq!((-1.0f64).interpolate(&1.0, 1.0), 1.0);
+    }
+
+    #[test]
+    fn test_integers_are_rounded() {
+        assert_eq!(0u8.interpolate(&3, 0.5), 2);
+        assert_eq!(0u8.interpolate(&3, 0.4), 1);
+        assert_eq!(10i32.interpolate(&-10, 0.75), -5);
+        assert_eq!(0u64.interpolate(&u64::MAX, 1.0), u64::MAX);
+        assert_eq!(u64::MAX.interpolate(&0, 0.0), u64::MAX);
+        assert_eq!(i64::MIN.interpolate(&i64::MAX, 1.0), i64::MAX);
+        assert_eq!((u64::MAX - 10).interpolate(&u64::MAX, 0.5), u64::MAX - 5);
+        assert_eq!(200u8.interpolate(&255, 1.0), 255);
+        assert_eq!(100usize.interpolate(&0, 0.5), 50);
+    }
+
+    #[test]
+    fn test_compound() {
+        assert_eq!([0.0, 10.0].interpolate(&[10.0, 20.0], 0.5), [5.0, 15.0]);
+        assert_eq!(
+            (0.0f64, 0u8, true).interpolate(&(1.0, 10, false), 0.5),
+            (0.5, 5, true)
+        );
+        assert_eq!(Some(0.0).interpolate(&Some(1.0), 0.5), Some(0.5));
+        assert_eq!(Some(0.0).interpolate(&None, 0.5), Some(0.0));
+        assert_eq!(None.interpolate(&Some(1.0), 0.5), None);
+    }
+
+    #[test]
+    fn test_snap() {
+        assert_eq!(snap(&State::Idle, &State::Running, 0.0), State::Idle);
+        assert_eq!(snap(&State::Idle, &State::Running, 0.99), State::Idle);
+        assert_eq!(snap(&State::Idle, &State::Running, 1.0), State::Running);
+        assert!(!false.interpolate(&true, 0.5));
+    }
+}
@@ -0,0 +1,470 @@
+use crate::snapshot::SnapshotEntity;
+use crate::{Component, ComponentRegistry, Eid, Interpolate, Snapshot};
+use std::collections::BTreeMap;
+
+/// A view of the state between two `Snapshot`s at some render time.
+///
+/// An entity is visible if it is in the `from` snapshot. Entities which are only in the
+/// `to` snapshot were created between the two snapshots and pop in once the render time
+/// reaches the tick of `to`. Entities which are only in `from` were destroyed between
+/// the two snapshots and pop out at the same moment.
+#[derive(Debug, Clone, Copy)]
+pub struct Interpolation<'a> {
+    from: &'a Snapshot,
+    to: &'a Snapshot,
+    alpha: f64,
+}
+
+impl<'a> Interpolation<'a> {
+    /// Returns the latest `Snapshot` at or before the render time.
+    pub fn from(&self) -> &'a Snapshot {
+        self.from
+    }
+
+    /// Returns the earliest `Snapshot` after the render time. If the render time is
+    /// exactly on the tick of the newest `Snapshot`, this is the same as `from`.
+    pub fn to(&self) -> &'a Snapshot {
+        self.to
+    }
+
+    /// Returns how far the render time is between `from` (0.0) and `to` (1.0).
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    /// Iterates over the entities which are visible at the render time.
+    pub fn entities(&self) -> impl Iterator<Item = Eid> + 'a {
+        self.from.entities()
+    }
+
+    /// Returns true if the entity is visible at the render time.
+    pub fn contains(&self, entity: Eid) -> bool {
+        self.from.contains(entity)
+    }
+
+    /// Gets the component C of a visible entity in `from` along with the component in
+    /// `to` if the entity still has it there. Returns None if the entity isn't visible
+    /// or doesn't have a component C.
+    pub fn endpoints<C: Component>(&self, entity: Eid) -> Option<(&'a C, Option<&'a C>)> {
+        let from = self.from.get_component::<C>(entity)?;
+        Some((from, self.to.get_component::<C>(entity)))
+    }
+
+    /// Gets the component C of a visible entity blended at the render time. If the
+    /// entity doesn't have a component C in `to`, the component from `from` is returned.
+    pub fn get<C: Component + Interpolate>(&self, entity: Eid) -> Option<C> {
+        match self.endpoints::<C>(entity)? {
+            (from, Some(to)) => Some(from.interpolate(to, self.alpha)),
+            (from, None) => Some(from.clone()),
+        }
+    }
+
+    /// Builds a `Snapshot` of the visible entities at the render time, which can be
+    /// applied to a `World` with `World::apply_snapshot`. Components registered with
+    /// `ComponentBuilder::interpolated` are blended, every other component snaps to its
+    /// value in `from`. The `Snapshot` has the tick of `from`.
+    ///
+    /// # Example
+    /// ```
+    /// extern crate ecsnap;
+    /// use ecsnap::{Component, Interpolate, Sample, SnapshotBuffer, System, World};
+    ///
+    /// #[derive(Debug, Clone, Copy, PartialEq)]
+    /// struct Pos(f64);
+    /// impl Component for Pos {}
+    /// impl Interpolate for Pos {
+    ///     fn interpolate(&self, other: &Self, t: f64) -> Self {
+    ///         Pos(self.0.interpolate(&other.0, t))
+    ///     }
+    /// }
+    ///
+    /// struct MoveSystem;
+    /// impl System for MoveSystem {
+    ///     type Data = Pos;
+    ///     fn run(&mut self, pos: &mut Pos) {
+    ///         pos.0 += 8.0;
+    ///     }
+    /// }
+    ///
+    /// let mut server = World::default();
+    /// server.registry_mut().register::<Pos>().interpolated().build().unwrap();
+    /// let e = server.create_entity().with(Pos(0.0)).build();
+    ///
+    /// let mut buffer = SnapshotBuffer::new(8);
+    /// buffer.insert(server.snapshot(0));
+    /// server.dispatch_system(&mut MoveSystem);
+    /// buffer.insert(server.snapshot(4));
+    ///
+    /// let mut client = World::default();
+    /// client.registry_mut().register::<Pos>().interpolated().build().unwrap();
+    /// if let Sample::Interpolated(view) = buffer.sample(1.0) {
+    ///     assert_eq!(view.get::<Pos>(e), Some(Pos(2.0)));
+    ///     client.apply_snapshot(&view.snapshot(client.registry()));
+    /// }
+    /// ```
+    pub fn snapshot(&self, registry: &ComponentRegistry) -> Snapshot {
+        let mut snapshot = Snapshot::new(self.from.tick());
+        for (eid, from) in self.from.entities.iter() {
+            let to = self.to.get(*eid);
+            let mut entity = SnapshotEntity::default();
+            for (type_id, component) in from.components.iter() {
+                let blended = match (
+                    registry.get_by_type(*type_id).and_then(|i| i.interpolate),
+                    to.and_then(|to| to.components.get(type_id)),
+                ) {
+                    (Some(interpolate), Some(to)) => interpolate(&**component, &**to, self.alpha),
+                    _ => None,
+                };
+                entity
+                    .components
+                    .insert(*type_id, blended.unwrap_or_else(|| component.clone()));
+            }
+            snapshot.entities.insert(*eid, entity);
+        }
+        snapshot
+    }
+}
+
+/// The result of sampling a `SnapshotBuffer` at some render time.
+#[derive(Debug, Clone, Copy)]
+pub enum Sample<'a> {
+    /// The buffer doesn't hold a `Snapshot` at or before the render time.
+    Empty,
+    /// The render time is covered by the buffer.
+    Interpolated(Interpolation<'a>),
+    /// The render time is past the newest `Snapshot` in the buffer, so the state has to
+    /// be extrapolated from it.
+    Starved {
+        /// The newest `Snapshot` in the buffer.
+        latest: &'a Snapshot,
+        /// The `Snapshot` before `latest`, if there is one.
+        previous: Option<&'a Snapshot>,
+        /// How many ticks the render time is past `latest`.
+        ticks_ahead: f64,
+    },
+}
+
+/// Stores `Snapshot`s received from a server, keyed by tick, so the client can render
+/// the state at a time which lies between them.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Sample, Snapshot, SnapshotBuffer};
+///
+/// let mut buffer = SnapshotBuffer::new(32);
+/// buffer.insert(Snapshot::new(20));
+/// buffer.insert(Snapshot::new(10));
+///
+/// match buffer.sample(12.5) {
+///     Sample::Interpolated(view) => {
+///         assert_eq!(view.from().tick(), 10);
+///         assert_eq!(view.to().tick(), 20);
+///         assert_eq!(view.alpha(), 0.25);
+///     }
+///     _ => panic!(),
+/// }
+/// assert!(buffer.is_starved(21.0));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SnapshotBuffer {
+    snapshots: BTreeMap<u64, Snapshot>,
+    capacity: usize,
+}
+
+impl SnapshotBuffer {
+    /// Creates a `SnapshotBuffer` which holds at most `capacity` snapshots. When full,
+    /// the oldest `Snapshot` is dropped to make room for a newer one.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a SnapshotBuffer needs room for a snapshot");
+        SnapshotBuffer {
+            snapshots: BTreeMap::new(),
+            capacity,
+        }
+    }
+
+    /// Returns the number of snapshots in the buffer.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Returns true if the buffer doesn't hold any snapshots.
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Returns the `Snapshot` with the highest tick.
+    pub fn latest(&self) -> Option<&Snapshot> {
+        self.snapshots.values().next_back()
+    }
+
+    /// Returns the `Snapshot` with the lowest tick.
+    pub fn oldest(&self) -> Option<&Snapshot> {
+        self.snapshots.values().next()
+    }
+
+    /// Gets the `Snapshot` for a tick.
+    pub fn get(&self, tick: u64) -> Option<&Snapshot> {
+        self.snapshots.get(&tick)
+    }
+
+    /// Adds a `Snapshot` to the buffer. Snapshots may arrive in any order. Returns false
+    /// and drops the `Snapshot` if the buffer already has one for its tick, or if the
+    /// buffer is full and the `Snapshot` is older than all of the ones in it.
+    pub fn insert(&mut self, snapshot: Snapshot) -> bool {
+        let tick = snapshot.tick();
+        if self.snapshots.contains_key(&tick) {
+            return false;
+        }
+        if self.snapshots.len() >= self.capacity {
+            match self.snapshots.keys().next() {
+                Some(oldest) if *oldest > tick => return false,
+                Some(oldest) => {
+                    let oldest = *oldest;
+                    self.snapshots.remove(&oldest);
+                }
+                None => {}
+            }
+        }
+        self.snapshots.insert(tick, snapshot);
+        true
+    }
+
+    /// Drops every `Snapshot` which is no longer needed to sample render times at or
+    /// after `render_tick`.
+    pub fn discard_before(&mut self, render_tick: f64) {
+        let keep = self
+            .snapshots
+            .keys()
+            .rev()
+            .find(|tick| **tick as f64 <= render_tick)
+            .cloned();
+        if let Some(keep) = keep {
+            self.snapshots = self.snapshots.split_off(&keep);
+        }
+    }
+
+    /// Returns true if `render_tick` is past the newest `Snapshot`, meaning the client
+    /// has to extrapolate.
+    pub fn is_starved(&self, render_tick: f64) -> bool {
+        match self.snapshots.keys().next_back() {
+            Some(latest) => render_tick > *latest as f64,
+            None => true,
+        }
+    }
+
+    /// Samples the buffer at a render time, measured in (fractional) ticks.
+    pub fn sample(&self, render_tick: f64) -> Sample<'_> {
+        let from = self
+            .snapshots
+            .values()
+            .rev()
+            .find(|s| s.tick() as f64 <= render_tick);
+        let from = match from {
+            Some(from) => from,
+            None => return Sample::Empty,
+        };
+        let to = self
+            .snapshots
+            .range(from.tick() + 1..)
+            .map(|(_, s)| s)
+            .next();
+        match to {
+            Some(to) => {
+                let span = (to.tick() - from.tick()) as f64;
+                Sample::Interpolated(Interpolation {
+                    from,
+                    to,
+                    alpha: (render_tick - from.tick() as f64) / span,
+                })
+            }
+            None if render_tick == from.tick() as f64 => Sample::Interpolated(Interpolation {
+                from,
+                to: from,
+                alpha: 0.0,
+            }),
+            None => Sample::Starved {
+                latest: from,
+                previous: self
+                    .snapshots
+                    .range(..from.tick())
+                    .map(|(_, s)| s)
+                    .next_back(),
+                ticks_ahead: render_tick - from.tick() as f64,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_interpolation {
+
+    use crate::{Component, Interpolate, Sample, Snapshot, SnapshotBuffer, World};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos {
+        x: f64,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Anim {
+        Idle,
+        Jump,
+    }
+
+    impl Component for Pos {}
+    impl Component for Anim {}
+
+    impl Interpolate for Pos {
+        fn interpolate(&self, other: &Self, t: f64) -> Self {
+            Pos {
+                x: self.x.interpolate(&other.x, t),
+            }
+        }
+    }
+
+    fn interpolated(sample: Sample) -> (u64, u64, f64) {
+        match sample {
+            Sample::Interpolated(view) => (view.from().tick(), view.to().tick(), view.alpha()),
+            other => panic!("expected an interpolation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_sample_brackets_render_time() {
+        let mut buffer = SnapshotBuffer::new(8);
+        assert!(matches!(buffer.sample(0.0), Sample::Empty));
+
+        assert!(buffer.insert(Snapshot::new(4)));
+        assert!(buffer.insert(Snapshot::new(2)));
+        assert!(buffer.insert(Snapshot::new(8)));
+        assert!(!buffer.insert(Snapshot::new(4)));
+
+        assert!(matches!(buffer.sample(1.5), Sample::Empty));
+        assert_eq!(interpolated(buffer.sample(2.0)), (2, 4, 0.0));
+        assert_eq!(interpolated(buffer.sample(3.0)), (2, 4, 0.5));
+        assert_eq!(interpolated(buffer.sample(4.0)), (4, 8, 0.0));
+        assert_eq!(interpolated(buffer.sample(7.0)), (4, 8, 0.75));
+        assert_eq!(interpolated(buffer.sample(8.0)), (8, 8, 0.0));
+    }
+
+    #[test]
+    fn test_starved() {
+        let mut buffer = SnapshotBuffer::new(8);
+        assert!(buffer.is_starved(0.0));
+        buffer.insert(Snapshot::new(1));
+        buffer.insert(Snapshot::new(2));
+
+        assert!(!buffer.is_starved(2.0));
+        assert!(buffer.is_starved(2.5));
+        match buffer.sample(3.5) {
+            Sample::Starved {
+                latest,
+                previous,
+                ticks_ahead,
+            } => {
+                assert_eq!(latest.tick(), 2);
+                assert_eq!(previous.unwrap().tick(), 1);
+                assert_eq!(ticks_ahead, 1.5);
+            }
+            other => panic!("expected starvation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_capacity_and_discard() {
+        let mut buffer = SnapshotBuffer::new(3);
+        for tick in 1..=4 {
+            assert!(buffer.insert(Snapshot::new(tick)));
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.oldest().unwrap().tick(), 2);
+        assert!(!buffer.insert(Snapshot::new(1)));
+
+        buffer.discard_before(3.5);
+        assert_eq!(buffer.oldest().unwrap().tick(), 3);
+        assert_eq!(buffer.latest().unwrap().tick(), 4);
+        assert_eq!(interpolated(buffer.sample(3.5)), (3, 4, 0.5));
+    }
+
+    #[test]
+    fn test_entities_pop_in_and_out() {
+        let mut world = World::default();
+        world.register_component::<Pos>();
+        let stays = world.create_entity().with(Pos { x: 0.0 }).build();
+        let leaves = world.create_entity().with(Pos { x: 5.0 }).build();
+        let first = world.snapshot(10);
+
+        world.destroy_entity(&leaves).unwrap();
+        let arrives = world.create_entity().with(Pos { x: 1.0 }).build();
+        let second = world.snapshot(20);
+
+        let mut buffer = SnapshotBuffer::new(4);
+        buffer.insert(first);
+        buffer.insert(second);
+
+        match buffer.sample(19.0) {
+            Sample::Interpolated(view) => {
+                assert!(view.contains(stays));
+                assert!(view.contains(leaves));
+                assert!(!view.contains(arrives));
+                assert_eq!(
+                    view.endpoints::<Pos>(stays),
+                    Some((&Pos { x: 0.0 }, Some(&Pos { x: 0.0 })))
+                );
+                assert_eq!(view.endpoints::<Pos>(leaves), Some((&Pos { x: 5.0 }, None)));
+                assert_eq!(view.endpoints::<Pos>(arrives), None);
+            }
+            other => panic!("expected an interpolation, got {:?}", other),
+        }
+        match buffer.sample(20.0) {
+            Sample::Interpolated(view) => {
+                assert_eq!(view.entities().collect::<Vec<_>>(), vec![stays, arrives]);
+            }
+            other => panic!("expected an interpolation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_registered_strategies() {
+        let mut world = World::default();
+        world
+            .registry_mut()
+            .register::<Pos>()
+            .interpolated()
+            .build()
+            .unwrap();
+        world.register_component::<Anim>();
+        let e = world
+            .create_entity()
+            .with(Pos { x: 0.0 })
+            .with(Anim::Idle)
+            .build();
+        let first = world.snapshot(0);
+        world.add_component_to_entity(&e, Pos { x: 10.0 }).unwrap();
+        world.add_component_to_entity(&e, Anim::Jump).unwrap();
+        let second = world.snapshot(10);
+
+        let mut buffer = SnapshotBuffer::new(4);
+        buffer.insert(first);
+        buffer.insert(second);
+
+        let view = match buffer.sample(3.0) {
+            Sample::Interpolated(view) => view,
+            other => panic!("expected an interpolation, got {:?}", other),
+        };
+        let blended = view.snapshot(world.registry());
+        assert_eq!(blended.tick(), 0);
+        assert_eq!(blended.get_component::<Pos>(e), Some(&Pos { x: 3.0 }));
+        assert_eq!(blended.get_component::<Anim>(e), Some(&Anim::Idle));
+
+        let mut client = World::default();
+        client.register_component::<Pos>();
+        client.register_component::<Anim>();
+        client.apply_snapshot(&blended);
+        assert_eq!(
+            client.get_component_for_entity::<Pos>(&e),
+            Ok(&Pos { x: 3.0 })
+        );
+    }
+}
@@ -45,9 +45,9 @@
 //! impl System for MovementSystem {
 //!     // Define the components required for this system.    
 //!     type Data = (Pos, Vel);
-//!     // Define the operation on the Component data. All data fetched is mutable.
-//!     fn run(&mut self, data: &mut Self::Data){
-//!         let (pos, vel) = data;
+//!     // Define the operation on the Component data. Components are borrowed straight
+//!     // from the World, so all data fetched is a mutable reference.
+//!     fn run(&mut self, (pos, vel): (&mut Pos, &mut Vel)) {
 //!         pos.x += vel.x * self.dt;
 //!         pos.y += vel.y * self.dt;
 //!         println!("Updated Position! {:?}", pos);
@@ -80,11 +80,59 @@
 mod component;
 pub use component::Component;
 
+mod encode;
+pub use encode::{BitReader, BitWriter, DecodeError, Encode, EncodeError};
+
+mod registry;
+pub use registry::{
+    ComponentBuilder, ComponentId, ComponentInfo, ComponentRegistry, RegistryError,
+};
+
 mod entity;
 pub use entity::{Eid, Entity, EntityBuilder};
 
+mod storage;
+pub use storage::Backend;
+
+mod archetype;
+
 mod world;
-pub use world::World;
+pub use world::{EcsError, World};
 
 mod system;
-pub use system::{System, SystemData};
+pub use system::{Access, Fetch, ParSystem, Read, System, SystemData, With, Without, Write};
+
+mod change;
+pub use change::{Added, Changed};
+
+mod removal;
+pub use removal::{RemovedComponents, Tombstones};
+
+mod diagnostics;
+pub use diagnostics::{Diagnostics, NoDiagnostics, StatsCollector, SystemStats};
+
+mod resource;
+pub use resource::{Res, ResMut, Resource};
+
+mod commands;
+pub use commands::{Commands, SpawnBuilder};
+
+mod schedule;
+pub use schedule::{Schedule, ScheduleError, Stage, SystemBuilder};
+
+mod tick;
+pub use tick::{Tick, TickLoop};
+
+mod snapshot;
+pub use snapshot::{Snapshot, SnapshotEntity};
+
+mod delta;
+
+mod interpolate;
+pub use interpolate::{snap, Interpolate};
+
+mod interpolation;
+pub use interpolation::{Interpolation, Sample, SnapshotBuffer};
+
+mod packet;
+pub use packet::{PacketError, Packetizer, Reassembler, DEFAULT_MTU, FRAGMENT_HEADER_SIZE};
@@ -0,0 +1,503 @@
+use crate::{BitReader, BitWriter, DecodeError, Encode};
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// A conservative datagram size which fits in the MTU of nearly every network path.
+pub const DEFAULT_MTU: usize = 1200;
+
+/// Size in bytes of the header at the start of every fragment.
+pub const FRAGMENT_HEADER_SIZE: usize = 8;
+
+/// Number of completed messages remembered so that late duplicates are ignored.
+const COMPLETED_HISTORY: usize = 256;
+
+/// Number of incomplete messages held at once, however few bytes they take.
+const MAX_PENDING_MESSAGES: usize = 256;
+
+/// Error returned when splitting or reassembling datagrams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketError {
+    /// The payload needs more fragments than the header can number.
+    PayloadTooLarge,
+    /// The datagram is too short to hold a fragment header.
+    Truncated,
+    /// The fragment header is inconsistent, or disagrees with earlier fragments of the
+    /// same message.
+    InvalidHeader,
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PacketError::PayloadTooLarge => write!(f, "payload needs too many fragments"),
+            PacketError::Truncated => write!(f, "datagram is shorter than a fragment header"),
+            PacketError::InvalidHeader => write!(f, "invalid fragment header"),
+        }
+    }
+}
+
+impl Error for PacketError {}
+
+/// Header written at the start of every fragment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FragmentHeader {
+    message: u32,
+    index: u16,
+    count: u16,
+}
+
+impl Encode for FragmentHeader {
+    fn encode(&self, writer: &mut BitWriter) {
+        self.message.encode(writer);
+        self.index.encode(writer);
+        self.count.encode(writer);
+    }
+    fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+        Ok(FragmentHeader {
+            message: u32::decode(reader)?,
+            index: u16::decode(reader)?,
+            count: u16::decode(reader)?,
+        })
+    }
+}
+
+/// Splits encoded messages, such as snapshots, into numbered fragments which each fit
+/// in a single UDP datagram.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Packetizer, Reassembler};
+/// use std::time::{Duration, Instant};
+///
+/// let payload: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+///
+/// let mut packetizer = Packetizer::new(1200);
+/// let datagrams = packetizer.packetize(&payload).unwrap();
+/// assert_eq!(datagrams.len(), 3);
+/// assert!(datagrams.iter().all(|d| d.len() <= 1200));
+///
+/// let mut reassembler = Reassembler::new(Duration::from_secs(1), 1 << 20);
+/// let now = Instant::now();
+/// assert_eq!(reassembler.receive(&datagrams[2], now), Ok(None));
+/// assert_eq!(reassembler.receive(&datagrams[0], now), Ok(None));
+/// assert_eq!(reassembler.receive(&datagrams[1], now), Ok(Some(payload)));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Packetizer {
+    mtu: usize,
+    next_message: u32,
+}
+
+impl Default for Packetizer {
+    fn default() -> Self {
+        Packetizer::new(DEFAULT_MTU)
+    }
+}
+
+impl Packetizer {
+    /// Creates a `Packetizer` which produces datagrams of at most `mtu` bytes, header
+    /// included.
+    pub fn new(mtu: usize) -> Self {
+        assert!(
+            mtu > FRAGMENT_HEADER_SIZE,
+            "mtu must leave room for a fragment header"
+        );
+        Packetizer {
+            mtu,
+            next_message: 0,
+        }
+    }
+
+    /// Returns the maximum size of the datagrams produced.
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Splits a payload into datagrams. Each call numbers its datagrams with a new
+    /// message id, so fragments of different payloads aren't mixed up on reassembly.
+    pub fn packetize(&mut self, payload: &[u8]) -> Result<Vec<Vec<u8>>, PacketError> {
+        let chunk_size = self.mtu - FRAGMENT_HEADER_SIZE;
+        let count = payload.len().div_ceil(chunk_size).max(1);
+        if count > usize::from(u16::MAX) {
+            return Err(PacketError::PayloadTooLarge);
+        }
+
+        let message = self.next_message;
+        self.next_message = self.next_message.wrapping_add(1);
+
+        let mut datagrams = Vec::with_capacity(count);
+        for index in 0..count {
+            let start = index * chunk_size;
+            let end = (start + chunk_size).min(payload.len());
+            let mut writer = BitWriter::new();
+            FragmentHeader {
+                message,
+                index: index as u16,
+                count: count as u16,
+            }
+            .encode(&mut writer);
+            let mut datagram = writer.into_bytes();
+            datagram.extend_from_slice(&payload[start..end]);
+            datagrams.push(datagram);
+        }
+        Ok(datagrams)
+    }
+}
+
+/// The fragments received so far for a message.
+#[derive(Debug)]
+struct Pending {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: usize,
+    first_seen: Instant,
+}
+
+impl Pending {
+    /// Returns the bytes a message split into `count` fragments takes before any of them
+    /// arrive, so that fragments claiming a huge count are charged for their slots.
+    fn overhead(count: usize) -> usize {
+        size_of::<Pending>() + size_of::<Option<Vec<u8>>>() * count
+    }
+}
+
+/// Rebuilds messages from the datagrams produced by a `Packetizer`. Fragments may
+/// arrive in any order and more than once. Messages which aren't complete within the
+/// timeout are dropped, as are the oldest incomplete messages whenever the fragments
+/// held would exceed the memory limit or too many messages are incomplete at once.
+#[derive(Debug)]
+pub struct Reassembler {
+    timeout: Duration,
+    max_pending_bytes: usize,
+    pending_bytes: usize,
+    pending: HashMap<u32, Pending>,
+    completed: VecDeque<u32>,
+}
+
+impl Reassembler {
+    /// Creates a `Reassembler` which drops incomplete messages after `timeout` and holds
+    /// at most `max_pending_bytes` bytes of fragments, counting the slots kept for the
+    /// fragments which haven't arrived yet.
+    pub fn new(timeout: Duration, max_pending_bytes: usize) -> Self {
+        Reassembler {
+            timeout,
+            max_pending_bytes,
+            pending_bytes: 0,
+            pending: HashMap::new(),
+            completed: VecDeque::new(),
+        }
+    }
+
+    /// Returns the number of incomplete messages being held.
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns the number of bytes held by incomplete messages, counting the slots kept
+    /// for their missing fragments.
+    pub fn pending_bytes(&self) -> usize {
+        self.pending_bytes
+    }
+
+    /// Drops every incomplete message first seen more than the timeout before `now`.
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let mut dropped = 0;
+        self.pending.retain(|_, p| {
+            let keep = now.saturating_duration_since(p.first_seen) <= timeout;
+            if !keep {
+                dropped += p.bytes;
+            }
+            keep
+        });
+        self.pending_bytes -= dropped;
+    }
+
+    /// Handles a datagram received at `now`. Returns the whole message once its last
+    /// missing fragment arrives, and None otherwise.
+    pub fn receive(
+        &mut self,
+        datagram: &[u8],
+        now: Instant,
+    ) -> Result<Option<Vec<u8>>, PacketError> {
+        if datagram.len() < FRAGMENT_HEADER_SIZE {
+            return Err(PacketError::Truncated);
+        }
+        let header = FragmentHeader::from_bytes(&datagram[..FRAGMENT_HEADER_SIZE])
+            .map_err(|_| PacketError::Truncated)?;
+        if header.count == 0 || header.index >= header.count {
+            return Err(PacketError::InvalidHeader);
+        }
+        let data = &datagram[FRAGMENT_HEADER_SIZE..];
+
+        self.expire(now);
+        if self.completed.contains(&header.message) {
+            return Ok(None);
+        }
+        if header.count == 1 {
+            self.complete(header.message);
+            return Ok(Some(data.to_vec()));
+        }
+        let overhead = Pending::overhead(usize::from(header.count));
+        if overhead > self.max_pending_bytes || data.len() > self.max_pending_bytes - overhead {
+            return Ok(None);
+        }
+
+        let pending_bytes = &mut self.pending_bytes;
+        let pending = self.pending.entry(header.message).or_insert_with(|| {
+            *pending_bytes += overhead;
+            Pending {
+                fragments: vec![None; usize::from(header.count)],
+                received: 0,
+                bytes: overhead,
+                first_seen: now,
+            }
+        });
+        if pending.fragments.len() != usize::from(header.count) {
+            return Err(PacketError::InvalidHeader);
+        }
+        let slot = &mut pending.fragments[usize::from(header.index)];
+        if slot.is_some() {
+            return Ok(None);
+        }
+        *slot = Some(data.to_vec());
+        pending.received += 1;
+        pending.bytes += data.len();
+        self.pending_bytes += data.len();
+
+        if pending.received == pending.fragments.len() {
+            let pending = self
+                .pending
+                .remove(&header.message)
+                .expect("pending message was just updated");
+            self.pending_bytes -= pending.bytes;
+            self.complete(header.message);
+            let mut message = Vec::with_capacity(pending.bytes - overhead);
+            for fragment in pending.fragments.into_iter().flatten() {
+                message.extend_from_slice(&fragment);
+            }
+            return Ok(Some(message));
+        }
+
+        self.evict(header.message);
+        Ok(None)
+    }
+
+    /// Remembers that a message was completed so that late duplicates are ignored.
+    fn complete(&mut self, message: u32) {
+        if self.completed.len() >= COMPLETED_HISTORY {
+            self.completed.pop_front();
+        }
+        self.completed.push_back(message);
+    }
+
+    /// Drops the oldest incomplete messages until the held fragments fit in the memory
+    /// limit and no more than `MAX_PENDING_MESSAGES` are held, preferring to keep
+    /// `current`.
+    fn evict(&mut self, current: u32) {
+        while self.pending_bytes > self.max_pending_bytes
+            || self.pending.len() > MAX_PENDING_MESSAGES
+        {
+            let oldest = self
+                .pending
+                .iter()
+                .filter(|(id, _)| **id != current || self.pending.len() == 1)
+                .min_by_key(|(id, p)| (p.first_seen, **id))
+                .map(|(id, _)| *id);
+            match oldest.and_then(|id| self.pending.remove(&id)) {
+                Some(dropped) => self.pending_bytes -= dropped.bytes,
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_packet {
+
+    use crate::packet::{Pending, MAX_PENDING_MESSAGES};
+    use crate::{PacketError, Packetizer, Reassembler, FRAGMENT_HEADER_SIZE};
+    use std::time::{Duration, Instant};
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7) as u8).collect()
+    }
+
+    #[test]
+    fn test_fragments_fit_in_mtu() {
+        let mut packetizer = Packetizer::new(100);
+        for len in [0, 1, 91, 92, 93, 1000].iter() {
+            let datagrams = packetizer.packetize(&payload(*len)).unwrap();
+            let chunk = 100 - FRAGMENT_HEADER_SIZE;
+            assert_eq!(datagrams.len(), len.div_ceil(chunk).max(1));
+            assert!(datagrams.iter().all(|d| d.len() <= 100));
+        }
+
+        let mut tiny = Packetizer::new(FRAGMENT_HEADER_SIZE + 1);
+        assert_eq!(
+            tiny.packetize(&payload(70_000)),
+            Err(PacketError::PayloadTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_out_of_order_and_duplicates() {
+        let mut packetizer = Packetizer::new(64);
+        let message = payload(500);
+        let datagrams = packetizer.packetize(&message).unwrap();
+        let mut reassembler = Reassembler::new(Duration::from_secs(1), 1 << 16);
+        let now = Instant::now();
+
+        let mut order: Vec<usize> = (0..datagrams.len()).rev().collect();
+        let last = order.pop().unwrap();
+        for i in order.iter() {
+            assert_eq!(reassembler.receive(&datagrams[*i], now), Ok(None));
+            assert_eq!(reassembler.receive(&datagrams[*i], now), Ok(None));
+        }
+        assert_eq!(
+            reassembler.receive(&datagrams[last], now),
+            Ok(Some(message))
+        );
+        assert_eq!(reassembler.pending_len(), 0);
+        assert_eq!(reassembler.pending_bytes(), 0);
+
+        // Late duplicates of a completed message are ignored.
+        assert_eq!(reassembler.receive(&datagrams[1], now), Ok(None));
+        assert_eq!(reassembler.pending_len(), 0);
+    }
+
+    #[test]
+    fn test_interleaved_messages() {
+        let mut packetizer = Packetizer::new(32);
+        let a = payload(100);
+        let b: Vec<u8> = payload(80).into_iter().rev().collect();
+        let da = packetizer.packetize(&a).unwrap();
+        let db = packetizer.packetize(&b).unwrap();
+        let mut reassembler = Reassembler::new(Duration::from_secs(1), 1 << 16);
+        let now = Instant::now();
+
+        let mut results = Vec::new();
+        for (x, y) in da.iter().zip(db.iter()) {
+            results.extend(reassembler.receive(x, now).unwrap());
+            results.extend(reassembler.receive(y, now).unwrap());
+        }
+        for x in da.iter().skip(db.len()) {
+            results.extend(reassembler.receive(x, now).unwrap());
+        }
+        assert_eq!(results, vec![b, a]);
+    }
+
+    #[test]
+    fn test_incomplete_messages_time_out() {
+        let mut packetizer = Packetizer::new(32);
+        let datagrams = packetizer.packetize(&payload(100)).unwrap();
+        let mut reassembler = Reassembler::new(Duration::from_millis(100), 1 << 16);
+        let start = Instant::now();
+
+        reassembler.receive(&datagrams[0], start).unwrap();
+        assert_eq!(reassembler.pending_len(), 1);
+        reassembler.expire(start + Duration::from_millis(50));
+        assert_eq!(reassembler.pending_len(), 1);
+
+        // The rest arrives too late, so the message can't be completed anymore.
+        let late = start + Duration::from_millis(150);
+        for d in datagrams.iter().skip(1) {
+            assert_eq!(reassembler.receive(d, late), Ok(None));
+        }
+        assert_eq!(reassembler.pending_len(), 1);
+        reassembler.expire(late + Duration::from_millis(150));
+        assert_eq!(reassembler.pending_len(), 0);
+        assert_eq!(reassembler.pending_bytes(), 0);
+    }
+
+    #[test]
+    fn test_memory_is_capped() {
+        let mut packetizer = Packetizer::new(32);
+        let old = packetizer.packetize(&payload(100)).unwrap();
+        let new = packetizer.packetize(&payload(100)).unwrap();
+        let overhead = Pending::overhead(old.len());
+        let mut reassembler = Reassembler::new(Duration::from_secs(1), overhead + 60);
+        let start = Instant::now();
+
+        reassembler.receive(&old[0], start).unwrap();
+        reassembler.receive(&old[1], start).unwrap();
+        assert_eq!(reassembler.pending_bytes(), overhead + 48);
+
+        // Holding fragments of the new message evicts the old one.
+        let later = start + Duration::from_millis(1);
+        reassembler.receive(&new[0], later).unwrap();
+        reassembler.receive(&new[1], later).unwrap();
+        assert!(reassembler.pending_bytes() <= overhead + 60);
+        assert_eq!(reassembler.pending_len(), 1);
+        for d in old.iter().skip(2) {
+            assert_eq!(reassembler.receive(d, later), Ok(None));
+        }
+    }
+
+    #[test]
+    fn test_flood_of_empty_fragments() {
+        let now = Instant::now();
+        let fragment = |message: u32, count: u16| {
+            let mut datagram = message.to_le_bytes().to_vec();
+            datagram.extend_from_slice(&0u16.to_le_bytes());
+            datagram.extend_from_slice(&count.to_le_bytes());
+            datagram
+        };
+
+        // Each fragment claims the most fragments a message can have, and is charged for
+        // their slots although it carries no data.
+        let limit = 1 << 20;
+        let mut reassembler = Reassembler::new(Duration::from_secs(1), limit);
+        for message in 0..1000 {
+            assert_eq!(
+                reassembler.receive(&fragment(message, u16::MAX), now),
+                Ok(None)
+            );
+            assert!(reassembler.pending_bytes() <= limit);
+        }
+        assert_eq!(reassembler.pending_len(), 0);
+
+        let mut reassembler = Reassembler::new(Duration::from_secs(1), 1 << 30);
+        for message in 0..1000 {
+            assert_eq!(reassembler.receive(&fragment(message, 2), now), Ok(None));
+            assert!(reassembler.pending_len() <= MAX_PENDING_MESSAGES);
+        }
+        assert_eq!(reassembler.pending_len(), MAX_PENDING_MESSAGES);
+        assert_eq!(
+            reassembler.pending_bytes(),
+            MAX_PENDING_MESSAGES * Pending::overhead(2)
+        );
+    }
+
+    #[test]
+    fn test_malformed_datagrams() {
+        let mut reassembler = Reassembler::new(Duration::from_secs(1), 1 << 16);
+        let now = Instant::now();
+        assert_eq!(
+            reassembler.receive(&[0, 1, 2], now),
+            Err(PacketError::Truncated)
+        );
+        // Fragment index 3 of a 2 fragment message.
+        assert_eq!(
+            reassembler.receive(&[0, 0, 0, 0, 3, 0, 2, 0], now),
+            Err(PacketError::InvalidHeader)
+        );
+        assert_eq!(
+            reassembler.receive(&[0, 0, 0, 0, 0, 0, 0, 0], now),
+            Err(PacketError::InvalidHeader)
+        );
+
+        let mut packetizer = Packetizer::new(32);
+        let mut datagrams = packetizer.packetize(&payload(100)).unwrap();
+        reassembler.receive(&datagrams[0], now).unwrap();
+        // Same message id claiming a different fragment count.
+        datagrams[1][6] = 9;
+        assert_eq!(
+            reassembler.receive(&datagrams[1], now),
+            Err(PacketError::InvalidHeader)
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use crate::component::{snapshot_component, AnyComponent, SnapshotFn};
+use crate::{BitReader, BitWriter, Component, DecodeError, Encode, Interpolate};
+use std::any::{type_name, TypeId};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Stable numeric identifier of a registered `Component`. Unlike `TypeId`, a
+/// `ComponentId` is the same for every build which registers its components in the same
+/// way, so it can be sent over the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ComponentId(pub u16);
+
+/// Function used to write a type erased component.
+pub(crate) type EncodeFn = fn(&dyn AnyComponent, &mut BitWriter);
+/// Function used to read a type erased component.
+pub(crate) type DecodeFn = fn(&mut BitReader) -> Result<Box<dyn AnyComponent>, DecodeError>;
+
+/// Function used to blend two type erased components.
+pub(crate) type InterpolateFn =
+    fn(&dyn AnyComponent, &dyn AnyComponent, f64) -> Option<Box<dyn AnyComponent>>;
+
+fn encode_component<C: Component + Encode>(c: &dyn AnyComponent, writer: &mut BitWriter) {
+    if let Some(c) = c.as_any().downcast_ref::<C>() {
+        c.encode(writer);
+    }
+}
+
+fn decode_component<C: Component + Encode>(
+    reader: &mut BitReader,
+) -> Result<Box<dyn AnyComponent>, DecodeError> {
+    Ok(Box::new(C::decode(reader)?))
+}
+
+fn interpolate_component<C: Component + Interpolate>(
+    from: &dyn AnyComponent,
+    to: &dyn AnyComponent,
+    t: f64,
+) -> Option<Box<dyn AnyComponent>> {
+    let from = from.as_any().downcast_ref::<C>()?;
+    let to = to.as_any().downcast_ref::<C>()?;
+    Some(Box::new(from.interpolate(to, t)))
+}
+
+/// Error returned when a `Component` can't be registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryError {
+    /// The component type is already registered with the contained id.
+    AlreadyRegistered(ComponentId),
+    /// Another component type is already registered with the id.
+    IdTaken(ComponentId),
+    /// Another component type is already registered with the name.
+    NameTaken(&'static str),
+    /// Every `ComponentId` is in use.
+    OutOfIds,
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistryError::AlreadyRegistered(id) => {
+                write!(f, "component is already registered with id {}", id.0)
+            }
+            RegistryError::IdTaken(id) => write!(f, "component id {} is already in use", id.0),
+            RegistryError::NameTaken(name) => {
+                write!(f, "component name {:?} is already in use", name)
+            }
+            RegistryError::OutOfIds => write!(f, "no component ids are left"),
+        }
+    }
+}
+
+impl Error for RegistryError {}
+
+/// Everything the `World` knows about a registered `Component` type.
+#[derive(Debug, Clone, Copy)]
+pub struct ComponentInfo {
+    id: ComponentId,
+    name: &'static str,
+    type_id: TypeId,
+    pub(crate) snapshot: SnapshotFn,
+    pub(crate) encode: Option<EncodeFn>,
+    pub(crate) decode: Option<DecodeFn>,
+    pub(crate) interpolate: Option<InterpolateFn>,
+}
+
+impl ComponentInfo {
+    /// Returns the stable id of the component.
+    pub fn id(&self) -> ComponentId {
+        self.id
+    }
+
+    /// Returns the name the component was registered with.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the `TypeId` of the component.
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    /// Returns true if the component was registered with an encoding, meaning it can be
+    /// sent over the network.
+    pub fn is_encodable(&self) -> bool {
+        self.encode.is_some()
+    }
+
+    /// Returns true if the component was registered with its `Interpolate`
+    /// implementation. Components which aren't snap to their latest value instead.
+    pub fn is_interpolated(&self) -> bool {
+        self.interpolate.is_some()
+    }
+}
+
+/// Maps `Component` types to stable `ComponentId`s and stores how to clone, encode,
+/// decode and interpolate each of them.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Component, ComponentId, ComponentRegistry};
+///
+/// #[derive(Debug, Clone, Copy)]
+/// struct Pos {
+///     x: f64,
+///     y: f64,
+/// }
+/// impl Component for Pos {}
+///
+/// #[derive(Debug, Clone, Copy)]
+/// struct Vel {
+///     x: f64,
+///     y: f64,
+/// }
+/// impl Component for Vel {}
+///
+/// let mut registry = ComponentRegistry::default();
+/// let pos = registry.register::<Pos>().build().unwrap();
+/// let vel = registry.register::<Vel>().with_name("vel").build().unwrap();
+///
+/// assert_eq!(pos, ComponentId(0));
+/// assert_eq!(registry.id_of::<Vel>(), Some(vel));
+/// assert_eq!(registry.get_by_name("vel").unwrap().id(), vel);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ComponentRegistry {
+    infos: Vec<ComponentInfo>,
+    by_type: HashMap<TypeId, usize>,
+    by_id: HashMap<ComponentId, usize>,
+    by_name: HashMap<&'static str, usize>,
+}
+
+impl ComponentRegistry {
+    /// Creates a `ComponentBuilder` to register a component C. By default the component
+    /// gets the lowest unused `ComponentId` and is named after its type.
+    pub fn register<C: Component>(&mut self) -> ComponentBuilder<'_, C> {
+        ComponentBuilder {
+            registry: self,
+            id: None,
+            name: type_name::<C>(),
+            encode: None,
+            decode: None,
+            interpolate: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the number of registered components.
+    pub fn len(&self) -> usize {
+        self.infos.len()
+    }
+
+    /// Returns true if no components are registered.
+    pub fn is_empty(&self) -> bool {
+        self.infos.is_empty()
+    }
+
+    /// Returns true if the component C is registered.
+    pub fn contains<C: Component>(&self) -> bool {
+        self.by_type.contains_key(&TypeId::of::<C>())
+    }
+
+    /// Returns the id of the component C if it is registered.
+    pub fn id_of<C: Component>(&self) -> Option<ComponentId> {
+        self.get_by_type(TypeId::of::<C>()).map(ComponentInfo::id)
+    }
+
+    /// Gets the component registered with an id.
+    pub fn get(&self, id: ComponentId) -> Option<&ComponentInfo> {
+        self.by_id.get(&id).map(|i| &self.infos[*i])
+    }
+
+    /// Gets the component registered for a type.
+    pub fn get_by_type(&self, type_id: TypeId) -> Option<&ComponentInfo> {
+        self.by_type.get(&type_id).map(|i| &self.infos[*i])
+    }
+
+    /// Gets the component registered with a name.
+    pub fn get_by_name(&self, name: &str) -> Option<&ComponentInfo> {
+        self.by_name.get(name).map(|i| &self.infos[*i])
+    }
+
+    /// Iterates over the registered components in registration order.
+    pub fn iter(&self) -> impl Iterator<Item = &ComponentInfo> {
+        self.infos.iter()
+    }
+
+    fn next_free_id(&self) -> Option<ComponentId> {
+        (0..=u16::MAX)
+            .map(ComponentId)
+            .find(|id| !self.by_id.contains_key(id))
+    }
+}
+
+/// A helper struct to register a `Component` with a `ComponentRegistry`.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{BitReader, BitWriter, Component, ComponentId, DecodeError, Encode, World};
+///
+/// #[derive(Debug, Clone, Copy)]
+/// struct Health(u8);
+/// impl Component for Health {}
+///
+/// impl Encode for Health {
+///     fn encode(&self, writer: &mut BitWriter) {
+///         self.0.encode(writer);
+///     }
+///     fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+///         Ok(Health(u8::decode(reader)?))
+///     }
+/// }
+///
+/// let mut world = World::default();
+/// let id = world
+///     .registry_mut()
+///     .register::<Health>()
+///     .with_id(ComponentId(12))
+///     .with_name("health")
+///     .encoded()
+///     .build()
+///     .unwrap();
+/// assert!(world.registry().get(id).unwrap().is_encodable());
+/// ```
+#[derive(Debug)]
+pub struct ComponentBuilder<'a, C: Component> {
+    registry: &'a mut ComponentRegistry,
+    id: Option<ComponentId>,
+    name: &'static str,
+    encode: Option<EncodeFn>,
+    decode: Option<DecodeFn>,
+    interpolate: Option<InterpolateFn>,
+    _marker: PhantomData<C>,
+}
+
+impl<'a, C: Component> ComponentBuilder<'a, C> {
+    /// Registers the component with an explicit id instead of the lowest unused one.
+    pub fn with_id(mut self, id: ComponentId) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Registers the component with an explicit name instead of its type name. Type
+    /// names aren't guaranteed to be stable between compiler versions.
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Finishes registering the component and returns its id.
+    pub fn build(self) -> Result<ComponentId, RegistryError> {
+        let registry = self.registry;
+        if let Some(id) = registry.id_of::<C>() {
+            return Err(RegistryError::AlreadyRegistered(id));
+        }
+        let id = match self.id {
+            Some(id) if registry.by_id.contains_key(&id) => {
+                return Err(RegistryError::IdTaken(id));
+            }
+            Some(id) => id,
+            None => registry.next_free_id().ok_or(RegistryError::OutOfIds)?,
+        };
+        if registry.by_name.contains_key(self.name) {
+            return Err(RegistryError::NameTaken(self.name));
+        }
+
+        let index = registry.infos.len();
+        registry.infos.push(ComponentInfo {
+            id,
+            name: self.name,
+            type_id: TypeId::of::<C>(),
+            snapshot: snapshot_component::<C>,
+            encode: self.encode,
+            decode: self.decode,
+            interpolate: self.interpolate,
+        });
+        registry.by_type.insert(TypeId::of::<C>(), index);
+        registry.by_id.insert(id, index);
+        registry.by_name.insert(self.name, index);
+        Ok(id)
+    }
+}
+
+impl<'a, C: Component + Encode> ComponentBuilder<'a, C> {
+    /// Registers the component with its `Encode` implementation so that it can be
+    /// written into encoded `Snapshot`s.
+    pub fn encoded(mut self) -> Self {
+        self.encode = Some(encode_component::<C>);
+        self.decode = Some(decode_component::<C>);
+        self
+    }
+}
+
+impl<'a, C: Component + Interpolate> ComponentBuilder<'a, C> {
+    /// Registers the component with its `Interpolate` implementation so that it is
+    /// blended when sampling a `SnapshotBuffer`.
+    pub fn interpolated(mut self) -> Self {
+        self.interpolate = Some(interpolate_component::<C>);
+        self
+    }
+}
+
+#[cfg(test)]
+mod test_registry {
+
+    use crate::{Component, ComponentId, ComponentRegistry, RegistryError};
+    use std::any::TypeId;
+
+    #[derive(Debug, Clone, Copy)]
+    struct Pos;
+    #[derive(Debug, Clone, Copy)]
+    struct Vel;
+    #[derive(Debug, Clone, Copy)]
+    struct Acc;
+
+    impl Component for Pos {}
+    impl Component for Vel {}
+    impl Component for Acc {}
+
+    #[test]
+    fn test_ids_follow_registration_order() {
+        let mut registry = ComponentRegistry::default();
+        assert_eq!(registry.register::<Pos>().build(), Ok(ComponentId(0)));
+        assert_eq!(registry.register::<Vel>().build(), Ok(ComponentId(1)));
+        assert_eq!(registry.register::<Acc>().build(), Ok(ComponentId(2)));
+
+        let info = registry.get(ComponentId(1)).unwrap();
+        assert_eq!(info.type_id(), TypeId::of::<Vel>());
+        assert!(!info.is_encodable());
+        assert_eq!(registry.id_of::<Acc>(), Some(ComponentId(2)));
+        assert_eq!(
+            registry.iter().map(|i| i.id()).collect::<Vec<_>>(),
+            vec![ComponentId(0), ComponentId(1), ComponentId(2)]
+        );
+    }
+
+    #[test]
+    fn test_explicit_ids_and_names() {
+        let mut registry = ComponentRegistry::default();
+        registry
+            .register::<Pos>()
+            .with_id(ComponentId(1))
+            .with_name("pos")
+            .build()
+            .unwrap();
+        assert_eq!(registry.register::<Vel>().build(), Ok(ComponentId(0)));
+        assert_eq!(registry.register::<Acc>().build(), Ok(ComponentId(2)));
+
+        assert_eq!(registry.get_by_name("pos").unwrap().id(), ComponentId(1));
+        assert_eq!(
+            registry.get_by_type(TypeId::of::<Pos>()).unwrap().name(),
+            "pos"
+        );
+        assert!(registry.get_by_name("vel").is_none());
+    }
+
+    #[test]
+    fn test_registration_errors() {
+        let mut registry = ComponentRegistry::default();
+        registry.register::<Pos>().with_name("pos").build().unwrap();
+
+        assert_eq!(
+            registry.register::<Pos>().build(),
+            Err(RegistryError::AlreadyRegistered(ComponentId(0)))
+        );
+        assert_eq!(
+            registry.register::<Vel>().with_id(ComponentId(0)).build(),
+            Err(RegistryError::IdTaken(ComponentId(0)))
+        );
+        assert_eq!(
+            registry.register::<Vel>().with_name("pos").build(),
+            Err(RegistryError::NameTaken("pos"))
+        );
+        assert_eq!(registry.len(), 1);
+        assert!(!registry.contains::<Vel>());
+    }
+}
@@ -0,0 +1,434 @@
+use crate::snapshot::read_component_info;
+use crate::{
+    Access, BitReader, BitWriter, Component, ComponentRegistry, DecodeError, Eid, Encode,
+    EncodeError, Fetch, SystemData,
+};
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+/// Number of despawns, and of removals of each component type, kept when they aren't
+/// acknowledged. The oldest quarter is forgotten whenever a log is full.
+pub(crate) const MAX_LOGGED: usize = 1 << 16;
+
+/// Entities which lost something, in the order they lost it, with the tick they lost it
+/// at.
+#[derive(Debug, Default)]
+struct Log {
+    ticks: Vec<u64>,
+    eids: Vec<Eid>,
+}
+
+impl Log {
+    fn push(&mut self, eid: Eid, tick: u64) {
+        if self.ticks.len() >= MAX_LOGGED {
+            self.ticks.drain(..MAX_LOGGED / 4);
+            self.eids.drain(..MAX_LOGGED / 4);
+        }
+        self.ticks.push(tick);
+        self.eids.push(eid);
+    }
+
+    fn since(&self, tick: u64) -> &[Eid] {
+        &self.eids[self.ticks.partition_point(|t| *t < tick)..]
+    }
+
+    fn acknowledge(&mut self, tick: u64) {
+        let end = self.ticks.partition_point(|t| *t < tick);
+        self.ticks.drain(..end);
+        self.eids.drain(..end);
+    }
+}
+
+/// The entities despawned from a `World`, and the components removed from its entities,
+/// which not every consumer has acknowledged yet.
+#[derive(Debug, Default)]
+pub(crate) struct Removals {
+    despawned: Log,
+    removed: HashMap<TypeId, Log>,
+}
+
+impl Removals {
+    /// Records that an entity was despawned along with the components it had.
+    pub(crate) fn despawn(&mut self, eid: Eid, types: impl Iterator<Item = TypeId>, tick: u64) {
+        self.despawned.push(eid, tick);
+        for type_id in types {
+            self.remove(eid, type_id, tick);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, eid: Eid, type_id: TypeId, tick: u64) {
+        self.removed.entry(type_id).or_default().push(eid, tick);
+    }
+
+    pub(crate) fn despawned(&self, since: u64) -> &[Eid] {
+        self.despawned.since(since)
+    }
+
+    pub(crate) fn removed(&self, type_id: TypeId, since: u64) -> &[Eid] {
+        self.removed
+            .get(&type_id)
+            .map_or(&[], |log| log.since(since))
+    }
+
+    /// Iterates over the entity and type of every component removed at or after the tick.
+    pub(crate) fn iter_removed(&self, since: u64) -> impl Iterator<Item = (Eid, TypeId)> + '_ {
+        self.removed.iter().flat_map(move |(type_id, log)| {
+            log.since(since).iter().map(move |eid| (*eid, *type_id))
+        })
+    }
+
+    /// Forgets everything which was removed before the tick.
+    pub(crate) fn acknowledge(&mut self, tick: u64) {
+        self.despawned.acknowledge(tick);
+        for log in self.removed.values_mut() {
+            log.acknowledge(tick);
+        }
+    }
+}
+
+/// Borrows the entities which lost their component C since the `System` last ran, either
+/// because it was removed or because they were despawned. Like `Res`, it is the same for
+/// every `Entity` visited, and a `System` which fetches nothing else runs once.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Component, Eid, RemovedComponents, System, World};
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Health(u8);
+/// impl Component for Health {}
+///
+/// #[derive(Default)]
+/// struct Forget(Vec<Eid>);
+/// impl System for Forget {
+///     type Data = RemovedComponents<Health>;
+///     fn run(&mut self, removed: &[Eid]) {
+///         self.0.extend_from_slice(removed);
+///     }
+/// }
+///
+/// let mut world = World::default();
+/// let a = world.create_entity().with(Health(3)).build();
+/// let b = world.create_entity().with(Health(3)).build();
+///
+/// world.remove_component_from_entity::<Health>(&a).unwrap();
+/// world.destroy_entity(&b).unwrap();
+/// let mut forget = Forget::default();
+/// world.dispatch_system(&mut forget);
+/// world.dispatch_system(&mut forget);
+/// assert_eq!(forget.0, vec![a, b]);
+/// ```
+#[derive(Debug)]
+pub struct RemovedComponents<C>(PhantomData<C>);
+
+impl<C> SystemData for RemovedComponents<C>
+where
+    C: Component,
+{
+    type Item<'a> = &'a [Eid];
+
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+        Some(fetch.removed::<C>())
+    }
+
+    fn access(_access: &mut Access) {}
+}
+
+/// The despawned entities and removed components a receiver has to be told about, since
+/// a `Snapshot` only says what still exists. Made with `World::tombstones_since` and
+/// applied with `World::apply_tombstones`. Only registered components are included.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Component, Tombstones, World};
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Health(u8);
+/// impl Component for Health {}
+///
+/// let mut server = World::default();
+/// server.register_component::<Health>();
+/// let a = server.create_entity().with(Health(3)).build();
+/// let b = server.create_entity().with(Health(3)).build();
+///
+/// let mut client = World::default();
+/// client.register_component::<Health>();
+/// client.apply_snapshot(&server.snapshot(0));
+///
+/// let tick = server.increment_change_tick();
+/// server.remove_component_from_entity::<Health>(&a).unwrap();
+/// server.destroy_entity(&b).unwrap();
+///
+/// let sent = server.increment_change_tick();
+/// let bytes = server.tombstones_since(tick).to_bytes(server.registry()).unwrap();
+/// let tombstones = Tombstones::from_bytes(client.registry(), &bytes).unwrap();
+/// client.apply_tombstones(&tombstones);
+///
+/// // Once the client acknowledged the tombstones.
+/// server.acknowledge_removals(sent);
+/// assert!(server.tombstones_since(tick).is_empty());
+///
+/// assert!(client.get_component_for_entity::<Health>(&a).is_err());
+/// assert!(!client.is_alive(&b));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tombstones {
+    pub(crate) despawned: Vec<Eid>,
+    pub(crate) removed: Vec<(Eid, TypeId)>,
+}
+
+impl Tombstones {
+    /// Returns true if nothing was despawned or removed.
+    pub fn is_empty(&self) -> bool {
+        self.despawned.is_empty() && self.removed.is_empty()
+    }
+
+    /// Iterates over the despawned entities in ascending order.
+    pub fn despawned(&self) -> impl Iterator<Item = Eid> + '_ {
+        self.despawned.iter().cloned()
+    }
+
+    /// Iterates over the removed components of entities which are still alive.
+    pub fn removed(&self) -> impl Iterator<Item = (Eid, TypeId)> + '_ {
+        self.removed.iter().cloned()
+    }
+
+    /// Writes the `Tombstones` using the component ids of a `ComponentRegistry`.
+    pub fn encode(
+        &self,
+        registry: &ComponentRegistry,
+        writer: &mut BitWriter,
+    ) -> Result<(), EncodeError> {
+        writer.write_varint(self.despawned.len() as u64);
+        for eid in self.despawned.iter() {
+            eid.encode(writer);
+        }
+        writer.write_varint(self.removed.len() as u64);
+        for (eid, type_id) in self.removed.iter() {
+            let info = registry
+                .get_by_type(*type_id)
+                .ok_or(EncodeError::UnregisteredComponent)?;
+            eid.encode(writer);
+            writer.write_varint(u64::from(info.id().0));
+        }
+        Ok(())
+    }
+
+    /// Reads `Tombstones` written by `Tombstones::encode`.
+    pub fn decode(
+        registry: &ComponentRegistry,
+        reader: &mut BitReader,
+    ) -> Result<Self, DecodeError> {
+        let mut tombstones = Tombstones::default();
+        for _ in 0..reader.read_len()? {
+            tombstones.despawned.push(Eid::decode(reader)?);
+        }
+        for _ in 0..reader.read_len()? {
+            let eid = Eid::decode(reader)?;
+            let type_id = read_component_info(registry, reader)?.type_id();
+            tombstones.removed.push((eid, type_id));
+        }
+        Ok(tombstones)
+    }
+
+    /// Encodes the `Tombstones` into a new buffer.
+    pub fn to_bytes(&self, registry: &ComponentRegistry) -> Result<Vec<u8>, EncodeError> {
+        let mut writer = BitWriter::new();
+        self.encode(registry, &mut writer)?;
+        Ok(writer.into_bytes())
+    }
+
+    /// Decodes `Tombstones` from the start of a buffer.
+    pub fn from_bytes(registry: &ComponentRegistry, bytes: &[u8]) -> Result<Self, DecodeError> {
+        Tombstones::decode(registry, &mut BitReader::new(bytes))
+    }
+}
+
+#[cfg(test)]
+mod test_removal {
+
+    use crate::removal::{Removals, MAX_LOGGED};
+    use crate::{
+        BitReader, BitWriter, Commands, Component, DecodeError, Eid, Encode, RemovedComponents,
+        System, Tombstones, World,
+    };
+    use std::any::TypeId;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos(f32);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Health(u8);
+
+    impl Component for Pos {}
+    impl Component for Health {}
+
+    impl Encode for Health {
+        fn encode(&self, writer: &mut BitWriter) {
+            self.0.encode(writer);
+        }
+        fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+            Ok(Health(u8::decode(reader)?))
+        }
+    }
+
+    fn world() -> World {
+        let mut world = World::default();
+        world.register_component::<Health>();
+        world
+    }
+
+    #[test]
+    fn test_log_until_acknowledged() {
+        let mut world = world();
+        let a = world.create_entity().with(Health(1)).with(Pos(0.0)).build();
+        let b = world.create_entity().with(Health(1)).build();
+
+        let first = world.increment_change_tick();
+        world.remove_component_from_entity::<Health>(&a).unwrap();
+        assert!(world.remove_component_from_entity::<Health>(&a).is_err());
+        let second = world.increment_change_tick();
+        world.destroy_entity(&b).unwrap();
+
+        assert_eq!(world.removed_since::<Health>(first), &[a, b]);
+        assert_eq!(world.removed_since::<Health>(second), &[b]);
+        assert_eq!(world.removed_since::<Pos>(first), &[]);
+        assert_eq!(world.despawned_since(first), &[b]);
+
+        world.acknowledge_removals(second);
+        assert_eq!(world.removed_since::<Health>(0), &[b]);
+        let tick = world.increment_change_tick();
+        world.acknowledge_removals(tick);
+        assert_eq!(world.removed_since::<Health>(0), &[]);
+        assert_eq!(world.despawned_since(0), &[]);
+    }
+
+    #[test]
+    fn test_log_is_capped() {
+        let mut removals = Removals::default();
+        for i in 0..=MAX_LOGGED as u32 {
+            removals.despawn(
+                Eid::new(i, 0),
+                std::iter::once(TypeId::of::<Pos>()),
+                u64::from(i),
+            );
+        }
+        let despawned = removals.despawned(0);
+        assert_eq!(despawned.len(), MAX_LOGGED * 3 / 4 + 1);
+        assert_eq!(despawned[0], Eid::new(MAX_LOGGED as u32 / 4, 0));
+        assert_eq!(despawned.last(), Some(&Eid::new(MAX_LOGGED as u32, 0)));
+        assert_eq!(removals.removed(TypeId::of::<Pos>(), 0), despawned);
+    }
+
+    #[test]
+    fn test_removed_by_commands() {
+        struct Kill;
+        impl System for Kill {
+            type Data = (Eid, Health, Commands);
+            fn run(&mut self, (eid, health, commands): (Eid, &mut Health, &mut Commands)) {
+                if health.0 == 0 {
+                    commands.remove::<Health>(eid);
+                }
+            }
+        }
+
+        #[derive(Default)]
+        struct Dead(Vec<Eid>);
+        impl System for Dead {
+            type Data = (Pos, RemovedComponents<Health>);
+            fn run(&mut self, (_, removed): (&mut Pos, &[Eid])) {
+                self.0.extend_from_slice(removed);
+            }
+        }
+
+        let mut world = world();
+        let a = world.create_entity().with(Health(0)).with(Pos(0.0)).build();
+        world.create_entity().with(Health(1)).build();
+
+        let mut dead = Dead::default();
+        world.dispatch_system(&mut dead);
+        assert_eq!(dead.0, vec![]);
+        world.dispatch_system(&mut Kill);
+        world.dispatch_system(&mut dead);
+        assert_eq!(dead.0, vec![a]);
+        world.dispatch_system(&mut dead);
+        assert_eq!(dead.0, vec![a]);
+    }
+
+    #[test]
+    fn test_tombstones() {
+        let mut world = world();
+        let a = world.create_entity().with(Health(1)).with(Pos(0.0)).build();
+        let b = world.create_entity().with(Health(1)).build();
+        let c = world.create_entity().with(Health(1)).build();
+        let d = world.create_entity().with(Pos(0.0)).build();
+
+        let tick = world.increment_change_tick();
+        world.remove_component_from_entity::<Health>(&a).unwrap();
+        world.remove_component_from_entity::<Pos>(&a).unwrap();
+        world.remove_component_from_entity::<Health>(&b).unwrap();
+        world.add_component_to_entity(&b, Health(2)).unwrap();
+        world.destroy_entity(&c).unwrap();
+        world.destroy_entity(&d).unwrap();
+
+        let tombstones = world.tombstones_since(tick);
+        assert_eq!(tombstones.despawned().collect::<Vec<_>>(), vec![c, d]);
+        assert_eq!(
+            tombstones.removed().collect::<Vec<_>>(),
+            vec![(a, TypeId::of::<Health>())]
+        );
+        let tick = world.increment_change_tick();
+        assert!(world.tombstones_since(tick).is_empty());
+    }
+
+    #[test]
+    fn test_tombstones_round_trip() {
+        let mut server = world();
+        let a = server.create_entity().with(Health(1)).build();
+        let b = server.create_entity().with(Health(1)).build();
+        let mut client = world();
+        client.apply_snapshot(&server.snapshot(0));
+
+        let tick = server.increment_change_tick();
+        server.remove_component_from_entity::<Health>(&a).unwrap();
+        server.destroy_entity(&b).unwrap();
+        let tombstones = server.tombstones_since(tick);
+        let bytes = tombstones.to_bytes(server.registry()).unwrap();
+        assert_eq!(
+            Tombstones::from_bytes(client.registry(), &bytes).unwrap(),
+            tombstones
+        );
+        for len in 0..bytes.len() {
+            assert_eq!(
+                Tombstones::from_bytes(client.registry(), &bytes[..len]).unwrap_err(),
+                DecodeError::UnexpectedEnd
+            );
+        }
+
+        let tick = client.increment_change_tick();
+        client.apply_tombstones(&tombstones);
+        assert!(client.is_alive(&a));
+        assert!(client.get_component_for_entity::<Health>(&a).is_err());
+        assert!(!client.is_alive(&b));
+        assert_eq!(client.tombstones_since(tick), tombstones);
+    }
+
+    #[test]
+    fn test_applied_snapshots_are_logged() {
+        let mut server = world();
+        let a = server.create_entity().with(Health(1)).build();
+        let b = server.create_entity().with(Health(1)).build();
+        let mut client = world();
+        client.apply_snapshot(&server.snapshot(0));
+
+        server.remove_component_from_entity::<Health>(&a).unwrap();
+        server.destroy_entity(&b).unwrap();
+        let tick = client.increment_change_tick();
+        client.apply_snapshot(&server.snapshot(1));
+        assert_eq!(client.removed_since::<Health>(tick), &[b, a]);
+        assert_eq!(client.despawned_since(tick), &[b]);
+    }
+}
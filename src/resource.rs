@@ -0,0 +1,196 @@
+use crate::component::AnyValue;
+use crate::{Access, Fetch, SystemData};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+/// Trait for singleton values stored in the `World`, like the delta time, a random
+/// number generator or the map. Every `'static` type which is `Send` and `Sync` is a
+/// `Resource`.
+pub trait Resource: Any + Send + Sync {}
+
+impl<T: Any + Send + Sync> Resource for T {}
+
+/// Storage for the resources of a `World`, keyed by type.
+pub(crate) type Resources = HashMap<TypeId, Box<AnyValue>>;
+
+/// Borrows a resource R of the `World` immutably. Entities are only visited if the
+/// `World` has a resource R.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Component, Res, System, World};
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Pos(f64);
+/// impl Component for Pos {}
+///
+/// struct DeltaTime(f64);
+///
+/// struct Fall;
+/// impl System for Fall {
+///     type Data = (Pos, Res<DeltaTime>);
+///     fn run(&mut self, (pos, dt): (&mut Pos, &DeltaTime)) {
+///         pos.0 -= 10.0 * dt.0;
+///     }
+/// }
+///
+/// let mut world = World::default();
+/// world.insert_resource(DeltaTime(0.5));
+/// let e = world.create_entity().with(Pos(10.0)).build();
+/// world.dispatch_system(&mut Fall);
+///
+/// assert_eq!(world.get_component_for_entity::<Pos>(&e), Ok(&Pos(5.0)));
+/// ```
+#[derive(Debug)]
+pub struct Res<R>(PhantomData<R>);
+
+impl<R> SystemData for Res<R>
+where
+    R: Resource,
+{
+    type Item<'a> = &'a R;
+
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+        fetch.resource::<R>()
+    }
+
+    fn access(access: &mut Access) {
+        access.add_resource_read::<R>();
+    }
+}
+
+/// Borrows a resource R of the `World` mutably. Entities are only visited if the
+/// `World` has a resource R.
+#[derive(Debug)]
+pub struct ResMut<R>(PhantomData<R>);
+
+impl<R> SystemData for ResMut<R>
+where
+    R: Resource,
+{
+    type Item<'a> = &'a mut R;
+
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+        fetch.resource_mut::<R>()
+    }
+
+    fn access(access: &mut Access) {
+        access.add_resource_write::<R>();
+    }
+}
+
+#[cfg(test)]
+mod test_resource {
+
+    use crate::{
+        Access, Commands, Component, EcsError, Eid, ParSystem, Read, Res, ResMut, System, World,
+    };
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Score(u32);
+
+    impl Component for Score {}
+
+    #[derive(Debug, PartialEq)]
+    struct Total(u32);
+
+    #[test]
+    fn test_world_resources() {
+        let mut world = World::default();
+        assert!(matches!(
+            world.resource::<Total>(),
+            Err(EcsError::MissingResource(_))
+        ));
+        assert_eq!(world.insert_resource(Total(1)), None);
+        assert_eq!(world.insert_resource(Total(2)), Some(Total(1)));
+
+        world.resource_mut::<Total>().unwrap().0 += 3;
+        assert_eq!(world.resource::<Total>(), Ok(&Total(5)));
+        assert_eq!(world.remove_resource::<Total>(), Ok(Total(5)));
+        assert!(world.resource_mut::<Total>().is_err());
+    }
+
+    #[test]
+    fn test_resources_in_systems() {
+        struct Sum;
+        impl System for Sum {
+            type Data = (Read<Score>, ResMut<Total>);
+            fn run(&mut self, (score, total): (&Score, &mut Total)) {
+                total.0 += score.0;
+            }
+        }
+
+        struct Aliased(usize);
+        impl System for Aliased {
+            type Data = (Res<Total>, ResMut<Total>);
+            fn run(&mut self, _: (&Total, &mut Total)) {
+                self.0 += 1;
+            }
+        }
+
+        let mut world = World::default();
+        world.create_entity().with(Score(3)).build();
+        world.create_entity().with(Score(4)).build();
+
+        // Without the resource no entity matches.
+        world.dispatch_system(&mut Sum);
+        world.insert_resource(Total(0));
+        world.dispatch_system(&mut Sum);
+        assert_eq!(world.resource::<Total>(), Ok(&Total(7)));
+
+        let mut aliased = Aliased(0);
+        world.dispatch_system(&mut aliased);
+        assert_eq!(aliased.0, 0);
+
+        assert!(Access::of::<Res<Total>>().is_resource_read::<Total>());
+        assert!(Access::of::<ResMut<Total>>().conflicts_with(&Access::of::<Res<Total>>()));
+        assert!(!Access::of::<Res<Total>>().conflicts_with(&Access::of::<Res<Total>>()));
+        assert!(!Access::of::<ResMut<Total>>().conflicts_with(&Access::of::<Score>()));
+    }
+
+    #[test]
+    fn test_resource_only_systems_run_once() {
+        struct Count;
+        impl System for Count {
+            type Data = ResMut<Total>;
+            fn run(&mut self, total: &mut Total) {
+                total.0 += 1;
+            }
+        }
+
+        struct Spawn;
+        impl System for Spawn {
+            type Data = (Res<Total>, Commands);
+            fn run(&mut self, (total, commands): (&Total, &mut Commands)) {
+                commands.spawn().with(Score(total.0));
+            }
+        }
+
+        struct ParCount(AtomicU32);
+        impl ParSystem for ParCount {
+            type Data = Res<Total>;
+            fn run(&self, _: &Total) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let mut world = World::default();
+        world.insert_resource(Total(0));
+        world.dispatch_system(&mut Count);
+        assert_eq!(world.resource::<Total>(), Ok(&Total(1)));
+
+        world.dispatch_system(&mut Spawn);
+        world.dispatch_system(&mut Spawn);
+        assert!(world.is_alive(&Eid::new(1, 0)));
+        assert!(!world.is_alive(&Eid::new(2, 0)));
+        world.dispatch_system(&mut Count);
+        assert_eq!(world.resource::<Total>(), Ok(&Total(2)));
+
+        let count = ParCount(AtomicU32::new(0));
+        world.par_dispatch_system_in_chunks(&count, 4);
+        assert_eq!(count.0.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,801 @@
+use crate::system::{Ran, View};
+use crate::{Access, Commands, System, World};
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::thread;
+
+/// The stages of a tick of a `Schedule`. Every system of a stage runs before any system
+/// of a later stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Stage {
+    /// Runs first, e.g. to read input or apply received snapshots.
+    PreUpdate,
+    /// Runs the gameplay systems. Systems are added to this stage by default.
+    Update,
+    /// Runs after the gameplay systems, e.g. to clean up dead entities.
+    PostUpdate,
+    /// Runs last, once the state of the tick is final, e.g. to take snapshots.
+    Snapshot,
+}
+
+/// Error returned when the systems of a `Schedule` can't be ordered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// Another system is already added with the label.
+    DuplicateLabel(&'static str),
+    /// An ordering constraint refers to a label no system was added with.
+    UnknownLabel(&'static str),
+    /// The ordering constraints contradict each other. Contains the labels of the
+    /// systems which would each have to run before the next one, and the last one
+    /// before the first one.
+    Cycle(Vec<&'static str>),
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScheduleError::DuplicateLabel(label) => {
+                write!(f, "a system is already labeled {:?}", label)
+            }
+            ScheduleError::UnknownLabel(label) => write!(f, "no system is labeled {:?}", label),
+            ScheduleError::Cycle(labels) => {
+                write!(f, "systems can't be ordered: {}", labels.join(" -> "))?;
+                match labels.first() {
+                    Some(first) => write!(f, " -> {}", first),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl Error for ScheduleError {}
+
+/// Object safe part of `System` so that systems of different types can be stored
+/// together.
+trait Runnable: Send {
+    fn dispatch(&mut self, label: &'static str, world: &mut World);
+    fn dispatch_view(&mut self, view: View<'_>, commands: &mut Commands, since: u64) -> Ran;
+}
+
+impl<S: System + Send> Runnable for S {
+    fn dispatch(&mut self, label: &'static str, world: &mut World) {
+        world.dispatch_labeled_system(label, self);
+    }
+
+    fn dispatch_view(&mut self, view: View<'_>, commands: &mut Commands, since: u64) -> Ran {
+        view.run::<S::Data, _>(commands, since, |_, data| self.run(data))
+    }
+}
+
+/// A system added to a `Schedule` and where it runs.
+struct Entry {
+    label: &'static str,
+    stage: Stage,
+    access: Access,
+    before: Vec<&'static str>,
+    after: Vec<&'static str>,
+    system: Box<dyn Runnable>,
+}
+
+/// Owns the systems of a game and runs them in order.
+///
+/// Systems run stage by stage. Within a stage, systems run in the order they were
+/// added unless `before` and `after` constraints say otherwise. `Schedule::run_parallel`
+/// runs systems which don't conflict at the same time on their own threads.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Component, Read, Schedule, Stage, System, World, Write};
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Pos(f64);
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Vel(f64);
+/// impl Component for Pos {}
+/// impl Component for Vel {}
+///
+/// struct Accelerate;
+/// impl System for Accelerate {
+///     type Data = Write<Vel>;
+///     fn run(&mut self, vel: &mut Vel) {
+///         vel.0 += 1.0;
+///     }
+/// }
+///
+/// struct Move;
+/// impl System for Move {
+///     type Data = (Write<Pos>, Read<Vel>);
+///     fn run(&mut self, (pos, vel): (&mut Pos, &Vel)) {
+///         pos.0 += vel.0;
+///     }
+/// }
+///
+/// let mut schedule = Schedule::default();
+/// schedule.add_system("move", Move).build().unwrap();
+/// schedule
+///     .add_system("accelerate", Accelerate)
+///     .before("move")
+///     .build()
+///     .unwrap();
+///
+/// let mut world = World::default();
+/// let e = world.create_entity().with(Pos(0.0)).with(Vel(0.0)).build();
+/// schedule.run(&mut world).unwrap();
+/// schedule.run(&mut world).unwrap();
+///
+/// assert_eq!(schedule.order().unwrap(), vec!["accelerate", "move"]);
+/// assert_eq!(world.get_component_for_entity::<Pos>(&e), Ok(&Pos(3.0)));
+/// ```
+#[derive(Default)]
+pub struct Schedule {
+    entries: Vec<Entry>,
+    order: Option<Vec<usize>>,
+}
+
+impl fmt::Debug for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.entries.iter().map(|e| (e.label, e.stage)))
+            .finish()
+    }
+}
+
+impl Schedule {
+    /// Creates a `SystemBuilder` to add a system with a unique label to the schedule.
+    pub fn add_system<S: System + Send + 'static>(
+        &mut self,
+        label: &'static str,
+        system: S,
+    ) -> SystemBuilder<'_> {
+        SystemBuilder {
+            schedule: self,
+            entry: Entry {
+                label,
+                stage: Stage::Update,
+                access: Access::of::<S::Data>(),
+                before: Vec::new(),
+                after: Vec::new(),
+                system: Box::new(system),
+            },
+        }
+    }
+
+    /// Returns the number of systems in the schedule.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the schedule doesn't have any systems.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the labels of the systems in the order they run.
+    pub fn order(&mut self) -> Result<Vec<&'static str>, ScheduleError> {
+        let order = self.sorted()?;
+        Ok(order.into_iter().map(|i| self.entries[i].label).collect())
+    }
+
+    /// Runs every system of the schedule once on the `World`.
+    pub fn run(&mut self, world: &mut World) -> Result<(), ScheduleError> {
+        for i in self.sorted()? {
+            let entry = &mut self.entries[i];
+            entry.system.dispatch(entry.label, world);
+        }
+        Ok(())
+    }
+
+    /// Returns the labels of the systems which `Schedule::run_parallel` runs at the same
+    /// time, batch by batch.
+    pub fn batches(&mut self) -> Result<Vec<Vec<&'static str>>, ScheduleError> {
+        let batches = self.batched()?;
+        Ok(batches
+            .into_iter()
+            .map(|batch| batch.into_iter().map(|i| self.entries[i].label).collect())
+            .collect())
+    }
+
+    /// Runs every system of the schedule once on the `World`, running the systems of a
+    /// batch at the same time, each on its own thread. The systems of a batch never
+    /// conflict, so the `World` ends up the same as after `Schedule::run`.
+    ///
+    /// # Example
+    /// ```
+    /// extern crate ecsnap;
+    /// use ecsnap::{Component, Read, Schedule, System, World, Write};
+    ///
+    /// #[derive(Debug, Clone, Copy, PartialEq)]
+    /// struct Pos(f64);
+    /// #[derive(Debug, Clone, Copy, PartialEq)]
+    /// struct Vel(f64);
+    /// #[derive(Debug, Clone, Copy, PartialEq)]
+    /// struct Age(u32);
+    /// impl Component for Pos {}
+    /// impl Component for Vel {}
+    /// impl Component for Age {}
+    ///
+    /// struct Move;
+    /// impl System for Move {
+    ///     type Data = (Write<Pos>, Read<Vel>);
+    ///     fn run(&mut self, (pos, vel): (&mut Pos, &Vel)) {
+    ///         pos.0 += vel.0;
+    ///     }
+    /// }
+    ///
+    /// struct Grow;
+    /// impl System for Grow {
+    ///     type Data = Write<Age>;
+    ///     fn run(&mut self, age: &mut Age) {
+    ///         age.0 += 1;
+    ///     }
+    /// }
+    ///
+    /// let mut schedule = Schedule::default();
+    /// schedule.add_system("move", Move).build().unwrap();
+    /// schedule.add_system("grow", Grow).build().unwrap();
+    /// assert_eq!(schedule.batches().unwrap(), vec![vec!["move", "grow"]]);
+    ///
+    /// let mut world = World::default();
+    /// let e = world.create_entity().with(Pos(0.0)).with(Vel(2.0)).with(Age(0)).build();
+    /// schedule.run_parallel(&mut world).unwrap();
+    ///
+    /// assert_eq!(world.get_component_for_entity::<Pos>(&e), Ok(&Pos(2.0)));
+    /// assert_eq!(world.get_component_for_entity::<Age>(&e), Ok(&Age(1)));
+    /// ```
+    pub fn run_parallel(&mut self, world: &mut World) -> Result<(), ScheduleError> {
+        for batch in self.batched()? {
+            if let [i] = batch[..] {
+                let entry = &mut self.entries[i];
+                entry.system.dispatch(entry.label, world);
+                continue;
+            }
+            let accesses: Vec<Access> = batch
+                .iter()
+                .map(|i| self.entries[*i].access.clone())
+                .collect();
+            let runs: Vec<(&'static str, u64, u64)> = batch
+                .iter()
+                .map(|i| {
+                    let label = self.entries[*i].label;
+                    let (since, tick) = world.start_run(label);
+                    (label, since, tick)
+                })
+                .collect();
+            let mut systems: Vec<Option<&mut Box<dyn Runnable>>> = self
+                .entries
+                .iter_mut()
+                .map(|e| Some(&mut e.system))
+                .collect();
+            let systems = batch.iter().filter_map(|i| systems[*i].take());
+            let mut commands: Vec<Commands> = batch.iter().map(|_| Commands::default()).collect();
+            let views = world.views(&accesses);
+            let ran: Vec<Ran> = thread::scope(|scope| {
+                let threads: Vec<_> = systems
+                    .zip(views)
+                    .zip(commands.iter_mut())
+                    .zip(runs.iter())
+                    .map(|(((system, view), commands), (_, since, _))| {
+                        scope.spawn(move || system.dispatch_view(view, commands, *since))
+                    })
+                    .collect();
+                threads
+                    .into_iter()
+                    .map(|thread| thread.join().expect("system panicked"))
+                    .collect()
+            });
+            for ((label, _, tick), ran) in runs.into_iter().zip(ran) {
+                world.finish_run(label, tick, ran);
+            }
+            world.increment_change_tick();
+            for commands in commands {
+                world.apply_commands(commands);
+            }
+        }
+        Ok(())
+    }
+
+    /// Splits the systems, in the order they run, into batches of systems of the same
+    /// stage which don't conflict with each other. A system only joins the last batch, so
+    /// systems which conflict still run in order.
+    fn batched(&mut self) -> Result<Vec<Vec<usize>>, ScheduleError> {
+        let mut batches: Vec<Vec<usize>> = Vec::new();
+        for i in self.sorted()? {
+            let entry = &self.entries[i];
+            let joins = |batch: &Vec<usize>| {
+                batch.iter().all(|j| {
+                    let other = &self.entries[*j];
+                    other.stage == entry.stage && !other.access.conflicts_with(&entry.access)
+                })
+            };
+            match batches.last_mut() {
+                Some(batch) if joins(batch) => batch.push(i),
+                _ => batches.push(vec![i]),
+            }
+        }
+        Ok(batches)
+    }
+
+    /// Returns the indices of the systems in the order they run. The order is only
+    /// worked out again after systems were added.
+    fn sorted(&mut self) -> Result<Vec<usize>, ScheduleError> {
+        match &self.order {
+            Some(order) => Ok(order.clone()),
+            None => {
+                let order = self.topological_order()?;
+                self.order = Some(order.clone());
+                Ok(order)
+            }
+        }
+    }
+
+    fn topological_order(&self) -> Result<Vec<usize>, ScheduleError> {
+        let index: HashMap<&'static str, usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.label, i))
+            .collect();
+        let lookup = |label: &'static str| {
+            index
+                .get(label)
+                .cloned()
+                .ok_or(ScheduleError::UnknownLabel(label))
+        };
+
+        // Edges between systems of the same stage. Constraints across stages only need
+        // to agree with the order of the stages.
+        let mut successors = vec![Vec::new(); self.entries.len()];
+        let mut predecessors = vec![Vec::new(); self.entries.len()];
+        for (i, entry) in self.entries.iter().enumerate() {
+            let edges = entry
+                .before
+                .iter()
+                .map(|label| lookup(label).map(|j| (i, j)))
+                .chain(
+                    entry
+                        .after
+                        .iter()
+                        .map(|label| lookup(label).map(|j| (j, i))),
+                );
+            for edge in edges {
+                let (from, to) = edge?;
+                let (from_stage, to_stage) = (self.entries[from].stage, self.entries[to].stage);
+                if from == to || from_stage > to_stage {
+                    let mut cycle = vec![self.entries[from].label];
+                    if from != to {
+                        cycle.push(self.entries[to].label);
+                    }
+                    return Err(ScheduleError::Cycle(cycle));
+                }
+                if from_stage == to_stage {
+                    successors[from].push(to);
+                    predecessors[to].push(from);
+                }
+            }
+        }
+
+        // Kahn's algorithm, always picking the earliest stage and then the system added
+        // first so the order is deterministic.
+        let mut blocked: Vec<usize> = predecessors.iter().map(Vec::len).collect();
+        let mut ready: BTreeSet<(Stage, usize)> = (0..self.entries.len())
+            .filter(|i| blocked[*i] == 0)
+            .map(|i| (self.entries[i].stage, i))
+            .collect();
+        let mut order = Vec::with_capacity(self.entries.len());
+        while let Some(next) = ready.iter().next().cloned() {
+            ready.remove(&next);
+            let (_, i) = next;
+            order.push(i);
+            for j in successors[i].iter() {
+                blocked[*j] -= 1;
+                if blocked[*j] == 0 {
+                    ready.insert((self.entries[*j].stage, *j));
+                }
+            }
+        }
+        if order.len() == self.entries.len() {
+            return Ok(order);
+        }
+
+        // Every system left over waits on another one which is left over, so walking
+        // backwards from any of them ends up going around a cycle.
+        let mut path = Vec::new();
+        let mut i = (0..self.entries.len())
+            .find(|i| blocked[*i] > 0)
+            .unwrap_or(0);
+        while !path.contains(&i) {
+            path.push(i);
+            i = predecessors[i]
+                .iter()
+                .cloned()
+                .find(|j| blocked[*j] > 0)
+                .unwrap_or(i);
+        }
+        let start = path.iter().position(|j| *j == i).unwrap_or(0);
+        Err(ScheduleError::Cycle(
+            path[start..]
+                .iter()
+                .rev()
+                .map(|i| self.entries[*i].label)
+                .collect(),
+        ))
+    }
+}
+
+/// A helper struct to add a system to a `Schedule`.
+pub struct SystemBuilder<'a> {
+    schedule: &'a mut Schedule,
+    entry: Entry,
+}
+
+impl<'a> fmt::Debug for SystemBuilder<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SystemBuilder")
+            .field("label", &self.entry.label)
+            .field("stage", &self.entry.stage)
+            .field("before", &self.entry.before)
+            .field("after", &self.entry.after)
+            .finish()
+    }
+}
+
+impl<'a> SystemBuilder<'a> {
+    /// Runs the system in a stage other than `Stage::Update`.
+    pub fn in_stage(mut self, stage: Stage) -> Self {
+        self.entry.stage = stage;
+        self
+    }
+
+    /// Runs the system before the system with the label.
+    pub fn before(mut self, label: &'static str) -> Self {
+        self.entry.before.push(label);
+        self
+    }
+
+    /// Runs the system after the system with the label.
+    pub fn after(mut self, label: &'static str) -> Self {
+        self.entry.after.push(label);
+        self
+    }
+
+    /// Finishes adding the system. Constraints are checked when the `Schedule` is
+    /// ordered, since they may refer to systems which are added later.
+    pub fn build(self) -> Result<(), ScheduleError> {
+        let (schedule, entry) = (self.schedule, self.entry);
+        if schedule.entries.iter().any(|e| e.label == entry.label) {
+            return Err(ScheduleError::DuplicateLabel(entry.label));
+        }
+        schedule.entries.push(entry);
+        schedule.order = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_schedule {
+
+    use crate::{ResMut, Schedule, ScheduleError, Stage, System, World};
+
+    #[derive(Debug, Default)]
+    struct Log(Vec<&'static str>);
+
+    struct Tag(&'static str);
+
+    impl System for Tag {
+        type Data = ResMut<Log>;
+
+        fn run(&mut self, log: &mut Log) {
+            log.0.push(self.0);
+        }
+    }
+
+    fn world() -> World {
+        let mut world = World::default();
+        world.insert_resource(Log::default());
+        world
+    }
+
+    #[test]
+    fn test_stages_and_insertion_order() {
+        let mut schedule = Schedule::default();
+        schedule
+            .add_system("snapshot", Tag("snapshot"))
+            .in_stage(Stage::Snapshot)
+            .build()
+            .unwrap();
+        schedule.add_system("a", Tag("a")).build().unwrap();
+        schedule
+            .add_system("cleanup", Tag("cleanup"))
+            .in_stage(Stage::PostUpdate)
+            .build()
+            .unwrap();
+        schedule.add_system("b", Tag("b")).build().unwrap();
+        schedule
+            .add_system("input", Tag("input"))
+            .in_stage(Stage::PreUpdate)
+            .build()
+            .unwrap();
+
+        let mut world = world();
+        schedule.run(&mut world).unwrap();
+        assert_eq!(
+            world.resource::<Log>().unwrap().0,
+            vec!["input", "a", "b", "cleanup", "snapshot"]
+        );
+        assert_eq!(schedule.len(), 5);
+    }
+
+    #[test]
+    fn test_before_and_after() {
+        let mut schedule = Schedule::default();
+        schedule
+            .add_system("a", Tag("a"))
+            .after("c")
+            .build()
+            .unwrap();
+        schedule.add_system("b", Tag("b")).build().unwrap();
+        schedule
+            .add_system("c", Tag("c"))
+            .after("b")
+            .build()
+            .unwrap();
+        schedule
+            .add_system("d", Tag("d"))
+            .before("b")
+            .build()
+            .unwrap();
+        assert_eq!(schedule.order(), Ok(vec!["d", "b", "c", "a"]));
+
+        // Constraints across stages only have to agree with the stages.
+        schedule
+            .add_system("e", Tag("e"))
+            .in_stage(Stage::PreUpdate)
+            .before("a")
+            .build()
+            .unwrap();
+        assert_eq!(schedule.order(), Ok(vec!["e", "d", "b", "c", "a"]));
+    }
+
+    #[test]
+    fn test_ordering_errors() {
+        let mut schedule = Schedule::default();
+        schedule.add_system("a", Tag("a")).build().unwrap();
+        assert_eq!(
+            schedule.add_system("a", Tag("a")).build(),
+            Err(ScheduleError::DuplicateLabel("a"))
+        );
+
+        schedule
+            .add_system("b", Tag("b"))
+            .after("missing")
+            .build()
+            .unwrap();
+        let mut world = world();
+        assert_eq!(
+            schedule.run(&mut world),
+            Err(ScheduleError::UnknownLabel("missing"))
+        );
+        assert!(world.resource::<Log>().unwrap().0.is_empty());
+
+        let mut schedule = Schedule::default();
+        schedule
+            .add_system("cleanup", Tag("cleanup"))
+            .in_stage(Stage::PostUpdate)
+            .before("a")
+            .build()
+            .unwrap();
+        schedule.add_system("a", Tag("a")).build().unwrap();
+        assert_eq!(
+            schedule.order(),
+            Err(ScheduleError::Cycle(vec!["cleanup", "a"]))
+        );
+    }
+
+    #[test]
+    fn test_cycle_detection() {
+        let mut schedule = Schedule::default();
+        schedule.add_system("x", Tag("x")).build().unwrap();
+        schedule
+            .add_system("a", Tag("a"))
+            .after("c")
+            .build()
+            .unwrap();
+        schedule
+            .add_system("b", Tag("b"))
+            .after("a")
+            .build()
+            .unwrap();
+        schedule
+            .add_system("c", Tag("c"))
+            .after("b")
+            .build()
+            .unwrap();
+        schedule
+            .add_system("d", Tag("d"))
+            .after("c")
+            .build()
+            .unwrap();
+
+        let error = schedule.order().unwrap_err();
+        match &error {
+            ScheduleError::Cycle(labels) => {
+                let mut sorted = labels.clone();
+                sorted.sort();
+                assert_eq!(sorted, vec!["a", "b", "c"]);
+            }
+            other => panic!("expected a cycle, got {:?}", other),
+        }
+        assert!(error.to_string().starts_with("systems can't be ordered"));
+    }
+}
+
+#[cfg(test)]
+mod test_parallel {
+
+    use crate::{
+        Backend, Commands, Component, Eid, Read, ResMut, Schedule, Stage, System, With, World,
+        Write,
+    };
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos(i64);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Vel(i64);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Age(u32);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Spawned;
+
+    impl Component for Pos {}
+    impl Component for Vel {}
+    impl Component for Age {}
+    impl Component for Spawned {}
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Total(i64);
+
+    struct Move;
+    impl System for Move {
+        type Data = (Write<Pos>, Read<Vel>);
+        fn run(&mut self, (pos, vel): (&mut Pos, &Vel)) {
+            pos.0 += vel.0;
+        }
+    }
+
+    struct Grow;
+    impl System for Grow {
+        type Data = (Write<Age>, With<Pos>);
+        fn run(&mut self, (age, _): (&mut Age, ())) {
+            age.0 += 1;
+        }
+    }
+
+    struct Sum;
+    impl System for Sum {
+        type Data = (Read<Vel>, ResMut<Total>);
+        fn run(&mut self, (vel, total): (&Vel, &mut Total)) {
+            total.0 += vel.0;
+        }
+    }
+
+    struct Accelerate;
+    impl System for Accelerate {
+        type Data = Write<Vel>;
+        fn run(&mut self, vel: &mut Vel) {
+            vel.0 *= 2;
+        }
+    }
+
+    struct Spawn;
+    impl System for Spawn {
+        type Data = (Eid, Read<Age>, Commands);
+        fn run(&mut self, (eid, age, commands): (Eid, &Age, &mut Commands)) {
+            if age.0 % 3 == 0 {
+                commands.insert(eid, Spawned);
+                commands.spawn().with(Age(1));
+            }
+        }
+    }
+
+    fn schedule() -> Schedule {
+        let mut schedule = Schedule::default();
+        schedule.add_system("move", Move).build().unwrap();
+        schedule.add_system("grow", Grow).build().unwrap();
+        schedule.add_system("sum", Sum).build().unwrap();
+        schedule
+            .add_system("accelerate", Accelerate)
+            .build()
+            .unwrap();
+        schedule.add_system("spawn", Spawn).build().unwrap();
+        schedule
+            .add_system("late_move", Move)
+            .in_stage(Stage::PostUpdate)
+            .build()
+            .unwrap();
+        schedule
+    }
+
+    fn world(backend: Backend) -> World {
+        let mut world = World::with_backend(backend);
+        world.insert_resource(Total::default());
+        for i in 0..200 {
+            let mut builder = world.create_entity().with(Age(i));
+            if i % 2 == 0 {
+                builder = builder.with(Pos(i as i64));
+            }
+            if i % 3 == 0 {
+                builder = builder.with(Vel(1 + i as i64));
+            }
+            builder.build();
+        }
+        world
+    }
+
+    /// The components of an entity, and whether it is `Spawned`.
+    type State = (Option<Pos>, Option<Vel>, Option<Age>, bool);
+
+    fn state(world: &World) -> Vec<State> {
+        (0..400)
+            .map(|i| Eid::new(i, 0))
+            .filter(|eid| world.is_alive(eid))
+            .map(|eid| {
+                (
+                    world.get_component_for_entity::<Pos>(&eid).ok().cloned(),
+                    world.get_component_for_entity::<Vel>(&eid).ok().cloned(),
+                    world.get_component_for_entity::<Age>(&eid).ok().cloned(),
+                    world.get_component_for_entity::<Spawned>(&eid).is_ok(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_batches() {
+        let mut schedule = schedule();
+        assert_eq!(
+            schedule.batches(),
+            Ok(vec![
+                vec!["move", "grow", "sum"],
+                vec!["accelerate"],
+                vec!["spawn"],
+                vec!["late_move"],
+            ])
+        );
+    }
+
+    #[test]
+    fn test_writers_never_share_a_batch() {
+        let mut schedule = Schedule::default();
+        schedule.add_system("a", Accelerate).build().unwrap();
+        schedule.add_system("b", Accelerate).build().unwrap();
+        schedule.add_system("c", Grow).build().unwrap();
+        schedule.add_system("d", Move).build().unwrap();
+        assert_eq!(
+            schedule.batches(),
+            Ok(vec![vec!["a"], vec!["b", "c"], vec!["d"]])
+        );
+    }
+
+    #[test]
+    fn test_parallel_matches_sequential() {
+        for backend in [Backend::SparseSet, Backend::Archetype] {
+            let mut sequential = world(backend);
+            let mut parallel = world(backend);
+            let mut schedule = schedule();
+            for _ in 0..3 {
+                schedule.run(&mut sequential).unwrap();
+                schedule.run_parallel(&mut parallel).unwrap();
+            }
+
+            assert_eq!(state(&parallel), state(&sequential));
+            assert_eq!(parallel.resource::<Total>(), sequential.resource::<Total>());
+            assert!(sequential.resource::<Total>().unwrap().0 > 0);
+        }
+    }
+}
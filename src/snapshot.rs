@@ -0,0 +1,571 @@
+use crate::component::AnyComponent;
+use crate::{
+    BitReader, BitWriter, Component, ComponentId, ComponentInfo, ComponentRegistry, DecodeError,
+    Eid, Encode, EncodeError,
+};
+use std::any::TypeId;
+use std::collections::{BTreeMap, HashMap};
+
+/// The replicated components of a single `Entity` at the time a `Snapshot` was taken.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotEntity {
+    pub(crate) components: HashMap<TypeId, Box<dyn AnyComponent>>,
+}
+
+impl SnapshotEntity {
+    /// Gets a reference to a component C of the snapshotted `Entity` if it had such a
+    /// component, otherwise None is returned.
+    pub fn get_component<C: Component>(&self) -> Option<&C> {
+        self.components
+            .get(&TypeId::of::<C>())
+            .and_then(|c| (**c).as_any().downcast_ref::<C>())
+    }
+
+    /// Returns true if the snapshotted `Entity` had a component of type C.
+    pub fn has_component<C: Component>(&self) -> bool {
+        self.components.contains_key(&TypeId::of::<C>())
+    }
+
+    /// Returns the number of replicated components stored for the `Entity`.
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    /// Returns true if no replicated components are stored for the `Entity`.
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    /// Returns the registered info of every component, sorted by `ComponentId`.
+    fn sorted_components<'a>(
+        &'a self,
+        registry: &'a ComponentRegistry,
+    ) -> Result<Vec<(&'a ComponentInfo, &'a dyn AnyComponent)>, EncodeError> {
+        let mut components = Vec::with_capacity(self.components.len());
+        for (type_id, component) in self.components.iter() {
+            let info = registry
+                .get_by_type(*type_id)
+                .ok_or(EncodeError::UnregisteredComponent)?;
+            components.push((info, &**component));
+        }
+        components.sort_by_key(|(info, _)| info.id());
+        Ok(components)
+    }
+
+    pub(crate) fn encode(
+        &self,
+        registry: &ComponentRegistry,
+        writer: &mut BitWriter,
+    ) -> Result<(), EncodeError> {
+        let components = self.sorted_components(registry)?;
+        writer.write_varint(components.len() as u64);
+        for (info, component) in components {
+            let encode = info.encode.ok_or(EncodeError::NotEncodable(info.id()))?;
+            writer.write_varint(u64::from(info.id().0));
+            encode(component, writer);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn decode(
+        registry: &ComponentRegistry,
+        reader: &mut BitReader,
+    ) -> Result<Self, DecodeError> {
+        let mut entity = SnapshotEntity::default();
+        for _ in 0..reader.read_len()? {
+            let (type_id, component) = decode_component(registry, reader)?;
+            if entity.components.insert(type_id, component).is_some() {
+                return Err(DecodeError::InvalidValue);
+            }
+        }
+        Ok(entity)
+    }
+}
+
+/// Reads a component id and looks up the component it identifies.
+pub(crate) fn read_component_info<'a>(
+    registry: &'a ComponentRegistry,
+    reader: &mut BitReader,
+) -> Result<&'a ComponentInfo, DecodeError> {
+    let id = reader.read_varint()?;
+    if id > u64::from(u16::MAX) {
+        return Err(DecodeError::UnknownComponent);
+    }
+    registry
+        .get(ComponentId(id as u16))
+        .ok_or(DecodeError::UnknownComponent)
+}
+
+/// Reads a component id followed by the component it identifies.
+pub(crate) fn decode_component(
+    registry: &ComponentRegistry,
+    reader: &mut BitReader,
+) -> Result<(TypeId, Box<dyn AnyComponent>), DecodeError> {
+    let info = read_component_info(registry, reader)?;
+    let decode = info.decode.ok_or(DecodeError::UnknownComponent)?;
+    Ok((info.type_id(), decode(reader)?))
+}
+
+/// An owned copy of every `Entity` in a `World` and its replicated components at a
+/// given tick. A `Snapshot` doesn't borrow from the `World` it was taken from, so it
+/// can be stored, sent to another thread or applied to another `World`.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Component, World};
+///
+/// #[derive(Debug, Clone, Copy)]
+/// struct Pos {
+///     x: f64,
+///     y: f64,
+/// }
+/// impl Component for Pos {}
+///
+/// let mut world = World::default();
+/// world.register_component::<Pos>();
+/// let e = world.create_entity().with(Pos { x: 1.0, y: 2.0 }).build();
+///
+/// let snapshot = world.snapshot(7);
+/// assert_eq!(snapshot.tick(), 7);
+/// assert_eq!(snapshot.get_component::<Pos>(e).unwrap().x, 1.0);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    tick: u64,
+    pub(crate) entities: BTreeMap<Eid, SnapshotEntity>,
+}
+
+impl Snapshot {
+    /// Creates an empty `Snapshot` for a tick.
+    pub fn new(tick: u64) -> Self {
+        Snapshot {
+            tick,
+            entities: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the tick the `Snapshot` was taken at.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Returns the number of entities in the `Snapshot`.
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Returns true if the `Snapshot` doesn't contain any entities.
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Returns true if the `Snapshot` contains the entity.
+    pub fn contains(&self, entity: Eid) -> bool {
+        self.entities.contains_key(&entity)
+    }
+
+    /// Iterates over the Eids in the `Snapshot` in ascending order.
+    pub fn entities(&self) -> impl Iterator<Item = Eid> + '_ {
+        self.entities.keys().cloned()
+    }
+
+    /// Gets the snapshotted state of an entity if it is in the `Snapshot`.
+    pub fn get(&self, entity: Eid) -> Option<&SnapshotEntity> {
+        self.entities.get(&entity)
+    }
+
+    /// Gets a reference to a component C of an entity in the `Snapshot`. If the entity
+    /// isn't in the `Snapshot` or didn't have a replicated component C, None is
+    /// returned.
+    pub fn get_component<C: Component>(&self, entity: Eid) -> Option<&C> {
+        self.entities
+            .get(&entity)
+            .and_then(|e| e.get_component::<C>())
+    }
+
+    /// Writes the `Snapshot` using the ids and encodings of a `ComponentRegistry`. Fails
+    /// if the `Snapshot` contains a component which isn't registered with an encoding.
+    pub fn encode(
+        &self,
+        registry: &ComponentRegistry,
+        writer: &mut BitWriter,
+    ) -> Result<(), EncodeError> {
+        writer.write_varint(self.tick);
+        writer.write_varint(self.entities.len() as u64);
+        for (eid, entity) in self.entities.iter() {
+            eid.encode(writer);
+            entity.encode(registry, writer)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a `Snapshot` written by `Snapshot::encode`. The registry must have the same
+    /// components registered with the same ids as the one used for encoding.
+    pub fn decode(
+        registry: &ComponentRegistry,
+        reader: &mut BitReader,
+    ) -> Result<Self, DecodeError> {
+        let mut snapshot = Snapshot::new(reader.read_varint()?);
+        for _ in 0..reader.read_len()? {
+            let eid = Eid::decode(reader)?;
+            let entity = SnapshotEntity::decode(registry, reader)?;
+            if snapshot.entities.insert(eid, entity).is_some() {
+                return Err(DecodeError::InvalidValue);
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Encodes the `Snapshot` into a new buffer.
+    ///
+    /// # Example
+    /// ```
+    /// extern crate ecsnap;
+    /// use ecsnap::{BitReader, BitWriter, Component, DecodeError, Encode, Snapshot, World};
+    ///
+    /// #[derive(Debug, Clone, Copy, PartialEq)]
+    /// struct Pos {
+    ///     x: f32,
+    ///     y: f32,
+    /// }
+    /// impl Component for Pos {}
+    ///
+    /// impl Encode for Pos {
+    ///     fn encode(&self, writer: &mut BitWriter) {
+    ///         self.x.encode(writer);
+    ///         self.y.encode(writer);
+    ///     }
+    ///     fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+    ///         Ok(Pos {
+    ///             x: f32::decode(reader)?,
+    ///             y: f32::decode(reader)?,
+    ///         })
+    ///     }
+    /// }
+    ///
+    /// let mut world = World::default();
+    /// world.registry_mut().register::<Pos>().encoded().build().unwrap();
+    /// let e = world.create_entity().with(Pos { x: 1.0, y: 2.0 }).build();
+    ///
+    /// let bytes = world.snapshot(5).to_bytes(world.registry()).unwrap();
+    /// let snapshot = Snapshot::from_bytes(world.registry(), &bytes).unwrap();
+    /// assert_eq!(snapshot.tick(), 5);
+    /// assert_eq!(snapshot.get_component::<Pos>(e), Some(&Pos { x: 1.0, y: 2.0 }));
+    /// ```
+    pub fn to_bytes(&self, registry: &ComponentRegistry) -> Result<Vec<u8>, EncodeError> {
+        let mut writer = BitWriter::new();
+        self.encode(registry, &mut writer)?;
+        Ok(writer.into_bytes())
+    }
+
+    /// Decodes a `Snapshot` from the start of a buffer.
+    pub fn from_bytes(registry: &ComponentRegistry, bytes: &[u8]) -> Result<Self, DecodeError> {
+        Snapshot::decode(registry, &mut BitReader::new(bytes))
+    }
+}
+
+#[cfg(test)]
+mod test_snapshot {
+
+    use crate::{
+        Backend, BitReader, BitWriter, Component, ComponentId, ComponentRegistry, DecodeError,
+        Encode, EncodeError, Snapshot, World,
+    };
+    use std::any::TypeId;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos {
+        x: f64,
+        y: f64,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Vel {
+        x: f64,
+        y: f64,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Local {
+        id: u32,
+    }
+
+    impl Component for Pos {}
+    impl Component for Vel {}
+    impl Component for Local {}
+
+    impl Encode for Pos {
+        fn encode(&self, writer: &mut BitWriter) {
+            (self.x, self.y).encode(writer);
+        }
+        fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+            let (x, y) = Encode::decode(reader)?;
+            Ok(Pos { x, y })
+        }
+    }
+
+    impl Encode for Vel {
+        fn encode(&self, writer: &mut BitWriter) {
+            (self.x, self.y).encode(writer);
+        }
+        fn decode(reader: &mut BitReader) -> Result<Self, DecodeError> {
+            let (x, y) = Encode::decode(reader)?;
+            Ok(Vel { x, y })
+        }
+    }
+
+    fn networked() -> World {
+        networked_with(Backend::SparseSet)
+    }
+
+    fn networked_with(backend: Backend) -> World {
+        let mut world = World::with_backend(backend);
+        world
+            .registry_mut()
+            .register::<Pos>()
+            .encoded()
+            .build()
+            .unwrap();
+        world
+            .registry_mut()
+            .register::<Vel>()
+            .encoded()
+            .build()
+            .unwrap();
+        world
+    }
+
+    fn server() -> World {
+        let mut world = World::default();
+        world.register_component::<Pos>();
+        world.register_component::<Vel>();
+        world
+    }
+
+    #[test]
+    fn test_snapshot_only_contains_replicated_components() {
+        let mut world = server();
+        let e1 = world
+            .create_entity()
+            .with(Pos { x: 1.0, y: 2.0 })
+            .with(Vel { x: 3.0, y: 4.0 })
+            .with(Local { id: 9 })
+            .build();
+        let e2 = world.create_entity().with(Local { id: 3 }).build();
+
+        let snapshot = world.snapshot(3);
+        assert_eq!(snapshot.tick(), 3);
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot.entities().collect::<Vec<_>>(), vec![e1, e2]);
+        assert_eq!(
+            snapshot.get_component::<Pos>(e1),
+            Some(&Pos { x: 1.0, y: 2.0 })
+        );
+        assert_eq!(
+            snapshot.get_component::<Vel>(e1),
+            Some(&Vel { x: 3.0, y: 4.0 })
+        );
+        assert!(snapshot.get_component::<Local>(e1).is_none());
+        assert!(snapshot.get(e2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_is_independent_of_world() {
+        let mut world = server();
+        let e = world.create_entity().with(Pos { x: 1.0, y: 2.0 }).build();
+
+        let snapshot = world.snapshot(0);
+        world
+            .add_component_to_entity(&e, Pos { x: 5.0, y: 5.0 })
+            .unwrap();
+        world.destroy_entity(&e).unwrap();
+
+        assert_eq!(
+            snapshot.clone().get_component::<Pos>(e),
+            Some(&Pos { x: 1.0, y: 2.0 })
+        );
+    }
+
+    #[test]
+    fn test_apply_snapshot_rebuilds_world() {
+        let mut world = server();
+        let e1 = world
+            .create_entity()
+            .with(Pos { x: 1.0, y: 2.0 })
+            .with(Vel { x: 3.0, y: 4.0 })
+            .build();
+        let e2 = world.create_entity().with(Pos { x: 5.0, y: 6.0 }).build();
+
+        let mut client = server();
+        client.apply_snapshot(&world.snapshot(1));
+
+        assert_eq!(
+            client.get_component_for_entity::<Pos>(&e1),
+            Ok(&Pos { x: 1.0, y: 2.0 })
+        );
+        assert_eq!(
+            client.get_component_for_entity::<Vel>(&e1),
+            Ok(&Vel { x: 3.0, y: 4.0 })
+        );
+        assert_eq!(
+            client.get_component_for_entity::<Pos>(&e2),
+            Ok(&Pos { x: 5.0, y: 6.0 })
+        );
+
+        // Entities created locally mustn't collide with replicated ones.
+        let e3 = client.create_entity().build();
+        assert!(e3 != e1 && e3 != e2);
+    }
+
+    #[test]
+    fn test_apply_snapshot_overwrites_entities() {
+        let mut world = server();
+        let e1 = world
+            .create_entity()
+            .with(Pos { x: 1.0, y: 2.0 })
+            .with(Vel { x: 3.0, y: 4.0 })
+            .build();
+        let e2 = world.create_entity().with(Pos { x: 5.0, y: 6.0 }).build();
+
+        let mut client = server();
+        client.apply_snapshot(&world.snapshot(1));
+        client
+            .add_component_to_entity(&e1, Local { id: 1 })
+            .unwrap();
+
+        world
+            .add_component_to_entity(&e1, Pos { x: 0.0, y: 0.0 })
+            .unwrap();
+        world.remove_component_from_entity::<Vel>(&e1).unwrap();
+        world.destroy_entity(&e2).unwrap();
+        client.apply_snapshot(&world.snapshot(2));
+
+        assert_eq!(
+            client.get_component_for_entity::<Pos>(&e1),
+            Ok(&Pos { x: 0.0, y: 0.0 })
+        );
+        assert!(client.get_component_for_entity::<Vel>(&e1).is_err());
+        // Components which aren't replicated are left alone.
+        assert_eq!(
+            client.get_component_for_entity::<Local>(&e1),
+            Ok(&Local { id: 1 })
+        );
+        assert!(!client.snapshot(2).contains(e2));
+    }
+
+    #[test]
+    fn test_apply_snapshot_marks_only_differences() {
+        let mut server = networked();
+        let e1 = server
+            .create_entity()
+            .with(Pos { x: 1.0, y: 2.0 })
+            .with(Vel { x: 3.0, y: 4.0 })
+            .build();
+        let e2 = server.create_entity().with(Pos { x: 5.0, y: 6.0 }).build();
+        let before = server.snapshot(1);
+        server
+            .add_component_to_entity(&e1, Pos { x: 0.0, y: 0.0 })
+            .unwrap();
+        server
+            .add_component_to_entity(&e2, Vel { x: 1.0, y: 1.0 })
+            .unwrap();
+        let after = server.snapshot(2);
+
+        for backend in [Backend::SparseSet, Backend::Archetype] {
+            let mut client = networked_with(backend);
+            client.apply_snapshot(&before);
+            let tick = client.increment_change_tick();
+            client.apply_snapshot(&before);
+            assert_eq!(client.changes_since(tick).count(), 0);
+
+            client.apply_snapshot(&after);
+            let mut changes: Vec<_> = client.changes_since(tick).collect();
+            changes.sort();
+            assert_eq!(
+                changes,
+                vec![(e1, TypeId::of::<Pos>()), (e2, TypeId::of::<Vel>())]
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let mut world = networked();
+        let e1 = world
+            .create_entity()
+            .with(Pos { x: 1.0, y: 2.0 })
+            .with(Vel { x: 3.0, y: 4.0 })
+            .build();
+        let e2 = world.create_entity().with(Local { id: 2 }).build();
+        let e3 = world.create_entity().with(Vel { x: -1.0, y: 0.5 }).build();
+
+        let bytes = world.snapshot(300).to_bytes(world.registry()).unwrap();
+        let snapshot = Snapshot::from_bytes(world.registry(), &bytes).unwrap();
+
+        assert_eq!(snapshot.tick(), 300);
+        assert_eq!(snapshot.entities().collect::<Vec<_>>(), vec![e1, e2, e3]);
+        assert_eq!(
+            snapshot.get_component::<Pos>(e1),
+            Some(&Pos { x: 1.0, y: 2.0 })
+        );
+        assert_eq!(
+            snapshot.get_component::<Vel>(e1),
+            Some(&Vel { x: 3.0, y: 4.0 })
+        );
+        assert!(snapshot.get(e2).unwrap().is_empty());
+        assert_eq!(
+            snapshot.get_component::<Vel>(e3),
+            Some(&Vel { x: -1.0, y: 0.5 })
+        );
+        assert!(snapshot.get_component::<Pos>(e3).is_none());
+    }
+
+    #[test]
+    fn test_encode_requires_registered_encoding() {
+        let mut world = networked();
+        world.register_component::<Local>();
+        world.create_entity().with(Local { id: 2 }).build();
+
+        let snapshot = world.snapshot(0);
+        assert_eq!(
+            snapshot.to_bytes(world.registry()),
+            Err(EncodeError::NotEncodable(ComponentId(2)))
+        );
+        assert_eq!(
+            snapshot.to_bytes(&ComponentRegistry::default()),
+            Err(EncodeError::UnregisteredComponent)
+        );
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut world = networked();
+        world
+            .create_entity()
+            .with(Pos { x: 1.0, y: 2.0 })
+            .with(Vel { x: 3.0, y: 4.0 })
+            .build();
+        let bytes = world.snapshot(0).to_bytes(world.registry()).unwrap();
+
+        for len in 0..bytes.len() {
+            assert_eq!(
+                Snapshot::from_bytes(world.registry(), &bytes[..len]).unwrap_err(),
+                DecodeError::UnexpectedEnd
+            );
+        }
+
+        // A client which doesn't know about Vel can't read the snapshot.
+        let mut client = World::default();
+        client
+            .registry_mut()
+            .register::<Pos>()
+            .encoded()
+            .build()
+            .unwrap();
+        assert_eq!(
+            Snapshot::from_bytes(client.registry(), &bytes).unwrap_err(),
+            DecodeError::UnknownComponent
+        );
+    }
+}
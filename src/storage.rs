@@ -0,0 +1,570 @@
+use crate::archetype::Archetypes;
+use crate::component::{AnyComponent, AnyValue};
+use crate::entity::Entities;
+use crate::system::{once, Rows, Slot};
+use crate::{Access, Component, Entity};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::mem;
+
+/// Storage for the components of type C of every entity. The components are packed
+/// together in a `Vec`, in no particular order, and `sparse` maps the index of an `Eid`
+/// to the position of its component so that it can be found without hashing.
+#[derive(Debug)]
+pub(crate) struct SparseSet<C> {
+    dense: Vec<C>,
+    indices: Vec<u32>,
+    sparse: Vec<Option<u32>>,
+}
+
+impl<C> Default for SparseSet<C> {
+    fn default() -> Self {
+        SparseSet {
+            dense: Vec::new(),
+            indices: Vec::new(),
+            sparse: Vec::new(),
+        }
+    }
+}
+
+impl<C> SparseSet<C> {
+    fn position(&self, index: u32) -> Option<usize> {
+        self.sparse
+            .get(index as usize)
+            .cloned()
+            .flatten()
+            .map(|p| p as usize)
+    }
+
+    pub(crate) fn get(&self, index: u32) -> Option<&C> {
+        self.position(index).map(|p| &self.dense[p])
+    }
+
+    pub(crate) fn get_mut(&mut self, index: u32) -> Option<&mut C> {
+        let p = self.position(index)?;
+        Some(&mut self.dense[p])
+    }
+
+    /// Stores the component of the entity with the index, returning the one it replaced.
+    pub(crate) fn insert(&mut self, index: u32, component: C) -> Option<C> {
+        if let Some(p) = self.position(index) {
+            return Some(mem::replace(&mut self.dense[p], component));
+        }
+        if self.sparse.len() <= index as usize {
+            self.sparse.resize(index as usize + 1, None);
+        }
+        self.sparse[index as usize] = Some(self.dense.len() as u32);
+        self.dense.push(component);
+        self.indices.push(index);
+        None
+    }
+
+    /// Removes the component of the entity with the index. The last component is moved
+    /// into its place so that the components stay packed.
+    pub(crate) fn remove(&mut self, index: u32) -> Option<C> {
+        let p = self.position(index)?;
+        self.sparse[index as usize] = None;
+        let component = self.dense.swap_remove(p);
+        self.indices.swap_remove(p);
+        if let Some(moved) = self.indices.get(p) {
+            self.sparse[*moved as usize] = Some(p as u32);
+        }
+        Some(component)
+    }
+}
+
+/// The components of a `Column` lent out to a `System`. Slots are taken out entity by
+/// entity.
+pub(crate) struct Borrowed<'a> {
+    /// The indices of the entities which have a component, in the order the components
+    /// are stored.
+    indices: &'a [u32],
+    sparse: &'a [Option<u32>],
+    values: Vec<Slot<'a>>,
+}
+
+impl<'a> Borrowed<'a> {
+    /// Returns true if the entity with the index has a component, even once it was taken.
+    pub(crate) fn has(&self, index: u32) -> bool {
+        matches!(self.sparse.get(index as usize), Some(Some(_)))
+    }
+
+    /// Takes the component of the entity with the index if it has one.
+    pub(crate) fn take(&mut self, index: u32) -> Option<Slot<'a>> {
+        let p = self.sparse.get(index as usize).cloned().flatten()?;
+        Some(mem::replace(&mut self.values[p as usize], Slot::Taken))
+    }
+}
+
+fn boxed<C: Component>(component: C) -> Box<dyn AnyComponent> {
+    Box::new(component)
+}
+
+/// Object safe part of `SparseSet` so that the columns of different component types can
+/// be stored together.
+pub(crate) trait Column: Any + Debug + Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn get(&self, index: u32) -> Option<&AnyValue>;
+    fn insert(
+        &mut self,
+        index: u32,
+        component: Box<dyn AnyComponent>,
+    ) -> Option<Box<dyn AnyComponent>>;
+    fn remove(&mut self, index: u32) -> Option<Box<dyn AnyComponent>>;
+    fn borrow(&mut self) -> Borrowed<'_>;
+}
+
+impl<C: Component> Column for SparseSet<C> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn get(&self, index: u32) -> Option<&AnyValue> {
+        let component: &AnyValue = SparseSet::get(self, index)?;
+        Some(component)
+    }
+
+    fn insert(
+        &mut self,
+        index: u32,
+        component: Box<dyn AnyComponent>,
+    ) -> Option<Box<dyn AnyComponent>> {
+        match component.into_any().downcast::<C>() {
+            Ok(component) => SparseSet::insert(self, index, *component).map(boxed),
+            Err(_) => panic!("component stored in the column of another type"),
+        }
+    }
+
+    fn remove(&mut self, index: u32) -> Option<Box<dyn AnyComponent>> {
+        SparseSet::remove(self, index).map(boxed)
+    }
+
+    fn borrow(&mut self) -> Borrowed<'_> {
+        Borrowed {
+            indices: &self.indices,
+            sparse: &self.sparse,
+            values: self.dense.iter_mut().map(|c| Slot::Free(c)).collect(),
+        }
+    }
+}
+
+/// The components of the entities of a `World`, with a `SparseSet` per component type.
+/// Entities are only known by the index of their `Eid` here, the `World` keeps track of
+/// which ones are alive.
+#[derive(Debug, Default)]
+pub(crate) struct SparseSets {
+    columns: HashMap<TypeId, Box<dyn Column>>,
+}
+
+impl SparseSets {
+    /// Returns the column of the component C if any entity ever had one.
+    pub(crate) fn column<C: Component>(&self) -> Option<&SparseSet<C>> {
+        self.columns
+            .get(&TypeId::of::<C>())?
+            .as_any()
+            .downcast_ref::<SparseSet<C>>()
+    }
+
+    fn column_mut<C: Component>(&mut self) -> Option<&mut SparseSet<C>> {
+        self.columns
+            .get_mut(&TypeId::of::<C>())?
+            .as_any_mut()
+            .downcast_mut::<SparseSet<C>>()
+    }
+
+    pub(crate) fn get<C: Component>(&self, index: u32) -> Option<&C> {
+        self.column::<C>()?.get(index)
+    }
+
+    pub(crate) fn get_mut<C: Component>(&mut self, index: u32) -> Option<&mut C> {
+        self.column_mut::<C>()?.get_mut(index)
+    }
+
+    pub(crate) fn insert<C: Component>(&mut self, index: u32, component: C) -> Option<C> {
+        if self.column::<C>().is_none() {
+            self.columns
+                .insert(TypeId::of::<C>(), Box::new(SparseSet::<C>::default()));
+        }
+        self.column_mut::<C>()?.insert(index, component)
+    }
+
+    pub(crate) fn remove<C: Component>(&mut self, index: u32) -> Option<C> {
+        self.column_mut::<C>()?.remove(index)
+    }
+
+    /// Stores a type erased component, creating its column if needed.
+    pub(crate) fn insert_boxed(
+        &mut self,
+        index: u32,
+        type_id: TypeId,
+        component: Box<dyn AnyComponent>,
+    ) -> Option<Box<dyn AnyComponent>> {
+        self.columns
+            .entry(type_id)
+            .or_insert_with(|| component.new_column())
+            .insert(index, component)
+    }
+
+    pub(crate) fn remove_boxed(
+        &mut self,
+        index: u32,
+        type_id: TypeId,
+    ) -> Option<Box<dyn AnyComponent>> {
+        self.columns.get_mut(&type_id)?.remove(index)
+    }
+
+    /// Moves the components of an `Entity` into their columns.
+    pub(crate) fn insert_entity(&mut self, index: u32, entity: Entity) {
+        for (type_id, component) in entity.components {
+            self.insert_boxed(index, type_id, component);
+        }
+    }
+
+    /// Moves every component of the entity with the index out of the columns.
+    pub(crate) fn remove_entity(&mut self, index: u32) -> Entity {
+        let mut entity = Entity::default();
+        for (type_id, column) in self.columns.iter_mut() {
+            if let Some(component) = column.remove(index) {
+                entity.components.insert(*type_id, component);
+            }
+        }
+        entity
+    }
+
+    /// Iterates over the components of the entity with the index.
+    pub(crate) fn components(&self, index: u32) -> impl Iterator<Item = (TypeId, &AnyValue)> {
+        self.columns
+            .iter()
+            .filter_map(move |(type_id, column)| column.get(index).map(|c| (*type_id, c)))
+    }
+
+    /// Lends out the components the `Access` uses of the living entities `matches` lets
+    /// through, so that a `System` can borrow them all at once. Only the entities in the
+    /// smallest column the `System` can't match without are looked at, in the order that
+    /// column stores their components, or every living entity in the order of their
+    /// index if there is no such column.
+    pub(crate) fn borrow(
+        &mut self,
+        entities: &Entities,
+        access: &Access,
+        matches: fn(&dyn Fn(TypeId) -> bool) -> bool,
+    ) -> Vec<Rows<'_>> {
+        // A column is required if an entity with every component the `System` wants, and
+        // none it skips, isn't matched without it.
+        let wanted: Vec<TypeId> = access.components().collect();
+        let required: Vec<TypeId> = wanted
+            .iter()
+            .filter(|&&type_id| {
+                matches(&|other| wanted.contains(&other))
+                    && !matches(&|other| other != type_id && wanted.contains(&other))
+            })
+            .cloned()
+            .collect();
+        if required.iter().any(|t| !self.columns.contains_key(t)) {
+            return Vec::new();
+        }
+        let columns: Vec<(TypeId, Borrowed<'_>)> = self
+            .columns
+            .iter_mut()
+            .filter(|(type_id, _)| access.uses_component(type_id))
+            .map(|(type_id, column)| (*type_id, column.borrow()))
+            .collect();
+
+        let has = |index: u32, type_id| columns.iter().any(|(t, c)| *t == type_id && c.has(index));
+        let smallest = columns
+            .iter()
+            .filter(|(type_id, _)| required.contains(type_id))
+            .map(|(_, column)| column.indices)
+            .min_by_key(|indices| indices.len());
+        let eids = match smallest {
+            Some(indices) => indices
+                .iter()
+                .filter_map(|i| entities.eid(*i))
+                .filter(|eid| matches(&|t| has(eid.index(), t)))
+                .collect(),
+            None => entities
+                .iter()
+                .filter(|eid| matches(&|t| has(eid.index(), t)))
+                .collect(),
+        };
+        let mut rows = Rows::new(eids);
+        for (type_id, mut column) in columns {
+            let slots = rows
+                .eids
+                .iter()
+                .map(|eid| column.take(eid.index()))
+                .collect();
+            rows.add_column(type_id, slots);
+        }
+        vec![rows]
+    }
+}
+
+/// How a `World` stores the components of its entities.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Backend, Component, World};
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Pos(f64);
+/// impl Component for Pos {}
+///
+/// let mut world = World::with_backend(Backend::Archetype);
+/// let e = world.create_entity().with(Pos(1.0)).build();
+/// assert_eq!(world.backend(), Backend::Archetype);
+/// assert_eq!(world.get_component_for_entity::<Pos>(&e), Ok(&Pos(1.0)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Every component type is stored in its own dense `Vec`, with a sparse index from
+    /// entities to their components. Adding and removing components is cheap, and systems
+    /// visit entities in the order the components of the rarest type they need are
+    /// stored in, which is the order they were added in until some are removed.
+    #[default]
+    SparseSet,
+    /// Entities with the same component types are stored together in a table. Adding and
+    /// removing components moves the entity to another table, but a system only looks at
+    /// the tables which have the components it needs and walks their rows in order.
+    /// Systems visit entities table by table.
+    Archetype,
+}
+
+/// The components of the entities of a `World`, stored by the `Backend` it was created
+/// with.
+#[derive(Debug)]
+pub(crate) enum Storage {
+    SparseSets(SparseSets),
+    Archetypes(Archetypes),
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Storage::new(Backend::default())
+    }
+}
+
+impl Storage {
+    pub(crate) fn new(backend: Backend) -> Self {
+        match backend {
+            Backend::SparseSet => Storage::SparseSets(SparseSets::default()),
+            Backend::Archetype => Storage::Archetypes(Archetypes::default()),
+        }
+    }
+
+    pub(crate) fn backend(&self) -> Backend {
+        match self {
+            Storage::SparseSets(_) => Backend::SparseSet,
+            Storage::Archetypes(_) => Backend::Archetype,
+        }
+    }
+
+    pub(crate) fn get<C: Component>(&self, index: u32) -> Option<&C> {
+        match self {
+            Storage::SparseSets(s) => s.get(index),
+            Storage::Archetypes(a) => a.get(index),
+        }
+    }
+
+    pub(crate) fn get_mut<C: Component>(&mut self, index: u32) -> Option<&mut C> {
+        match self {
+            Storage::SparseSets(s) => s.get_mut(index),
+            Storage::Archetypes(a) => a.get_mut(index),
+        }
+    }
+
+    pub(crate) fn insert<C: Component>(&mut self, index: u32, component: C) -> Option<C> {
+        match self {
+            Storage::SparseSets(s) => s.insert(index, component),
+            Storage::Archetypes(a) => a.insert(index, component),
+        }
+    }
+
+    pub(crate) fn remove<C: Component>(&mut self, index: u32) -> Option<C> {
+        match self {
+            Storage::SparseSets(s) => s.remove(index),
+            Storage::Archetypes(a) => a.remove(index),
+        }
+    }
+
+    pub(crate) fn insert_boxed(
+        &mut self,
+        index: u32,
+        type_id: TypeId,
+        component: Box<dyn AnyComponent>,
+    ) -> Option<Box<dyn AnyComponent>> {
+        match self {
+            Storage::SparseSets(s) => s.insert_boxed(index, type_id, component),
+            Storage::Archetypes(a) => a.insert_boxed(index, type_id, component),
+        }
+    }
+
+    pub(crate) fn remove_boxed(
+        &mut self,
+        index: u32,
+        type_id: TypeId,
+    ) -> Option<Box<dyn AnyComponent>> {
+        match self {
+            Storage::SparseSets(s) => s.remove_boxed(index, type_id),
+            Storage::Archetypes(a) => a.remove_boxed(index, type_id),
+        }
+    }
+
+    pub(crate) fn insert_entity(&mut self, index: u32, entity: Entity) {
+        match self {
+            Storage::SparseSets(s) => s.insert_entity(index, entity),
+            Storage::Archetypes(a) => a.insert_entity(index, entity),
+        }
+    }
+
+    pub(crate) fn remove_entity(&mut self, index: u32) -> Entity {
+        match self {
+            Storage::SparseSets(s) => s.remove_entity(index),
+            Storage::Archetypes(a) => a.remove_entity(index),
+        }
+    }
+
+    pub(crate) fn components(&self, index: u32) -> Vec<(TypeId, &AnyValue)> {
+        match self {
+            Storage::SparseSets(s) => s.components(index).collect(),
+            Storage::Archetypes(a) => a.components(index),
+        }
+    }
+
+    /// Lends out the components the `Access` uses of the living entities, in runs of
+    /// rows, so that a `System` with the `Access` can borrow them all at once. `matches` tells from the component types of
+    /// an entity whether the `System` could match it, which lets backends skip entities
+    /// wholesale. A `System` which fetches nothing from entities gets a single target
+    /// without components instead, so that it runs once.
+    pub(crate) fn borrow(
+        &mut self,
+        entities: &Entities,
+        access: &Access,
+        matches: fn(&dyn Fn(TypeId) -> bool) -> bool,
+    ) -> Vec<Rows<'_>> {
+        if !access.fetches_entities() {
+            return once();
+        }
+        match self {
+            Storage::SparseSets(s) => s.borrow(entities, access, matches),
+            Storage::Archetypes(a) => a.borrow(entities, access, matches),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_storage {
+
+    use crate::entity::Entities;
+    use crate::storage::{SparseSet, SparseSets};
+    use crate::system::Lend;
+    use crate::{Access, Component, Entity, Read, SystemData, With, Without, Write};
+    use std::any::TypeId;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos(u32);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Vel(u32);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Frozen;
+
+    impl Component for Pos {}
+    impl Component for Vel {}
+    impl Component for Frozen {}
+
+    #[test]
+    fn test_sparse_set_stays_packed() {
+        let mut set = SparseSet::default();
+        for i in 0..5 {
+            assert_eq!(set.insert(i * 2, Pos(i)), None);
+        }
+        assert_eq!(set.insert(4, Pos(7)), Some(Pos(2)));
+        assert_eq!(set.dense.len(), 5);
+
+        assert_eq!(set.remove(2), Some(Pos(1)));
+        assert_eq!(set.remove(2), None);
+        assert_eq!(set.dense.len(), 4);
+        assert_eq!(set.get(2), None);
+        assert_eq!(set.get(100), None);
+
+        // The last component was moved into the hole and can still be found.
+        assert_eq!(set.get(8), Some(&Pos(4)));
+        set.get_mut(8).unwrap().0 = 9;
+        assert_eq!(set.dense, vec![Pos(0), Pos(9), Pos(7), Pos(3)]);
+        assert_eq!(set.remove(8), Some(Pos(9)));
+        assert_eq!(set.get(6), Some(&Pos(3)));
+        assert_eq!(set.get(0), Some(&Pos(0)));
+    }
+
+    #[test]
+    fn test_entities_move_in_and_out() {
+        let mut entity = Entity::default();
+        entity.add_component(Pos(1));
+        entity.add_component(Vel(3));
+
+        let mut storage = SparseSets::default();
+        storage.insert_entity(4, entity);
+        assert_eq!(storage.get::<Pos>(4), Some(&Pos(1)));
+        assert_eq!(storage.components(4).count(), 2);
+        assert_eq!(storage.components(3).count(), 0);
+
+        let entity = storage.remove_entity(4);
+        assert_eq!(entity.get_component::<Pos>(), Some(&Pos(1)));
+        assert_eq!(entity.get_component::<Vel>(), Some(&Vel(3)));
+        assert!(storage.column::<Pos>().unwrap().dense.is_empty());
+    }
+
+    #[test]
+    fn test_borrow_only_what_is_used() {
+        let mut entities = Entities::default();
+        let mut storage = SparseSets::default();
+        let mut moving = Vec::new();
+        for i in 0..100 {
+            let eid = entities.insert();
+            storage.insert(eid.index(), Pos(i));
+            if i % 40 == 1 {
+                storage.insert(eid.index(), Vel(i));
+                moving.push(eid);
+            }
+        }
+
+        type Move = (Write<Pos>, Read<Vel>);
+        let borrowed = storage.borrow(&entities, &Access::of::<Move>(), Move::matches);
+        assert_eq!(borrowed.len(), 1);
+        assert_eq!(borrowed[0].eids, moving);
+        assert!((0..3).all(|row| borrowed[0].has(row, TypeId::of::<Pos>())));
+
+        // Only the columns which are used are lent out.
+        type Fast = Read<Vel>;
+        let borrowed = storage.borrow(&entities, &Access::of::<Fast>(), Fast::matches);
+        assert_eq!(borrowed[0].eids, moving);
+        assert!((0..3).all(|row| !borrowed[0].has(row, TypeId::of::<Pos>())));
+
+        type Still = (Read<Pos>, Without<Vel>);
+        let borrowed = storage.borrow(&entities, &Access::of::<Still>(), Still::matches);
+        assert_eq!(borrowed[0].eids.len(), 97);
+
+        type Maybe = (Read<Pos>, Option<Read<Vel>>, Without<Frozen>);
+        let borrowed = storage.borrow(&entities, &Access::of::<Maybe>(), Maybe::matches);
+        assert_eq!(borrowed[0].eids.len(), 100);
+
+        type Stuck = (Read<Vel>, With<Frozen>);
+        let borrowed = storage.borrow(&entities, &Access::of::<Stuck>(), Stuck::matches);
+        assert!(borrowed.is_empty());
+
+        // Entities are visited in the order of the smallest column, not of their index.
+        let vel = storage.remove::<Vel>(moving[0].index()).unwrap();
+        storage.insert(moving[0].index(), vel);
+        let borrowed = storage.borrow(&entities, &Access::of::<Move>(), Move::matches);
+        assert_eq!(borrowed[0].eids, vec![moving[2], moving[1], moving[0]]);
+    }
+}
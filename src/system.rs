@@ -1,77 +1,977 @@
-use crate::{Component, Entity};
+use crate::change::ChangeTicks;
+use crate::component::AnyValue;
+use crate::removal::Removals;
+use crate::{Commands, Component, Eid, Resource};
+use std::any::TypeId;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// A component or resource which is either untouched, borrowed by `Read`s or
+/// borrowed by a `Write`.
+#[derive(Debug)]
+pub(crate) enum Slot<'a> {
+    Free(&'a mut AnyValue),
+    Shared(&'a AnyValue),
+    Taken,
+}
+
+impl<'a> Slot<'a> {
+    fn read(&mut self) -> Option<&'a AnyValue> {
+        let value: &'a AnyValue = match mem::replace(self, Slot::Taken) {
+            Slot::Free(value) => value,
+            Slot::Shared(value) => value,
+            Slot::Taken => return None,
+        };
+        *self = Slot::Shared(value);
+        Some(value)
+    }
+
+    fn write(&mut self) -> Option<&'a mut AnyValue> {
+        match mem::replace(self, Slot::Taken) {
+            Slot::Free(value) => Some(value),
+            other => {
+                *self = other;
+                None
+            }
+        }
+    }
+
+    /// Lends the value out for a shorter time, leaving the slot as it is.
+    fn reborrow(&mut self) -> Slot<'_> {
+        match self {
+            Slot::Free(value) => Slot::Free(&mut **value),
+            Slot::Shared(value) => Slot::Shared(*value),
+            Slot::Taken => Slot::Taken,
+        }
+    }
+}
+
+pub(crate) type Slots<'a> = Vec<(TypeId, Slot<'a>)>;
+
+pub(crate) fn slots<'a>(values: &'a mut HashMap<TypeId, Box<AnyValue>>) -> Slots<'a> {
+    values
+        .iter_mut()
+        .map(|(type_id, value)| (*type_id, Slot::Free(&mut **value)))
+        .collect()
+}
+
+/// The components of a run of entities lent out to a `System`, with a column per
+/// component type and a row per entity. A column holds the slot of the component of
+/// every row, or None for the rows whose entity doesn't have one, so that a `Fetch` finds
+/// a component from its type and row alone.
+#[derive(Debug, Default)]
+pub(crate) struct Rows<'a> {
+    pub(crate) eids: Vec<Eid>,
+    /// Sorted by type.
+    columns: Vec<(TypeId, Vec<Option<Slot<'a>>>)>,
+}
+
+impl<'a> Rows<'a> {
+    pub(crate) fn new(eids: Vec<Eid>) -> Self {
+        Rows {
+            eids,
+            columns: Vec::new(),
+        }
+    }
+
+    /// Adds the column of a component type, which has a slot per row.
+    pub(crate) fn add_column(&mut self, type_id: TypeId, column: Vec<Option<Slot<'a>>>) {
+        debug_assert_eq!(column.len(), self.eids.len());
+        let position = self.columns.partition_point(|(t, _)| *t < type_id);
+        self.columns.insert(position, (type_id, column));
+    }
+
+    fn column(&self, type_id: TypeId) -> Option<&[Option<Slot<'a>>]> {
+        let position = self
+            .columns
+            .binary_search_by_key(&type_id, |(t, _)| *t)
+            .ok()?;
+        Some(&self.columns[position].1)
+    }
+
+    fn slot(&mut self, row: usize, type_id: TypeId) -> Option<&mut Slot<'a>> {
+        let position = self
+            .columns
+            .binary_search_by_key(&type_id, |(t, _)| *t)
+            .ok()?;
+        self.columns[position].1.get_mut(row)?.as_mut()
+    }
+
+    /// Splits off the rows from `at` on.
+    pub(crate) fn split_off(&mut self, at: usize) -> Rows<'a> {
+        Rows {
+            eids: self.eids.split_off(at),
+            columns: self
+                .columns
+                .iter_mut()
+                .map(|(type_id, column)| (*type_id, column.split_off(at)))
+                .collect(),
+        }
+    }
+
+    /// Splits the rows between several systems. Each `Access` gets the columns it uses,
+    /// the way `split` splits values: a column written by one of them is lent mutably to
+    /// that system and shows as taken to the others, every other column is shared.
+    pub(crate) fn split(self, accesses: &[Access]) -> Vec<Rows<'a>> {
+        let mut split: Vec<Rows<'a>> = accesses
+            .iter()
+            .map(|_| Rows::new(self.eids.clone()))
+            .collect();
+        for (type_id, mut column) in self.columns {
+            let writer = accesses.iter().position(|a| a.writes_component(&type_id));
+            for (i, (rows, access)) in split.iter_mut().zip(accesses).enumerate() {
+                if Some(i) == writer || !access.uses_component(&type_id) {
+                    continue;
+                }
+                let lent = column
+                    .iter_mut()
+                    .map(|slot| {
+                        slot.as_mut().map(|slot| match writer {
+                            Some(_) => Slot::Taken,
+                            None => slot.read().map_or(Slot::Taken, Slot::Shared),
+                        })
+                    })
+                    .collect();
+                rows.columns.push((type_id, lent));
+            }
+            if let Some(writer) = writer {
+                split[writer].columns.push((type_id, column));
+            }
+        }
+        split
+    }
+}
+
+/// Splits runs of rows into chunks of `size` rows, the last of which can be smaller.
+pub(crate) fn chunks(rows: Vec<Rows<'_>>, size: usize) -> Vec<Vec<Rows<'_>>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut left = size.max(1);
+    for mut rows in rows {
+        while rows.eids.len() >= left {
+            let rest = rows.split_off(left);
+            chunk.push(rows);
+            chunks.push(mem::take(&mut chunk));
+            rows = rest;
+            left = size.max(1);
+        }
+        if !rows.eids.is_empty() {
+            left -= rows.eids.len();
+            chunk.push(rows);
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// Lends out the components of `Rows` for `'a`, which can be shorter than the `Rows` were
+/// borrowed for, so that a `Fetch` only borrows them while it fetches from one entity.
+pub(crate) trait Lend<'a>: Debug {
+    fn has(&self, row: usize, type_id: TypeId) -> bool;
+    fn read(&mut self, row: usize, type_id: TypeId) -> Option<&'a AnyValue>;
+    fn write(&mut self, row: usize, type_id: TypeId) -> Option<&'a mut AnyValue>;
+}
+
+impl<'a, 'r: 'a> Lend<'a> for Rows<'r> {
+    fn has(&self, row: usize, type_id: TypeId) -> bool {
+        self.column(type_id)
+            .is_some_and(|column| matches!(column.get(row), Some(Some(_))))
+    }
+
+    fn read(&mut self, row: usize, type_id: TypeId) -> Option<&'a AnyValue> {
+        self.slot(row, type_id)?.read()
+    }
+
+    fn write(&mut self, row: usize, type_id: TypeId) -> Option<&'a mut AnyValue> {
+        self.slot(row, type_id)?.write()
+    }
+}
+
+/// The only target of a `System` which fetches nothing from entities, so that it runs
+/// once.
+pub(crate) fn once<'a>() -> Vec<Rows<'a>> {
+    vec![Rows::new(vec![Eid::new(u32::MAX, u32::MAX)])]
+}
+
+fn find<'s, 'a>(slots: &'s mut Slots<'a>, type_id: TypeId) -> Option<&'s mut Slot<'a>> {
+    slots
+        .iter_mut()
+        .find(|(t, _)| *t == type_id)
+        .map(|(_, slot)| slot)
+}
+
+/// The share of the `World` a system borrows. Values the system writes are `Slot::Free`,
+/// values nobody writes are `Slot::Shared` and values another system running at the same
+/// time writes are `Slot::Taken`, so the system can still tell that an `Entity` has
+/// them. Only the resources the system uses are lent out.
+#[derive(Debug, Default)]
+pub(crate) struct View<'a> {
+    pub(crate) rows: Vec<Rows<'a>>,
+    pub(crate) resources: Slots<'a>,
+    pub(crate) ticks: Option<&'a ChangeTicks>,
+    pub(crate) removals: Option<&'a Removals>,
+}
+
+impl<'a> View<'a> {
+    /// Fetches `D` from every `Entity` of the view and hands it to `run` along with its
+    /// `Eid`, queueing the commands of the system in `commands`. `Changed`, `Added` and
+    /// `RemovedComponents` look for changes at or after the tick `since`.
+    pub(crate) fn run<D, F>(mut self, commands: &mut Commands, since: u64, mut run: F) -> Ran
+    where
+        D: SystemData,
+        F: FnMut(Eid, D::Item<'_>),
+    {
+        let start = Instant::now();
+        let mut ran = Ran::default();
+        for rows in self.rows.iter_mut() {
+            for row in 0..rows.eids.len() {
+                let eid = rows.eids[row];
+                let written = ran.written.len();
+                let mut fetch = Fetch {
+                    eid,
+                    row,
+                    rows: &mut *rows,
+                    commands: Some(&mut *commands),
+                    resources: self
+                        .resources
+                        .iter_mut()
+                        .map(|(type_id, slot)| (*type_id, slot.reborrow()))
+                        .collect(),
+                    ticks: self.ticks,
+                    removals: self.removals,
+                    since,
+                    written: &mut ran.written,
+                };
+                let matched = match D::fetch(&mut fetch) {
+                    Some(data) => {
+                        run(eid, data);
+                        true
+                    }
+                    None => false,
+                };
+                if matched {
+                    ran.matched += 1;
+                } else {
+                    // Nothing ran, so whatever was borrowed mutably wasn't written.
+                    ran.written.truncate(written);
+                }
+            }
+        }
+        ran.elapsed = start.elapsed();
+        ran
+    }
+}
+
+/// What a `System` did while it ran.
+#[derive(Debug, Default)]
+pub(crate) struct Ran {
+    /// The number of entities it ran on.
+    pub(crate) matched: usize,
+    /// The entity index and type of every component it wrote.
+    pub(crate) written: Vec<(u32, TypeId)>,
+    pub(crate) elapsed: Duration,
+}
+
+impl Ran {
+    /// Adds up what the system did on several threads.
+    pub(crate) fn extend(&mut self, other: Ran) {
+        self.matched += other.matched;
+        self.written.extend(other.written);
+    }
+}
+
+/// Splits borrowed values between several systems. A value written by one of the
+/// `Access`es is lent mutably to that system and hidden from the others, every other
+/// value is shared with all of them.
+pub(crate) fn split<'a>(
+    values: Slots<'a>,
+    accesses: &[Access],
+    writes: impl Fn(&Access, &TypeId) -> bool,
+) -> Vec<Slots<'a>> {
+    let mut split: Vec<Slots<'a>> = accesses.iter().map(|_| Vec::new()).collect();
+    for (type_id, mut value) in values {
+        match accesses.iter().position(|a| writes(a, &type_id)) {
+            Some(writer) => {
+                for (i, slots) in split.iter_mut().enumerate() {
+                    if i != writer {
+                        slots.push((type_id, Slot::Taken));
+                    }
+                }
+                split[writer].push((type_id, value));
+            }
+            None => {
+                let value = value.read();
+                for slots in split.iter_mut() {
+                    slots.push((type_id, value.map_or(Slot::Taken, Slot::Shared)));
+                }
+            }
+        }
+    }
+    split
+}
+
+/// The components of a single `Entity`, and the resources of the `World`, which a
+/// `SystemData` borrows from. A component or resource can be read any number of times
+/// or written once, so the references handed to a `System` never alias.
+#[derive(Debug)]
+pub struct Fetch<'a> {
+    eid: Eid,
+    row: usize,
+    rows: &'a mut dyn Lend<'a>,
+    commands: Option<&'a mut Commands>,
+    resources: Slots<'a>,
+    ticks: Option<&'a ChangeTicks>,
+    removals: Option<&'a Removals>,
+    since: u64,
+    /// Where the entity index and type of every component borrowed mutably go.
+    written: &'a mut Vec<(u32, TypeId)>,
+}
+
+impl<'a> Fetch<'a> {
+    /// Returns the `Eid` of the `Entity` being fetched from.
+    pub fn eid(&self) -> Eid {
+        self.eid
+    }
+
+    /// Borrows the `Commands` buffer of the running `System`. Returns None if it was
+    /// already borrowed.
+    pub fn commands(&mut self) -> Option<&'a mut Commands> {
+        self.commands.take()
+    }
+
+    /// Returns true if the `Entity` has a component C, whether it was borrowed or not.
+    pub fn has<C: Component>(&self) -> bool {
+        self.contains(TypeId::of::<C>())
+    }
+
+    fn contains(&self, type_id: TypeId) -> bool {
+        self.rows.has(self.row, type_id)
+    }
+
+    /// Borrows the component C. Returns None if the `Entity` doesn't have a component C
+    /// or if it was already borrowed mutably.
+    pub fn read<C: Component>(&mut self) -> Option<&'a C> {
+        self.rows
+            .read(self.row, TypeId::of::<C>())?
+            .downcast_ref::<C>()
+    }
+
+    /// Borrows the component C mutably. Returns None if the `Entity` doesn't have a
+    /// component C or if it was already borrowed.
+    pub fn write<C: Component>(&mut self) -> Option<&'a mut C> {
+        let component = self
+            .rows
+            .write(self.row, TypeId::of::<C>())?
+            .downcast_mut::<C>()?;
+        self.written.push((self.eid.index(), TypeId::of::<C>()));
+        Some(component)
+    }
+
+    /// Returns true if the component C of the `Entity` changed, or was added, since the
+    /// `System` last ran.
+    pub fn changed<C: Component>(&self) -> bool {
+        self.ticks
+            .and_then(|ticks| ticks.get(TypeId::of::<C>(), self.eid.index()))
+            .is_some_and(|ticks| ticks.changed >= self.since)
+    }
+
+    /// Returns true if the component C was added to the `Entity` since the `System` last
+    /// ran.
+    pub fn added<C: Component>(&self) -> bool {
+        self.ticks
+            .and_then(|ticks| ticks.get(TypeId::of::<C>(), self.eid.index()))
+            .is_some_and(|ticks| ticks.added >= self.since)
+    }
+
+    /// Returns the entities which lost their component C since the `System` last ran.
+    pub fn removed<C: Component>(&self) -> &'a [Eid] {
+        self.removals.map_or(&[], |removals| {
+            removals.removed(TypeId::of::<C>(), self.since)
+        })
+    }
+
+    /// Borrows the resource R. Returns None if the `World` doesn't have a resource R or
+    /// if it was already borrowed mutably.
+    pub fn resource<R: Resource>(&mut self) -> Option<&'a R> {
+        find(&mut self.resources, TypeId::of::<R>())?
+            .read()?
+            .downcast_ref::<R>()
+    }
+
+    /// Borrows the resource R mutably. Returns None if the `World` doesn't have a
+    /// resource R or if it was already borrowed.
+    pub fn resource_mut<R: Resource>(&mut self) -> Option<&'a mut R> {
+        find(&mut self.resources, TypeId::of::<R>())?
+            .write()?
+            .downcast_mut::<R>()
+    }
+}
+
+/// The components and resources a `SystemData` reads and writes. Two systems conflict if
+/// one of them writes a component or resource the other one reads or writes, or if
+/// either of them queues `Commands`, which change the `World` between systems.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Access, Component, Read, Write};
+///
+/// #[derive(Debug, Clone, Copy)]
+/// struct Pos(f64);
+/// #[derive(Debug, Clone, Copy)]
+/// struct Vel(f64);
+/// impl Component for Pos {}
+/// impl Component for Vel {}
+///
+/// let movement = Access::of::<(Write<Pos>, Read<Vel>)>();
+/// let render = Access::of::<Read<Pos>>();
+/// let steering = Access::of::<Write<Vel>>();
+/// let friction = Access::of::<Vel>();
+///
+/// assert!(movement.is_written::<Pos>());
+/// assert!(movement.conflicts_with(&render));
+/// assert!(movement.conflicts_with(&steering));
+/// assert!(!render.conflicts_with(&steering));
+/// assert!(steering.conflicts_with(&friction));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Access {
+    reads: HashSet<TypeId>,
+    writes: HashSet<TypeId>,
+    resource_reads: HashSet<TypeId>,
+    resource_writes: HashSet<TypeId>,
+    filters: HashSet<TypeId>,
+    exclusions: HashSet<TypeId>,
+    eid: bool,
+    commands: bool,
+}
+
+impl Access {
+    /// Returns the `Access` of a `SystemData`.
+    pub fn of<D: SystemData>() -> Self {
+        let mut access = Access::default();
+        D::access(&mut access);
+        access
+    }
+
+    /// Records that the component C is read.
+    pub fn add_read<C: Component>(&mut self) {
+        self.reads.insert(TypeId::of::<C>());
+    }
+
+    /// Records that the component C is written.
+    pub fn add_write<C: Component>(&mut self) {
+        self.writes.insert(TypeId::of::<C>());
+    }
+
+    /// Records that the resource R is read.
+    pub fn add_resource_read<R: Resource>(&mut self) {
+        self.resource_reads.insert(TypeId::of::<R>());
+    }
+
+    /// Records that the resource R is written.
+    pub fn add_resource_write<R: Resource>(&mut self) {
+        self.resource_writes.insert(TypeId::of::<R>());
+    }
+
+    /// Records that entities are filtered by the component C without borrowing it.
+    pub fn add_filter<C: Component>(&mut self) {
+        self.filters.insert(TypeId::of::<C>());
+    }
+
+    /// Records that entities which have the component C are skipped.
+    pub fn add_exclusion<C: Component>(&mut self) {
+        self.exclusions.insert(TypeId::of::<C>());
+    }
+
+    /// Records that the `Eid` of entities is fetched.
+    pub fn add_eid(&mut self) {
+        self.eid = true;
+    }
+
+    /// Records that `Commands` are queued.
+    pub fn add_commands(&mut self) {
+        self.commands = true;
+    }
+
+    /// Returns true if the component C is read.
+    pub fn is_read<C: Component>(&self) -> bool {
+        self.reads.contains(&TypeId::of::<C>())
+    }
+
+    /// Returns true if the component C is written.
+    pub fn is_written<C: Component>(&self) -> bool {
+        self.writes.contains(&TypeId::of::<C>())
+    }
+
+    /// Returns true if the resource R is read.
+    pub fn is_resource_read<R: Resource>(&self) -> bool {
+        self.resource_reads.contains(&TypeId::of::<R>())
+    }
+
+    /// Returns true if the resource R is written.
+    pub fn is_resource_written<R: Resource>(&self) -> bool {
+        self.resource_writes.contains(&TypeId::of::<R>())
+    }
+
+    /// Returns true if `Commands` are queued.
+    pub fn queues_commands(&self) -> bool {
+        self.commands
+    }
+
+    /// Returns true if anything is fetched from entities. A `System` which only
+    /// borrows resources or queues commands runs once per dispatch instead, even in a
+    /// `World` without entities.
+    pub fn fetches_entities(&self) -> bool {
+        self.eid
+            || !self.reads.is_empty()
+            || !self.writes.is_empty()
+            || !self.filters.is_empty()
+            || !self.exclusions.is_empty()
+    }
+
+    /// Iterates over the components which are read.
+    pub fn reads(&self) -> impl Iterator<Item = TypeId> + '_ {
+        self.reads.iter().cloned()
+    }
+
+    /// Iterates over the components which are written.
+    pub fn writes(&self) -> impl Iterator<Item = TypeId> + '_ {
+        self.writes.iter().cloned()
+    }
+
+    /// Iterates over the resources which are written.
+    pub fn resource_writes(&self) -> impl Iterator<Item = TypeId> + '_ {
+        self.resource_writes.iter().cloned()
+    }
+
+    /// Adds everything another `Access` reads and writes to this one.
+    pub fn extend(&mut self, other: &Access) {
+        self.reads.extend(other.reads.iter().cloned());
+        self.writes.extend(other.writes.iter().cloned());
+        self.resource_reads
+            .extend(other.resource_reads.iter().cloned());
+        self.resource_writes
+            .extend(other.resource_writes.iter().cloned());
+        self.filters.extend(other.filters.iter().cloned());
+        self.exclusions.extend(other.exclusions.iter().cloned());
+        self.eid |= other.eid;
+        self.commands |= other.commands;
+    }
+
+    pub(crate) fn writes_component(&self, type_id: &TypeId) -> bool {
+        self.writes.contains(type_id)
+    }
+
+    /// Iterates over the components which are read, written or filtered by, except the
+    /// ones entities are skipped for.
+    pub(crate) fn components(&self) -> impl Iterator<Item = TypeId> + '_ {
+        self.reads
+            .union(&self.writes)
+            .chain(
+                self.filters
+                    .iter()
+                    .filter(move |t| !self.reads.contains(t) && !self.writes.contains(t)),
+            )
+            .filter(move |t| !self.exclusions.contains(t))
+            .cloned()
+    }
+
+    /// Returns true if the component is read, written, filtered by or skipped for.
+    pub(crate) fn uses_component(&self, type_id: &TypeId) -> bool {
+        self.reads.contains(type_id)
+            || self.writes.contains(type_id)
+            || self.filters.contains(type_id)
+            || self.exclusions.contains(type_id)
+    }
+
+    /// Returns true if the resource is read or written.
+    pub(crate) fn uses_resource(&self, type_id: &TypeId) -> bool {
+        self.resource_reads.contains(type_id) || self.resource_writes.contains(type_id)
+    }
+
+    pub(crate) fn writes_resource(&self, type_id: &TypeId) -> bool {
+        self.resource_writes.contains(type_id)
+    }
+
+    /// Returns true if systems with the two `Access`es can't run at the same time.
+    pub fn conflicts_with(&self, other: &Access) -> bool {
+        self.commands
+            || other.commands
+            || !self.writes.is_disjoint(&other.writes)
+            || !self.writes.is_disjoint(&other.reads)
+            || !self.reads.is_disjoint(&other.writes)
+            || !self.resource_writes.is_disjoint(&other.resource_writes)
+            || !self.resource_writes.is_disjoint(&other.resource_reads)
+            || !self.resource_reads.is_disjoint(&other.resource_writes)
+    }
+}
 
 /// Trait used to define what kind of data can be used to Query in a `System`.
-/// `SystemData` can `fetch` data from an entity if it has the system data. Additionaly,
-/// `SystemData` can `set` the data to an entity.
+/// `SystemData` can `fetch` references to the components of an entity if it has them.
 ///
-/// `SystemData` can be a single `Component` or a tuple of `Components`.
-/// #TODO:
-///     Allow for generic Component tuple instead of just (A,B).
-pub trait SystemData: Sized + Clone + Debug {
+/// `SystemData` can be `Read<C>` or `Write<C>` to borrow a component C immutably or
+/// mutably, a bare `Component`, which is the same as `Write<C>`, or a tuple of up to 12
+/// `SystemData`. A tuple is only fetched if every one of its elements is. `Eid` hands
+/// over the id of the entity being visited, `Commands` a buffer to queue changes to the
+/// `World` in and `Res<R>` and `ResMut<R>` borrow a resource of the `World`.
+/// `Option<T>` fetches `T` if it can and hands back None otherwise, while `With<C>` and
+/// `Without<C>` filter entities by a component without borrowing it.
+pub trait SystemData {
+    /// The references handed to `System::run`.
+    type Item<'a>;
     /// Returns the `SystemData` of an `Entity` if the `Entity` has the requisite
     /// `Components`. If the `Entity` doesn't have the requisite `Components` than `None`
     /// is returned.
-    fn fetch(e: &Entity) -> Option<Self>;
-    /// Defines the behaviour for updateing an `Entities` data to some new `SystemData`.
-    fn set(self, e: &mut Entity);
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>>;
+    /// Records the components which `fetch` borrows or filters by, and whether it
+    /// fetches the `Eid`.
+    fn access(access: &mut Access);
+    /// Returns false if `fetch` can't succeed on an `Entity` with the component types
+    /// `has` returns true for. Lets storage backends skip entities without fetching from
+    /// them.
+    fn matches(_has: &dyn Fn(TypeId) -> bool) -> bool {
+        true
+    }
 }
 
 impl<C> SystemData for C
 where
     C: Component,
 {
-    fn fetch(e: &Entity) -> Option<Self> {
-        if let Some(c) = e.get_component::<C>() {
-            Some((*c).clone())
+    type Item<'a> = &'a mut C;
+
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+        fetch.write::<C>()
+    }
+
+    fn access(access: &mut Access) {
+        access.add_write::<C>();
+    }
+
+    fn matches(has: &dyn Fn(TypeId) -> bool) -> bool {
+        has(TypeId::of::<C>())
+    }
+}
+
+/// Borrows a component C immutably. Systems which only read a component can run
+/// alongside each other.
+#[derive(Debug)]
+pub struct Read<C>(PhantomData<C>);
+
+impl<C> SystemData for Read<C>
+where
+    C: Component,
+{
+    type Item<'a> = &'a C;
+
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+        fetch.read::<C>()
+    }
+
+    fn access(access: &mut Access) {
+        access.add_read::<C>();
+    }
+
+    fn matches(has: &dyn Fn(TypeId) -> bool) -> bool {
+        has(TypeId::of::<C>())
+    }
+}
+
+/// Borrows a component C mutably.
+#[derive(Debug)]
+pub struct Write<C>(PhantomData<C>);
+
+impl<C> SystemData for Write<C>
+where
+    C: Component,
+{
+    type Item<'a> = &'a mut C;
+
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+        fetch.write::<C>()
+    }
+
+    fn access(access: &mut Access) {
+        access.add_write::<C>();
+    }
+
+    fn matches(has: &dyn Fn(TypeId) -> bool) -> bool {
+        has(TypeId::of::<C>())
+    }
+}
+
+/// Hands the `Eid` of the `Entity` to the `System`.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Component, Eid, Read, System, World};
+///
+/// #[derive(Debug, Clone, Copy)]
+/// struct Health(u8);
+/// impl Component for Health {}
+///
+/// #[derive(Default)]
+/// struct FindDead {
+///     dead: Vec<Eid>,
+/// }
+/// impl System for FindDead {
+///     type Data = (Eid, Read<Health>);
+///     fn run(&mut self, (eid, health): (Eid, &Health)) {
+///         if health.0 == 0 {
+///             self.dead.push(eid);
+///         }
+///     }
+/// }
+///
+/// let mut world = World::default();
+/// world.create_entity().with(Health(3)).build();
+/// let dead = world.create_entity().with(Health(0)).build();
+///
+/// let mut find_dead = FindDead::default();
+/// world.dispatch_system(&mut find_dead);
+/// assert_eq!(find_dead.dead, vec![dead]);
+/// ```
+impl SystemData for Eid {
+    type Item<'a> = Eid;
+
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+        Some(fetch.eid())
+    }
+
+    fn access(access: &mut Access) {
+        access.add_eid();
+    }
+}
+
+impl<T> SystemData for Option<T>
+where
+    T: SystemData,
+{
+    type Item<'a> = Option<T::Item<'a>>;
+
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+        // T could borrow part of the entity before failing, so it is only fetched if the
+        // entity has the components it needs. Filters like `Changed` can still fail once
+        // it does, in which case what T borrowed mutably isn't counted as written.
+        if !T::matches(&|type_id| fetch.contains(type_id)) {
+            return Some(None);
+        }
+        let written = fetch.written.len();
+        let item = T::fetch(fetch);
+        if item.is_none() {
+            fetch.written.truncate(written);
+        }
+        Some(item)
+    }
+
+    fn access(access: &mut Access) {
+        T::access(access);
+    }
+}
+
+/// Filter which only matches entities which have a component C.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Component, System, With, Without, World};
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Pos(f64);
+/// #[derive(Debug, Clone, Copy)]
+/// struct Player;
+/// #[derive(Debug, Clone, Copy)]
+/// struct Frozen;
+/// impl Component for Pos {}
+/// impl Component for Player {}
+/// impl Component for Frozen {}
+///
+/// struct MovePlayers;
+/// impl System for MovePlayers {
+///     type Data = (Pos, With<Player>, Without<Frozen>);
+///     fn run(&mut self, (pos, _, _): (&mut Pos, (), ())) {
+///         pos.0 += 1.0;
+///     }
+/// }
+///
+/// let mut world = World::default();
+/// let moving = world.create_entity().with(Pos(0.0)).with(Player).build();
+/// let frozen = world.create_entity().with(Pos(0.0)).with(Player).with(Frozen).build();
+/// let npc = world.create_entity().with(Pos(0.0)).build();
+/// world.dispatch_system(&mut MovePlayers);
+///
+/// assert_eq!(world.get_component_for_entity::<Pos>(&moving), Ok(&Pos(1.0)));
+/// assert_eq!(world.get_component_for_entity::<Pos>(&frozen), Ok(&Pos(0.0)));
+/// assert_eq!(world.get_component_for_entity::<Pos>(&npc), Ok(&Pos(0.0)));
+/// ```
+#[derive(Debug)]
+pub struct With<C>(PhantomData<C>);
+
+impl<C> SystemData for With<C>
+where
+    C: Component,
+{
+    type Item<'a> = ();
+
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+        if fetch.has::<C>() {
+            Some(())
         } else {
             None
         }
     }
-    fn set(self, e: &mut Entity) {
-        e.add_component::<C>(self);
+
+    fn access(access: &mut Access) {
+        access.add_filter::<C>();
+    }
+
+    fn matches(has: &dyn Fn(TypeId) -> bool) -> bool {
+        has(TypeId::of::<C>())
     }
 }
 
-impl<A, B> SystemData for (A, B)
+/// Filter which only matches entities which don't have a component C.
+#[derive(Debug)]
+pub struct Without<C>(PhantomData<C>);
+
+impl<C> SystemData for Without<C>
 where
-    A: Component,
-    B: Component,
+    C: Component,
 {
-    fn fetch(e: &Entity) -> Option<Self> {
-        match (e.get_component::<A>(), e.get_component::<B>()) {
-            (Some(a), Some(b)) => Some(((*a).clone(), (*b).clone())),
-            _ => None,
+    type Item<'a> = ();
+
+    fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+        if fetch.has::<C>() {
+            None
+        } else {
+            Some(())
         }
     }
-    fn set(self, e: &mut Entity) {
-        e.add_component::<A>(self.0);
-        e.add_component::<B>(self.1);
+
+    fn access(access: &mut Access) {
+        access.add_exclusion::<C>();
     }
+
+    fn matches(has: &dyn Fn(TypeId) -> bool) -> bool {
+        !has(TypeId::of::<C>())
+    }
+}
+
+macro_rules! impl_system_data_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: SystemData),+> SystemData for ($($name,)+) {
+            type Item<'a> = ($($name::Item<'a>,)+);
+
+            fn fetch<'a>(fetch: &mut Fetch<'a>) -> Option<Self::Item<'a>> {
+                Some(($($name::fetch(fetch)?,)+))
+            }
+
+            fn access(access: &mut Access) {
+                $($name::access(access);)+
+            }
+
+            fn matches(has: &dyn Fn(TypeId) -> bool) -> bool {
+                $($name::matches(has))&&+
+            }
+        }
+    };
 }
 
+impl_system_data_tuple!(A);
+impl_system_data_tuple!(A, B);
+impl_system_data_tuple!(A, B, C);
+impl_system_data_tuple!(A, B, C, D);
+impl_system_data_tuple!(A, B, C, D, E);
+impl_system_data_tuple!(A, B, C, D, E, F);
+impl_system_data_tuple!(A, B, C, D, E, F, G);
+impl_system_data_tuple!(A, B, C, D, E, F, G, H);
+impl_system_data_tuple!(A, B, C, D, E, F, G, H, I);
+impl_system_data_tuple!(A, B, C, D, E, F, G, H, I, J);
+impl_system_data_tuple!(A, B, C, D, E, F, G, H, I, J, K);
+impl_system_data_tuple!(A, B, C, D, E, F, G, H, I, J, K, L);
+
 /// Trait defining a generic System. Any `Entity` with that doens't return `None` to
 /// `System::Data::fetch` will have `run` called on its Data.
 pub trait System {
     /// Defines the type of data to be queried.
     type Data: SystemData;
-    /// Defines the behaviour of the system. Gets called in World::system_dispatch.
-    fn run(&mut self, data: &mut Self::Data);
+    /// Defines the behaviour of the system. Gets called in World::system_dispatch with
+    /// references to the components of each matching `Entity`.
+    fn run(&mut self, data: <Self::Data as SystemData>::Item<'_>);
+}
+
+/// Trait defining a `System` which can run on many entities at the same time. The
+/// entities of the `World` are split into chunks which are handed to different threads by
+/// `World::par_dispatch_system`, so `run` only borrows the system immutably. Shared
+/// state has to be synchronised, e.g. with atomics or a `Mutex`.
+///
+/// Resources are shared between the threads, so `ResMut` is never fetched.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Component, ParSystem, Read, World, Write};
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Pos(f64);
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Vel(f64);
+/// impl Component for Pos {}
+/// impl Component for Vel {}
+///
+/// struct Physics {
+///     dt: f64,
+/// }
+///
+/// impl ParSystem for Physics {
+///     type Data = (Write<Pos>, Read<Vel>);
+///     fn run(&self, (pos, vel): (&mut Pos, &Vel)) {
+///         pos.0 += vel.0 * self.dt;
+///     }
+/// }
+///
+/// let mut world = World::default();
+/// let entities: Vec<_> = (0..1000)
+///     .map(|i| world.create_entity().with(Pos(0.0)).with(Vel(i as f64)).build())
+///     .collect();
+/// world.par_dispatch_system(&Physics { dt: 0.5 });
+///
+/// assert_eq!(world.get_component_for_entity::<Pos>(&entities[10]), Ok(&Pos(5.0)));
+/// ```
+///
+/// # Panics
+/// Resources are shared between the threads, so dispatching a `ParSystem` whose `Data`
+/// writes one with `ResMut` panics.
+pub trait ParSystem: Sync {
+    /// Defines the type of data to be queried.
+    type Data: SystemData;
+    /// Defines the behaviour of the system. Gets called in World::par_dispatch_system
+    /// with references to the components of each matching `Entity`, from any thread.
+    fn run(&self, data: <Self::Data as SystemData>::Item<'_>);
 }
 
 #[cfg(test)]
 mod test_system {
 
     use crate::{Component, System, World};
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[test]
     fn ideal() {
-        #[derive(Debug, Clone, Copy)]
+        #[derive(Debug, Clone, Copy, PartialEq)]
         struct Pos {
             x: f64,
             y: f64,
         }
 
+        #[allow(dead_code)]
         #[derive(Debug, Clone, Copy)]
         struct Vel {
             x: f64,
@@ -81,7 +981,7 @@ mod test_system {
         impl Component for Vel {}
 
         let mut world = World::default();
-        world
+        let e = world
             .create_entity()
             .with(Pos { x: 0.0, y: 0.0 })
             .with(Vel { x: 1.6, y: -4.5 })
@@ -92,18 +992,590 @@ mod test_system {
         impl System for ReadSys {
             type Data = (Pos, Vel);
 
-            fn run(&mut self, data: &mut Self::Data) {
-                let (pos, vel) = data;
-                println!("Pos: {:?}", pos);
-                println!("Vel: {:?}", vel);
+            fn run(&mut self, (pos, _vel): (&mut Pos, &mut Vel)) {
                 pos.x += 10.0;
                 pos.y += 5.0;
             }
         }
 
         let mut rs = ReadSys {};
-        println!("Dispatching System!");
         world.dispatch_system(&mut rs);
         world.dispatch_system(&mut rs);
+        assert_eq!(
+            world.get_component_for_entity::<Pos>(&e),
+            Ok(&Pos { x: 20.0, y: 10.0 })
+        );
+    }
+
+    #[test]
+    fn test_components_are_not_cloned() {
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Debug)]
+        struct Big {
+            data: Vec<u8>,
+        }
+
+        impl Clone for Big {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::SeqCst);
+                Big {
+                    data: self.data.clone(),
+                }
+            }
+        }
+        impl Component for Big {}
+
+        struct Grow;
+
+        impl System for Grow {
+            type Data = Big;
+
+            fn run(&mut self, big: &mut Big) {
+                big.data.push(1);
+            }
+        }
+
+        let mut world = World::default();
+        let e = world.create_entity().with(Big { data: vec![] }).build();
+        world.dispatch_system(&mut Grow);
+        world.dispatch_system(&mut Grow);
+
+        assert_eq!(CLONES.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            world.get_component_for_entity::<Big>(&e).unwrap().data,
+            vec![1, 1]
+        );
+    }
+
+    #[test]
+    fn test_component_is_only_borrowed_once() {
+        #[derive(Debug, Clone, Copy)]
+        struct Pos {
+            x: f64,
+        }
+        impl Component for Pos {}
+
+        struct Twice {
+            runs: usize,
+        }
+
+        impl System for Twice {
+            type Data = (Pos, Pos);
+
+            fn run(&mut self, (a, b): (&mut Pos, &mut Pos)) {
+                a.x += b.x;
+                self.runs += 1;
+            }
+        }
+
+        let mut world = World::default();
+        world.create_entity().with(Pos { x: 1.0 }).build();
+        let mut twice = Twice { runs: 0 };
+        world.dispatch_system(&mut twice);
+        assert_eq!(twice.runs, 0);
+    }
+}
+
+#[cfg(test)]
+mod test_system_filters {
+
+    use crate::{Changed, Component, Eid, Read, System, With, Without, World, Write};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos {
+        x: f64,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Vel {
+        x: f64,
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct Frozen;
+
+    impl Component for Pos {}
+    impl Component for Vel {}
+    impl Component for Frozen {}
+
+    #[derive(Default)]
+    struct Movement {
+        visited: Vec<(Eid, bool)>,
+    }
+
+    impl System for Movement {
+        type Data = (Eid, Pos, Option<Vel>, Without<Frozen>);
+
+        fn run(&mut self, (eid, pos, vel, _): (Eid, &mut Pos, Option<&mut Vel>, ())) {
+            self.visited.push((eid, vel.is_some()));
+            if let Some(vel) = vel {
+                pos.x += vel.x;
+            }
+        }
+    }
+
+    #[test]
+    fn test_optional_components() {
+        let mut world = World::default();
+        let still = world.create_entity().with(Pos { x: 1.0 }).build();
+        let moving = world
+            .create_entity()
+            .with(Pos { x: 0.0 })
+            .with(Vel { x: 2.0 })
+            .build();
+        let frozen = world
+            .create_entity()
+            .with(Pos { x: 0.0 })
+            .with(Vel { x: 2.0 })
+            .with(Frozen)
+            .build();
+        world.create_entity().with(Vel { x: 2.0 }).build();
+
+        let mut movement = Movement::default();
+        world.dispatch_system(&mut movement);
+
+        assert_eq!(movement.visited, vec![(still, false), (moving, true)]);
+        assert_eq!(
+            world.get_component_for_entity::<Pos>(&still),
+            Ok(&Pos { x: 1.0 })
+        );
+        assert_eq!(
+            world.get_component_for_entity::<Pos>(&moving),
+            Ok(&Pos { x: 2.0 })
+        );
+        assert_eq!(
+            world.get_component_for_entity::<Pos>(&frozen),
+            Ok(&Pos { x: 0.0 })
+        );
+    }
+
+    #[test]
+    fn test_optional_tuples_borrow_all_or_nothing() {
+        struct Push(Vec<bool>);
+        impl System for Push {
+            type Data = (Option<(Write<Pos>, Read<Vel>)>, Read<Pos>);
+            fn run(&mut self, (push, _): (Option<(&mut Pos, &Vel)>, &Pos)) {
+                self.0.push(push.is_some());
+            }
+        }
+
+        let mut world = World::default();
+        let still = world.create_entity().with(Pos { x: 1.0 }).build();
+        let tick = world.increment_change_tick();
+
+        let mut push = Push(Vec::new());
+        world.dispatch_system(&mut push);
+        assert_eq!(push.0, vec![false]);
+        assert_eq!(world.changes_since(tick).count(), 0);
+        assert_eq!(
+            world.get_component_for_entity::<Pos>(&still),
+            Ok(&Pos { x: 1.0 })
+        );
+    }
+
+    #[test]
+    fn test_optional_filters_dont_count_as_writes() {
+        struct Nudge(usize);
+        impl System for Nudge {
+            type Data = Option<(Write<Pos>, Changed<Pos>)>;
+            fn run(&mut self, nudge: Option<(&mut Pos, ())>) {
+                if let Some((pos, _)) = nudge {
+                    pos.x += 1.0;
+                    self.0 += 1;
+                }
+            }
+        }
+
+        let mut world = World::default();
+        world.create_entity().with(Pos { x: 1.0 }).build();
+        let mut nudge = Nudge(0);
+        world.dispatch_system(&mut nudge);
+        let tick = world.increment_change_tick();
+
+        world.dispatch_system(&mut nudge);
+        assert_eq!(nudge.0, 1);
+        assert_eq!(world.changes_since(tick).count(), 0);
+    }
+
+    #[test]
+    fn test_with_does_not_borrow() {
+        struct Count(usize);
+        impl System for Count {
+            type Data = (With<Vel>, Vel, Option<Frozen>);
+            fn run(&mut self, (_, vel, frozen): ((), &mut Vel, Option<&mut Frozen>)) {
+                if frozen.is_none() {
+                    vel.x = 0.0;
+                }
+                self.0 += 1;
+            }
+        }
+
+        let mut world = World::default();
+        let e = world.create_entity().with(Vel { x: 1.0 }).build();
+        world
+            .create_entity()
+            .with(Vel { x: 1.0 })
+            .with(Frozen)
+            .build();
+        world.create_entity().with(Pos { x: 1.0 }).build();
+
+        let mut count = Count(0);
+        world.dispatch_system(&mut count);
+        assert_eq!(count.0, 2);
+        assert_eq!(
+            world.get_component_for_entity::<Vel>(&e),
+            Ok(&Vel { x: 0.0 })
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_system_access {
+
+    use crate::{Access, Commands, Component, Eid, Read, System, With, Without, World, Write};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos {
+        x: f64,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Vel {
+        x: f64,
+    }
+
+    impl Component for Pos {}
+    impl Component for Vel {}
+
+    #[test]
+    fn test_read_and_write() {
+        struct Movement;
+        impl System for Movement {
+            type Data = (Write<Pos>, Read<Vel>);
+            fn run(&mut self, (pos, vel): (&mut Pos, &Vel)) {
+                pos.x += vel.x;
+            }
+        }
+
+        struct Sum(f64);
+        impl System for Sum {
+            type Data = (Read<Pos>, Read<Pos>);
+            fn run(&mut self, (a, b): (&Pos, &Pos)) {
+                self.0 += a.x + b.x;
+            }
+        }
+
+        struct Aliased(usize);
+        impl System for Aliased {
+            type Data = (Read<Pos>, Write<Pos>);
+            fn run(&mut self, _: (&Pos, &mut Pos)) {
+                self.0 += 1;
+            }
+        }
+
+        let mut world = World::default();
+        let e = world
+            .create_entity()
+            .with(Pos { x: 1.0 })
+            .with(Vel { x: 2.0 })
+            .build();
+        world.dispatch_system(&mut Movement);
+        assert_eq!(
+            world.get_component_for_entity::<Pos>(&e),
+            Ok(&Pos { x: 3.0 })
+        );
+
+        let mut sum = Sum(0.0);
+        world.dispatch_system(&mut sum);
+        assert_eq!(sum.0, 6.0);
+
+        let mut aliased = Aliased(0);
+        world.dispatch_system(&mut aliased);
+        assert_eq!(aliased.0, 0);
+    }
+
+    #[test]
+    fn test_access_sets() {
+        let access = Access::of::<(Read<Pos>, Option<Write<Vel>>, With<Vel>)>();
+        assert!(access.is_read::<Pos>());
+        assert!(!access.is_written::<Pos>());
+        assert!(access.is_written::<Vel>());
+        assert_eq!(access.reads().count(), 1);
+        assert_eq!(access.writes().count(), 1);
+
+        assert_eq!(Access::of::<Pos>(), Access::of::<Write<Pos>>());
+        let without = Access::of::<Without<Pos>>();
+        assert_eq!(without.reads().count() + without.writes().count(), 0);
+        assert!(!without.conflicts_with(&Access::of::<Pos>()));
+        assert!(without.fetches_entities());
+        assert!(Access::of::<Eid>().fetches_entities());
+        assert!(!Access::of::<Commands>().fetches_entities());
+
+        let readers = Access::of::<(Read<Pos>, Read<Vel>)>();
+        assert!(!readers.conflicts_with(&Access::of::<Read<Pos>>()));
+        assert!(readers.conflicts_with(&Access::of::<Write<Vel>>()));
+        assert!(Access::of::<Pos>().conflicts_with(&Access::of::<Pos>()));
+        assert!(!Access::of::<Pos>().conflicts_with(&Access::of::<Vel>()));
+
+        // Commands change the `World` between systems.
+        let spawner = Access::of::<(Read<Pos>, Commands)>();
+        assert!(spawner.queues_commands());
+        assert!(spawner.conflicts_with(&Access::of::<Vel>()));
+        assert!(Access::default().conflicts_with(&spawner));
+    }
+}
+
+#[cfg(test)]
+mod test_system_tuples {
+
+    use crate::{Component, Eid, System, SystemData, World};
+
+    macro_rules! components {
+        ($($name:ident),+) => {
+            $(
+                #[derive(Debug, Clone, Copy)]
+                struct $name(u32);
+                impl Component for $name {}
+            )+
+        };
+    }
+
+    components!(C0, C1, C2, C3, C4, C5, C6, C7, C8, C9, C10, C11);
+
+    /// Creates an entity with every component and one without C0, which no tuple
+    /// below matches.
+    fn world() -> (World, Eid, Eid) {
+        let mut world = World::default();
+        let full = world
+            .create_entity()
+            .with(C0(0))
+            .with(C1(0))
+            .with(C2(0))
+            .with(C3(0))
+            .with(C4(0))
+            .with(C5(0))
+            .with(C6(0))
+            .with(C7(0))
+            .with(C8(0))
+            .with(C9(0))
+            .with(C10(0))
+            .with(C11(0))
+            .build();
+        let partial = world
+            .create_entity()
+            .with(C1(0))
+            .with(C2(0))
+            .with(C3(0))
+            .with(C4(0))
+            .with(C5(0))
+            .with(C6(0))
+            .with(C7(0))
+            .with(C8(0))
+            .with(C9(0))
+            .with(C10(0))
+            .with(C11(0))
+            .build();
+        (world, full, partial)
+    }
+
+    /// Returns the values of every component of an entity.
+    fn values(world: &World, e: &Eid) -> Vec<u32> {
+        vec![
+            world.get_component_for_entity::<C0>(e).map_or(0, |c| c.0),
+            world.get_component_for_entity::<C1>(e).map_or(0, |c| c.0),
+            world.get_component_for_entity::<C2>(e).map_or(0, |c| c.0),
+            world.get_component_for_entity::<C3>(e).map_or(0, |c| c.0),
+            world.get_component_for_entity::<C4>(e).map_or(0, |c| c.0),
+            world.get_component_for_entity::<C5>(e).map_or(0, |c| c.0),
+            world.get_component_for_entity::<C6>(e).map_or(0, |c| c.0),
+            world.get_component_for_entity::<C7>(e).map_or(0, |c| c.0),
+            world.get_component_for_entity::<C8>(e).map_or(0, |c| c.0),
+            world.get_component_for_entity::<C9>(e).map_or(0, |c| c.0),
+            world.get_component_for_entity::<C10>(e).map_or(0, |c| c.0),
+            world.get_component_for_entity::<C11>(e).map_or(0, |c| c.0),
+        ]
+    }
+
+    macro_rules! arity_test {
+        ($test:ident, $arity:expr, $($name:ident $var:ident),+) => {
+            #[test]
+            fn $test() {
+                struct Increment;
+                impl System for Increment {
+                    type Data = ($($name,)+);
+                    fn run(&mut self, ($($var,)+): <Self::Data as SystemData>::Item<'_>) {
+                        $($var.0 += 1;)+
+                    }
+                }
+
+                let (mut world, full, partial) = world();
+                world.dispatch_system(&mut Increment);
+
+                let mut expected = vec![0; 12];
+                for v in expected.iter_mut().take($arity) {
+                    *v = 1;
+                }
+                assert_eq!(values(&world, &full), expected);
+                assert_eq!(values(&world, &partial), vec![0; 12]);
+            }
+        };
+    }
+
+    arity_test!(test_arity_1, 1, C0 a);
+    arity_test!(test_arity_2, 2, C0 a, C1 b);
+    arity_test!(test_arity_3, 3, C0 a, C1 b, C2 c);
+    arity_test!(test_arity_4, 4, C0 a, C1 b, C2 c, C3 d);
+    arity_test!(test_arity_5, 5, C0 a, C1 b, C2 c, C3 d, C4 e);
+    arity_test!(test_arity_6, 6, C0 a, C1 b, C2 c, C3 d, C4 e, C5 f);
+    arity_test!(test_arity_7, 7, C0 a, C1 b, C2 c, C3 d, C4 e, C5 f, C6 g);
+    arity_test!(test_arity_8, 8, C0 a, C1 b, C2 c, C3 d, C4 e, C5 f, C6 g, C7 h);
+    arity_test!(test_arity_9, 9, C0 a, C1 b, C2 c, C3 d, C4 e, C5 f, C6 g, C7 h, C8 i);
+    arity_test!(test_arity_10, 10, C0 a, C1 b, C2 c, C3 d, C4 e, C5 f, C6 g, C7 h, C8 i, C9 j);
+    arity_test!(test_arity_11, 11, C0 a, C1 b, C2 c, C3 d, C4 e, C5 f, C6 g, C7 h, C8 i, C9 j, C10 k);
+    arity_test!(test_arity_12, 12, C0 a, C1 b, C2 c, C3 d, C4 e, C5 f, C6 g, C7 h, C8 i, C9 j, C10 k, C11 l);
+
+    #[test]
+    fn test_nested_tuples() {
+        struct Nested;
+        impl System for Nested {
+            type Data = (C0, (C1, C2));
+            fn run(&mut self, (a, (b, c)): (&mut C0, (&mut C1, &mut C2))) {
+                a.0 = b.0 + c.0 + 7;
+            }
+        }
+
+        let (mut world, full, _) = world();
+        world.dispatch_system(&mut Nested);
+        assert_eq!(values(&world, &full)[0], 7);
+    }
+}
+
+#[cfg(test)]
+mod test_par_system {
+
+    use crate::{
+        Commands, Component, Eid, ParSystem, Read, Res, ResMut, System, SystemData, World, Write,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos(i64);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Vel(i64);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Child(Eid);
+
+    impl Component for Pos {}
+    impl Component for Vel {}
+    impl Component for Child {}
+
+    struct Gravity(i64);
+
+    #[derive(Default)]
+    struct Physics {
+        visited: AtomicUsize,
+    }
+
+    impl ParSystem for Physics {
+        type Data = (Eid, Write<Pos>, Read<Vel>, Res<Gravity>, Commands);
+
+        fn run(&self, (eid, pos, vel, gravity, commands): <Self::Data as SystemData>::Item<'_>) {
+            pos.0 += vel.0 - gravity.0;
+            if pos.0 % 7 == 0 {
+                commands.spawn().with(Child(eid));
+            }
+            self.visited.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    impl System for Physics {
+        type Data = <Self as ParSystem>::Data;
+
+        fn run(&mut self, data: <Self::Data as SystemData>::Item<'_>) {
+            ParSystem::run(&*self, data);
+        }
+    }
+
+    fn world() -> World {
+        let mut world = World::default();
+        world.insert_resource(Gravity(1));
+        for i in 0..1000 {
+            let builder = world.create_entity().with(Pos(i));
+            if i % 5 != 0 {
+                builder.with(Vel(i % 11)).build();
+            } else {
+                builder.build();
+            }
+        }
+        world
+    }
+
+    fn state(world: &World) -> Vec<(Option<Pos>, Option<Child>)> {
+        (0..2000)
+            .map(|i| Eid::new(i, 0))
+            .filter(|eid| world.is_alive(eid))
+            .map(|eid| {
+                (
+                    world.get_component_for_entity::<Pos>(&eid).ok().cloned(),
+                    world.get_component_for_entity::<Child>(&eid).ok().cloned(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_par_dispatch_matches_dispatch() {
+        let mut sequential = world();
+        let mut physics = Physics::default();
+        sequential.dispatch_system(&mut physics);
+        sequential.dispatch_system(&mut physics);
+
+        for chunks in [1, 3, 8, 5000] {
+            let mut parallel = world();
+            let par_physics = Physics::default();
+            parallel.par_dispatch_system_in_chunks(&par_physics, chunks);
+            parallel.par_dispatch_system_in_chunks(&par_physics, chunks);
+            assert_eq!(state(&parallel), state(&sequential));
+            assert_eq!(par_physics.visited.load(Ordering::SeqCst), 1600);
+        }
+
+        let mut parallel = world();
+        parallel.par_dispatch_system(&Physics::default());
+        let mut sequential = world();
+        sequential.dispatch_system(&mut Physics::default());
+        assert_eq!(state(&parallel), state(&sequential));
+    }
+
+    #[test]
+    fn test_resources_are_shared() {
+        struct Count(AtomicUsize);
+        impl ParSystem for Count {
+            type Data = (Read<Pos>, Res<Gravity>);
+            fn run(&self, (_, gravity): (&Pos, &Gravity)) {
+                self.0.fetch_add(gravity.0 as usize, Ordering::SeqCst);
+            }
+        }
+
+        let mut world = world();
+        let count = Count(AtomicUsize::new(0));
+        world.par_dispatch_system_in_chunks(&count, 4);
+        assert_eq!(count.0.load(Ordering::SeqCst), 1000);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_resources_cant_be_written() {
+        struct Count;
+        impl ParSystem for Count {
+            type Data = (Read<Pos>, ResMut<Gravity>);
+            fn run(&self, (_, gravity): (&Pos, &mut Gravity)) {
+                gravity.0 += 1;
+            }
+        }
+
+        world().par_dispatch_system_in_chunks(&Count, 4);
     }
 }
@@ -0,0 +1,210 @@
+use crate::{Schedule, ScheduleError, World};
+use std::time::Duration;
+
+/// The tick a `TickLoop` is simulating, inserted as a resource before every step so
+/// systems can read it with `Res<Tick>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Tick(pub u64);
+
+/// Runs a `Schedule` at a fixed timestep, however much wall time passes between calls
+/// to `TickLoop::advance`. Time which isn't enough for a whole step is carried over to the
+/// next call, and the fraction of a step it makes up is the `alpha` to render with.
+///
+/// # Example
+/// ```
+/// extern crate ecsnap;
+/// use ecsnap::{Component, Res, Schedule, System, Tick, TickLoop, World};
+/// use std::time::Duration;
+///
+/// #[derive(Debug, Clone, Copy, PartialEq)]
+/// struct Spawned(u64);
+/// impl Component for Spawned {}
+///
+/// struct Age;
+/// impl System for Age {
+///     type Data = (Spawned, Res<Tick>);
+///     fn run(&mut self, (spawned, tick): (&mut Spawned, &Tick)) {
+///         assert!(spawned.0 < tick.0);
+///     }
+/// }
+///
+/// let mut world = World::default();
+/// world.create_entity().with(Spawned(0)).build();
+/// let mut schedule = Schedule::default();
+/// schedule.add_system("age", Age).build().unwrap();
+///
+/// let mut ticks = TickLoop::new(Duration::from_millis(20), 5);
+/// assert_eq!(ticks.advance(Duration::from_millis(50), &mut world, &mut schedule), Ok(2));
+/// assert_eq!(ticks.tick(), 2);
+/// assert_eq!(world.resource::<Tick>(), Ok(&Tick(2)));
+/// assert!((ticks.alpha() - 0.5).abs() < 1e-9);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct TickLoop {
+    timestep: Duration,
+    max_steps: u32,
+    accumulator: Duration,
+    tick: u64,
+    dropped: u64,
+}
+
+impl TickLoop {
+    /// Creates a `TickLoop` which steps every `timestep` and at most `max_steps` times per
+    /// call to `TickLoop::advance`, so that a slow step can't make the next call run even
+    /// more steps.
+    pub fn new(timestep: Duration, max_steps: u32) -> Self {
+        assert!(!timestep.is_zero(), "timestep must not be zero");
+        assert!(max_steps > 0, "max_steps must not be zero");
+        TickLoop {
+            timestep,
+            max_steps,
+            accumulator: Duration::ZERO,
+            tick: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Returns the wall time simulated by a step.
+    pub fn timestep(&self) -> Duration {
+        self.timestep
+    }
+
+    /// Returns the last tick which was simulated, 0 before the first step.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Returns the number of steps which were dropped because a call to
+    /// `TickLoop::advance` would have run more than `max_steps`.
+    pub fn dropped_steps(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Returns how far the wall time is between the last tick and the next one, from 0
+    /// up to but excluding 1, to interpolate what is rendered.
+    pub fn alpha(&self) -> f64 {
+        self.accumulator.as_secs_f64() / self.timestep.as_secs_f64()
+    }
+
+    /// Adds the wall time which elapsed and runs the schedule once per whole timestep.
+    /// Before each step, the tick is advanced and inserted into the `World` as a `Tick`
+    /// resource, and after it `World::end_tick` is called. Steps past `max_steps` are
+    /// dropped. Returns the number of steps run, or fails without running any if the
+    /// schedule can't be ordered.
+    pub fn advance(
+        &mut self,
+        elapsed: Duration,
+        world: &mut World,
+        schedule: &mut Schedule,
+    ) -> Result<u32, ScheduleError> {
+        schedule.order()?;
+        self.accumulator += elapsed;
+        let mut steps = 0;
+        while self.accumulator >= self.timestep {
+            if steps == self.max_steps {
+                let timestep = self.timestep.as_nanos();
+                let behind = self.accumulator.as_nanos();
+                self.dropped += (behind / timestep) as u64;
+                self.accumulator = Duration::from_nanos((behind % timestep) as u64);
+                break;
+            }
+            self.accumulator -= self.timestep;
+            self.tick += 1;
+            world.insert_resource(Tick(self.tick));
+            schedule.run(world)?;
+            world.end_tick();
+            steps += 1;
+        }
+        Ok(steps)
+    }
+}
+
+#[cfg(test)]
+mod test_tick {
+
+    use crate::{
+        Res, ResMut, Schedule, ScheduleError, StatsCollector, System, Tick, TickLoop, World,
+    };
+    use std::time::Duration;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Seen(Vec<u64>);
+
+    struct Record;
+    impl System for Record {
+        type Data = (ResMut<Seen>, Res<Tick>);
+        fn run(&mut self, (seen, tick): (&mut Seen, &Tick)) {
+            seen.0.push(tick.0);
+        }
+    }
+
+    fn setup() -> (World, Schedule) {
+        let mut world = World::default();
+        world.insert_resource(Seen::default());
+        let mut schedule = Schedule::default();
+        schedule.add_system("record", Record).build().unwrap();
+        (world, schedule)
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_accumulates() {
+        let (mut world, mut schedule) = setup();
+        let mut ticks = TickLoop::new(ms(10), 8);
+
+        assert_eq!(ticks.advance(ms(4), &mut world, &mut schedule), Ok(0));
+        assert!((ticks.alpha() - 0.4).abs() < 1e-9);
+        assert_eq!(ticks.advance(ms(4), &mut world, &mut schedule), Ok(0));
+        assert_eq!(ticks.advance(ms(4), &mut world, &mut schedule), Ok(1));
+        assert!((ticks.alpha() - 0.2).abs() < 1e-9);
+        assert_eq!(ticks.advance(ms(30), &mut world, &mut schedule), Ok(3));
+        assert_eq!(ticks.tick(), 4);
+        assert_eq!(world.resource::<Seen>(), Ok(&Seen(vec![1, 2, 3, 4])));
+    }
+
+    #[test]
+    fn test_spiral_of_death() {
+        let (mut world, mut schedule) = setup();
+        world.set_diagnostics(StatsCollector::default());
+        let mut ticks = TickLoop::new(ms(10), 3);
+
+        assert_eq!(ticks.advance(ms(105), &mut world, &mut schedule), Ok(3));
+        assert_eq!(ticks.tick(), 3);
+        assert_eq!(ticks.dropped_steps(), 7);
+        assert!((ticks.alpha() - 0.5).abs() < 1e-9);
+        assert_eq!(ticks.advance(ms(5), &mut world, &mut schedule), Ok(1));
+        assert_eq!(ticks.tick(), 4);
+
+        let stats = world.diagnostics::<StatsCollector>().unwrap();
+        assert_eq!(stats.ticks(), 4);
+    }
+
+    #[test]
+    fn test_unordered_schedule() {
+        let (mut world, mut schedule) = setup();
+        schedule
+            .add_system("cycle", Record)
+            .before("record")
+            .after("record")
+            .build()
+            .unwrap();
+        let mut ticks = TickLoop::new(ms(10), 3);
+
+        assert!(matches!(
+            ticks.advance(ms(25), &mut world, &mut schedule),
+            Err(ScheduleError::Cycle(_))
+        ));
+        assert_eq!(ticks.tick(), 0);
+        assert_eq!(ticks.alpha(), 0.0);
+        assert!(world.resource::<Tick>().is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zero_timestep() {
+        TickLoop::new(Duration::ZERO, 1);
+    }
+}
@@ -1,17 +1,123 @@
-use crate::{Component, Eid, Entity, EntityBuilder, System, SystemData};
-use std::any::TypeId;
-use std::collections::{HashMap, HashSet};
+use crate::change::ChangeTicks;
+use crate::commands::Command;
+use crate::delta::same_encoding;
+use crate::entity::Entities;
+use crate::removal::Removals;
+use crate::resource::Resources;
+use crate::snapshot::SnapshotEntity;
+use crate::storage::{Backend, Storage};
+use crate::system::{chunks as chunks_of, once, slots, split, Ran, Slot, View};
+use crate::{
+    Access, Commands, Component, ComponentInfo, ComponentRegistry, Diagnostics, Eid, Entity,
+    EntityBuilder, ParSystem, Resource, Snapshot, System, SystemData, Tombstones,
+};
+use std::any::{type_name, Any, TypeId};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+use std::num::NonZeroUsize;
+use std::thread;
+use std::time::Instant;
+
+/// Error returned when the `World` can't do what was asked of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcsError {
+    /// The entity doesn't exist, either because it was never created or because it was
+    /// destroyed.
+    NoSuchEntity(Eid),
+    /// The entity doesn't have a component of the named type.
+    MissingComponent(&'static str),
+    /// The named component type isn't registered with the `World`.
+    UnregisteredComponent(&'static str),
+    /// The `World` doesn't have a resource of the named type.
+    MissingResource(&'static str),
+}
+
+impl EcsError {
+    fn missing_component<C: Component>() -> Self {
+        EcsError::MissingComponent(type_name::<C>())
+    }
+}
+
+impl fmt::Display for EcsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EcsError::NoSuchEntity(eid) => write!(
+                f,
+                "entity {}v{} doesn't exist",
+                eid.index(),
+                eid.generation()
+            ),
+            EcsError::MissingComponent(name) => {
+                write!(f, "entity doesn't have a component {}", name)
+            }
+            EcsError::UnregisteredComponent(name) => {
+                write!(f, "component {} isn't registered", name)
+            }
+            EcsError::MissingResource(name) => write!(f, "resource {} doesn't exist", name),
+        }
+    }
+}
+
+impl Error for EcsError {}
 
 /// A container for all the `Entities`.
 #[derive(Debug, Default)]
 pub struct World {
-    component_ids: HashSet<TypeId>,
-    entities: HashMap<Eid, Entity>,
-    next_entity_id: Eid,
+    registry: ComponentRegistry,
+    entities: Entities,
+    storage: Storage,
+    resources: Resources,
+    ticks: ChangeTicks,
+    removals: Removals,
+    change_tick: u64,
+    last_runs: HashMap<&'static str, u64>,
+    diagnostics: Box<dyn Diagnostics>,
 }
 
 impl World {
-    /// Registers a component which can be used by a system (TODO).
+    /// Creates an empty `World` which stores components with the `Backend` given.
+    /// `World::default` uses `Backend::SparseSet`.
+    pub fn with_backend(backend: Backend) -> Self {
+        World {
+            storage: Storage::new(backend),
+            ..World::default()
+        }
+    }
+
+    /// Returns the `Backend` the `World` stores components with.
+    pub fn backend(&self) -> Backend {
+        self.storage.backend()
+    }
+
+    /// Sets the `Diagnostics` told about every `System` the `World` runs and returns the
+    /// previous ones. A `World` starts with `NoDiagnostics`.
+    pub fn set_diagnostics<D: Diagnostics>(&mut self, diagnostics: D) -> Box<dyn Diagnostics> {
+        mem::replace(&mut self.diagnostics, Box::new(diagnostics))
+    }
+
+    /// Gets a reference to the `Diagnostics` of the `World` if they are a D.
+    pub fn diagnostics<D: Diagnostics>(&self) -> Option<&D> {
+        let diagnostics: &dyn Any = &*self.diagnostics;
+        diagnostics.downcast_ref::<D>()
+    }
+
+    /// Gets a mutable reference to the `Diagnostics` of the `World` if they are a D.
+    pub fn diagnostics_mut<D: Diagnostics>(&mut self) -> Option<&mut D> {
+        let diagnostics: &mut dyn Any = &mut *self.diagnostics;
+        diagnostics.downcast_mut::<D>()
+    }
+
+    /// Tells the `Diagnostics` that every system of a tick ran.
+    pub fn end_tick(&mut self) {
+        self.diagnostics.tick_ended();
+    }
+
+    /// Registers a component to be replicated with the next unused `ComponentId`. Only
+    /// registered components are copied into a `Snapshot` of the `World`. Returns false if
+    /// the component was already registered. Use `World::registry_mut` to register a
+    /// component with an explicit id, name or encoding.
     ///
     /// # Example
     /// ```
@@ -30,7 +136,18 @@ impl World {
     /// world.register_component::<Pos>();
     /// ```
     pub fn register_component<C: Component>(&mut self) -> bool {
-        self.component_ids.insert(TypeId::of::<C>())
+        self.registry.register::<C>().build().is_ok()
+    }
+
+    /// Returns the `ComponentRegistry` of the `World`.
+    pub fn registry(&self) -> &ComponentRegistry {
+        &self.registry
+    }
+
+    /// Returns the `ComponentRegistry` of the `World` so that components can be
+    /// registered with options.
+    pub fn registry_mut(&mut self) -> &mut ComponentRegistry {
+        &mut self.registry
     }
 
     /// Creates an `EntityBuilder` to start creating an `Entity`. Calling .build() on the
@@ -53,52 +170,488 @@ impl World {
     ///     .with(Pos { x: 0.0, y: 0.0 })
     ///     .build();
     /// ```
-    pub fn create_entity<'a>(&mut self) -> EntityBuilder {
+    pub fn create_entity(&mut self) -> EntityBuilder<'_> {
         EntityBuilder::new(self)
     }
 
     pub(crate) fn insert_entity(&mut self, e: Entity) -> Eid {
-        let id = self.next_entity_id;
-        self.entities.insert(id, e);
-        self.next_entity_id += 1;
-        id
+        let eid = self.entities.insert();
+        for type_id in e.components.keys() {
+            self.ticks.insert(*type_id, eid.index(), self.change_tick);
+        }
+        self.storage.insert_entity(eid.index(), e);
+        eid
+    }
+
+    /// Returns true if the entity exists. An `Eid` of a destroyed entity is never alive
+    /// again, even after its slot is reused.
+    pub fn is_alive(&self, entity: &Eid) -> bool {
+        self.entities.contains(entity)
+    }
+
+    /// Adds a component to an `Entity`. If the `Entity` already had a component of type
+    /// C, the component is replaced and the old one is returned.
+    ///
+    /// # Example
+    /// ```
+    /// extern crate ecsnap;
+    /// use ecsnap::{Component, EcsError, World};
+    ///
+    /// #[derive(Debug, Clone, Copy, PartialEq)]
+    /// struct Pos {
+    ///     x: f64,
+    ///     y: f64,
+    /// }
+    /// impl Component for Pos {}
+    ///
+    /// let mut world = World::default();
+    /// let e = world.create_entity().build();
+    /// assert_eq!(world.add_component_to_entity(&e, Pos { x: 1.0, y: 0.0 }), Ok(None));
+    ///
+    /// world.destroy_entity(&e).unwrap();
+    /// assert_eq!(
+    ///     world.add_component_to_entity(&e, Pos { x: 1.0, y: 0.0 }),
+    ///     Err(EcsError::NoSuchEntity(e))
+    /// );
+    /// ```
+    pub fn add_component_to_entity<C: Component>(
+        &mut self,
+        entity: &Eid,
+        component: C,
+    ) -> Result<Option<Box<C>>, EcsError> {
+        let index = self.index(entity)?;
+        self.ticks
+            .insert(TypeId::of::<C>(), index, self.change_tick);
+        Ok(self.storage.insert(index, component).map(Box::new))
     }
 
-    /// Adds a component to an `Entity`
-    #[allow(dead_code)]
-    pub(crate) fn add_component_to_entity<C: Component>(
+    /// Sets the component C of an `Entity`, adding it if the `Entity` doesn't have one.
+    /// Unlike `World::add_component_to_entity`, the component is left untouched, and isn't
+    /// marked as changed, if it is equal to the new value. Returns true if the component
+    /// changed.
+    pub fn set_component_for_entity<C: Component + PartialEq>(
         &mut self,
         entity: &Eid,
         component: C,
-    ) -> Option<Box<C>> {
-        self.entities
-            .get_mut(entity)
-            .unwrap()
-            .add_component(component)
+    ) -> Result<bool, EcsError> {
+        let index = self.index(entity)?;
+        if self.storage.get::<C>(index) == Some(&component) {
+            return Ok(false);
+        }
+        self.ticks
+            .insert(TypeId::of::<C>(), index, self.change_tick);
+        self.storage.insert(index, component);
+        Ok(true)
+    }
+
+    /// Gets a reference to the component C of an `Entity`.
+    ///
+    /// # Example
+    /// ```
+    /// extern crate ecsnap;
+    /// use ecsnap::{Component, EcsError, World};
+    ///
+    /// #[derive(Debug, Clone, Copy, PartialEq)]
+    /// struct Pos {
+    ///     x: f64,
+    ///     y: f64,
+    /// }
+    /// impl Component for Pos {}
+    ///
+    /// #[derive(Debug, Clone, Copy, PartialEq)]
+    /// struct Vel {
+    ///     x: f64,
+    ///     y: f64,
+    /// }
+    /// impl Component for Vel {}
+    ///
+    /// let mut world = World::default();
+    /// let e = world.create_entity().with(Pos { x: 1.0, y: 2.0 }).build();
+    ///
+    /// assert_eq!(world.get_component_for_entity::<Pos>(&e), Ok(&Pos { x: 1.0, y: 2.0 }));
+    /// assert!(matches!(
+    ///     world.get_component_for_entity::<Vel>(&e),
+    ///     Err(EcsError::MissingComponent(_))
+    /// ));
+    /// ```
+    pub fn get_component_for_entity<C: Component>(&self, entity: &Eid) -> Result<&C, EcsError> {
+        self.storage
+            .get::<C>(self.index(entity)?)
+            .ok_or_else(EcsError::missing_component::<C>)
     }
 
-    #[allow(dead_code)]
-    pub(crate) fn get_component_for_entity<C: Component>(&self, entity: &Eid) -> Option<&C> {
-        self.entities.get(entity).unwrap().get_component::<C>()
+    /// Gets a mutable reference to the component C of an `Entity`, marking it as
+    /// changed.
+    pub fn get_mut_component_for_entity<C: Component>(
+        &mut self,
+        entity: &Eid,
+    ) -> Result<&mut C, EcsError> {
+        let index = self.index(entity)?;
+        self.ticks
+            .change(TypeId::of::<C>(), index, self.change_tick);
+        self.storage
+            .get_mut::<C>(index)
+            .ok_or_else(EcsError::missing_component::<C>)
     }
 
-    #[allow(dead_code)]
-    pub(crate) fn remove_component_from_entity<C: Component>(
+    /// Removes the component C from an `Entity` and returns it.
+    pub fn remove_component_from_entity<C: Component>(
         &mut self,
         entity: &Eid,
-    ) -> Option<Box<C>> {
-        self.entities
-            .get_mut(entity)
-            .unwrap()
-            .remove_component::<C>()
+    ) -> Result<Box<C>, EcsError> {
+        let index = self.index(entity)?;
+        let component = self
+            .storage
+            .remove::<C>(index)
+            .ok_or_else(EcsError::missing_component::<C>)?;
+        self.ticks.remove(TypeId::of::<C>(), index);
+        self.removals
+            .remove(*entity, TypeId::of::<C>(), self.change_tick);
+        Ok(Box::new(component))
+    }
+
+    /// Removes a component by type if the entity is alive and has one, recording the
+    /// removal.
+    fn remove_boxed(&mut self, entity: &Eid, type_id: TypeId) {
+        if self.entities.contains(entity)
+            && self.storage.remove_boxed(entity.index(), type_id).is_some()
+        {
+            self.ticks.remove(type_id, entity.index());
+            self.removals.remove(*entity, type_id, self.change_tick);
+        }
+    }
+
+    /// Removes an `Entity` and all of its components from the `World`. Its `Eid` is never
+    /// alive again.
+    ///
+    /// # Example
+    /// ```
+    /// extern crate ecsnap;
+    /// use ecsnap::{EcsError, World};
+    ///
+    /// let mut world = World::default();
+    /// let e = world.create_entity().build();
+    ///
+    /// assert!(world.destroy_entity(&e).is_ok());
+    /// assert_eq!(world.destroy_entity(&e).unwrap_err(), EcsError::NoSuchEntity(e));
+    /// ```
+    pub fn destroy_entity(&mut self, entity: &Eid) -> Result<Entity, EcsError> {
+        if self.entities.remove(entity) {
+            let removed = self.storage.remove_entity(entity.index());
+            self.ticks.remove_entity(entity.index());
+            self.removals.despawn(
+                *entity,
+                removed.components.keys().cloned(),
+                self.change_tick,
+            );
+            Ok(removed)
+        } else {
+            Err(EcsError::NoSuchEntity(*entity))
+        }
+    }
+
+    /// Gets the `ComponentInfo` the component C was registered with.
+    pub fn component_info<C: Component>(&self) -> Result<&ComponentInfo, EcsError> {
+        self.registry
+            .get_by_type(TypeId::of::<C>())
+            .ok_or(EcsError::UnregisteredComponent(type_name::<C>()))
+    }
+
+    /// Adds a resource to the `World`. If the `World` already had a resource of type R,
+    /// it is replaced and the old one is returned.
+    ///
+    /// # Example
+    /// ```
+    /// extern crate ecsnap;
+    /// use ecsnap::World;
+    ///
+    /// struct DeltaTime(f64);
+    ///
+    /// let mut world = World::default();
+    /// world.insert_resource(DeltaTime(1.0 / 60.0));
+    /// world.resource_mut::<DeltaTime>().unwrap().0 = 1.0 / 30.0;
+    /// assert_eq!(world.resource::<DeltaTime>().unwrap().0, 1.0 / 30.0);
+    /// ```
+    pub fn insert_resource<R: Resource>(&mut self, resource: R) -> Option<R> {
+        self.resources
+            .insert(TypeId::of::<R>(), Box::new(resource))
+            .and_then(|old| old.downcast::<R>().ok())
+            .map(|old| *old)
+    }
+
+    /// Gets a reference to the resource R.
+    pub fn resource<R: Resource>(&self) -> Result<&R, EcsError> {
+        self.resources
+            .get(&TypeId::of::<R>())
+            .and_then(|r| r.downcast_ref::<R>())
+            .ok_or(EcsError::MissingResource(type_name::<R>()))
+    }
+
+    /// Gets a mutable reference to the resource R.
+    pub fn resource_mut<R: Resource>(&mut self) -> Result<&mut R, EcsError> {
+        self.resources
+            .get_mut(&TypeId::of::<R>())
+            .and_then(|r| r.downcast_mut::<R>())
+            .ok_or(EcsError::MissingResource(type_name::<R>()))
+    }
+
+    /// Removes the resource R from the `World` and returns it.
+    pub fn remove_resource<R: Resource>(&mut self) -> Result<R, EcsError> {
+        self.resources
+            .remove(&TypeId::of::<R>())
+            .and_then(|r| r.downcast::<R>().ok())
+            .map(|r| *r)
+            .ok_or(EcsError::MissingResource(type_name::<R>()))
     }
 
-    #[allow(dead_code)]
-    pub(crate) fn destroy_entity(&mut self, entity: &Eid) -> Option<Entity> {
-        self.entities.remove(entity)
+    /// Returns the tick changes to components are currently recorded at.
+    pub fn change_tick(&self) -> u64 {
+        self.change_tick
     }
 
-    /// Runs a system on the `World`.
+    /// Advances the tick changes to components are recorded at and returns it. Every
+    /// change made from then on is returned by `World::changes_since` the returned tick.
+    /// Dispatching a `System` advances the tick on its own.
+    pub fn increment_change_tick(&mut self) -> u64 {
+        self.change_tick += 1;
+        self.change_tick
+    }
+
+    /// Iterates, in no particular order, over the `Eid` and component type of every
+    /// component which was added or changed at or after the tick.
+    ///
+    /// # Example
+    /// ```
+    /// extern crate ecsnap;
+    /// use ecsnap::{Component, World};
+    /// use std::any::TypeId;
+    ///
+    /// #[derive(Debug, Clone, Copy, PartialEq)]
+    /// struct Pos(f64);
+    /// impl Component for Pos {}
+    ///
+    /// let mut world = World::default();
+    /// let a = world.create_entity().with(Pos(0.0)).build();
+    /// let b = world.create_entity().with(Pos(0.0)).build();
+    ///
+    /// let tick = world.increment_change_tick();
+    /// world.get_mut_component_for_entity::<Pos>(&b).unwrap().0 = 1.0;
+    ///
+    /// let changes: Vec<_> = world.changes_since(tick).collect();
+    /// assert_eq!(changes, vec![(b, TypeId::of::<Pos>())]);
+    /// assert_eq!(world.changes_since(0).count(), 2);
+    /// # let _ = a;
+    /// ```
+    pub fn changes_since(&self, tick: u64) -> impl Iterator<Item = (Eid, TypeId)> + '_ {
+        self.ticks
+            .iter()
+            .filter(move |(_, _, ticks)| ticks.changed >= tick)
+            .filter_map(move |(type_id, index, _)| Some((self.entities.eid(index)?, type_id)))
+    }
+
+    /// Returns the entities despawned at or after the tick which weren't acknowledged yet.
+    pub fn despawned_since(&self, tick: u64) -> &[Eid] {
+        self.removals.despawned(tick)
+    }
+
+    /// Returns the entities which lost their component C, by having it removed or by
+    /// being despawned, at or after the tick and which weren't acknowledged yet.
+    pub fn removed_since<C: Component>(&self, tick: u64) -> &[Eid] {
+        self.removals.removed(TypeId::of::<C>(), tick)
+    }
+
+    /// Forgets the despawns and removals recorded before the tick, once every consumer
+    /// has seen them. The `World` keeps them until then, up to 65536 despawns and as many
+    /// removals of each component type, past which the oldest are forgotten anyway.
+    pub fn acknowledge_removals(&mut self, tick: u64) {
+        self.removals.acknowledge(tick);
+    }
+
+    /// Collects the entities despawned, and the registered components removed from
+    /// entities which are still alive, at or after the tick. Components which were added
+    /// again are left out.
+    pub fn tombstones_since(&self, tick: u64) -> Tombstones {
+        let mut despawned: Vec<Eid> = self.removals.despawned(tick).to_vec();
+        despawned.sort();
+        despawned.dedup();
+
+        let mut removed: Vec<(Eid, TypeId)> = self
+            .removals
+            .iter_removed(tick)
+            .filter(|(eid, type_id)| {
+                self.entities.contains(eid)
+                    && self.ticks.get(*type_id, eid.index()).is_none()
+                    && self.registry.get_by_type(*type_id).is_some()
+            })
+            .collect();
+        removed.sort_by_key(|(eid, type_id)| {
+            (
+                *eid,
+                self.registry.get_by_type(*type_id).map(|info| info.id()),
+            )
+        });
+        removed.dedup();
+        Tombstones { despawned, removed }
+    }
+
+    /// Applies `Tombstones` to the `World`, destroying the despawned entities and
+    /// removing the removed components. Entities which don't exist are skipped.
+    pub fn apply_tombstones(&mut self, tombstones: &Tombstones) {
+        for eid in tombstones.despawned() {
+            let _ = self.destroy_entity(&eid);
+        }
+        for (eid, type_id) in tombstones.removed() {
+            self.remove_boxed(&eid, type_id);
+        }
+    }
+
+    /// Advances the change tick for a `System` about to run and returns the tick its
+    /// filters look for changes since, along with the tick its writes are recorded at.
+    pub(crate) fn start_run(&mut self, system: &'static str) -> (u64, u64) {
+        let since = self.last_runs.get(system).map_or(0, |tick| tick + 1);
+        (since, self.increment_change_tick())
+    }
+
+    /// Marks the components a `System` wrote as changed at the tick it ran at and tells
+    /// the `Diagnostics` about the run.
+    pub(crate) fn finish_run(&mut self, system: &'static str, tick: u64, ran: Ran) {
+        for (index, type_id) in ran.written {
+            self.ticks.change(type_id, index, tick);
+        }
+        self.last_runs.insert(system, tick);
+        self.diagnostics
+            .system_ran(system, ran.matched, ran.elapsed);
+    }
+
+    /// Returns the index the components of an entity are stored at if it is alive.
+    fn index(&self, entity: &Eid) -> Result<u32, EcsError> {
+        if self.entities.contains(entity) {
+            Ok(entity.index())
+        } else {
+            Err(EcsError::NoSuchEntity(*entity))
+        }
+    }
+
+    /// Takes a `Snapshot` of every `Entity` in the `World` and its registered
+    /// components.
+    ///
+    /// # Example
+    /// ```
+    /// extern crate ecsnap;
+    /// use ecsnap::{Component, World};
+    ///
+    /// #[derive(Debug, Clone, Copy)]
+    /// struct Pos {
+    ///     x: f64,
+    ///     y: f64,
+    /// }
+    /// impl Component for Pos {}
+    ///
+    /// let mut world = World::default();
+    /// world.register_component::<Pos>();
+    /// let e = world.create_entity().with(Pos { x: 0.0, y: 0.0 }).build();
+    ///
+    /// let snapshot = world.snapshot(0);
+    /// assert!(snapshot.contains(e));
+    /// assert!(snapshot.get_component::<Pos>(e).is_some());
+    /// ```
+    pub fn snapshot(&self, tick: u64) -> Snapshot {
+        let mut snapshot = Snapshot::new(tick);
+        for eid in self.entities.iter() {
+            let mut snap_entity = SnapshotEntity::default();
+            for (type_id, component) in self.storage.components(eid.index()) {
+                if let Some(copy) = self
+                    .registry
+                    .get_by_type(type_id)
+                    .and_then(|info| (info.snapshot)(component))
+                {
+                    snap_entity.components.insert(type_id, copy);
+                }
+            }
+            snapshot.entities.insert(eid, snap_entity);
+        }
+        snapshot
+    }
+
+    /// Applies a `Snapshot` to the `World`. Entities in the `Snapshot` are created if
+    /// they don't exist yet, and have their registered components replaced by those in
+    /// the `Snapshot`. Components which are encoded the same as in the `Snapshot`, and
+    /// those which aren't registered, are left untouched and aren't marked as changed.
+    /// Entities which aren't in the `Snapshot` are destroyed.
+    ///
+    /// # Example
+    /// ```
+    /// extern crate ecsnap;
+    /// use ecsnap::{Component, World};
+    ///
+    /// #[derive(Debug, Clone, Copy)]
+    /// struct Pos {
+    ///     x: f64,
+    ///     y: f64,
+    /// }
+    /// impl Component for Pos {}
+    ///
+    /// let mut server = World::default();
+    /// server.register_component::<Pos>();
+    /// let e = server.create_entity().with(Pos { x: 4.0, y: 2.0 }).build();
+    ///
+    /// let mut client = World::default();
+    /// client.register_component::<Pos>();
+    /// client.apply_snapshot(&server.snapshot(0));
+    /// ```
+    pub fn apply_snapshot(&mut self, snapshot: &Snapshot) {
+        let stale: Vec<Eid> = self
+            .entities
+            .iter()
+            .filter(|eid| !snapshot.contains(*eid))
+            .collect();
+        for eid in stale {
+            let _ = self.destroy_entity(&eid);
+        }
+        for (eid, snap_entity) in snapshot.entities.iter() {
+            let index = eid.index();
+            if !self.entities.contains(eid) {
+                if let Some(replaced) = self.entities.eid(index) {
+                    let _ = self.destroy_entity(&replaced);
+                }
+                self.entities.insert_at(*eid);
+            }
+            let unchanged: Vec<TypeId> = self
+                .storage
+                .components(index)
+                .into_iter()
+                .filter(|(type_id, current)| {
+                    let info = self.registry.get_by_type(*type_id);
+                    match (info, snap_entity.components.get(type_id)) {
+                        (Some(info), Some(new)) => (info.snapshot)(*current)
+                            .is_some_and(|current| same_encoding(info, &*current, &**new)),
+                        _ => false,
+                    }
+                })
+                .map(|(type_id, _)| type_id)
+                .collect();
+            for info in self.registry.iter() {
+                let type_id = info.type_id();
+                if !snap_entity.components.contains_key(&type_id)
+                    && self.storage.remove_boxed(index, type_id).is_some()
+                {
+                    self.ticks.remove(type_id, index);
+                    self.removals.remove(*eid, type_id, self.change_tick);
+                }
+            }
+            for (type_id, component) in snap_entity.components.iter() {
+                if !unchanged.contains(type_id) {
+                    self.ticks.insert(*type_id, index, self.change_tick);
+                    self.storage
+                        .insert_boxed(index, *type_id, component.clone());
+                }
+            }
+        }
+    }
+
+    /// Runs a system on the `World`. `Changed` and `Added` filters see the changes made
+    /// since the last time a system of the same type was dispatched this way, use
+    /// `World::dispatch_labeled_system` to track several systems of one type apart.
     ///
     /// # Example
     /// ```
@@ -126,8 +679,7 @@ impl World {
     ///
     /// impl System for MovementSystem {
     ///     type Data = (Pos, Vel);
-    ///     fn run(&mut self, data: &mut Self::Data){
-    ///         let (pos, vel) = data;
+    ///     fn run(&mut self, (pos, vel): (&mut Pos, &mut Vel)) {
     ///         pos.x += vel.x * self.dt;
     ///         pos.y += vel.y * self.dt;
     ///         println!("Updated Position! {:?}", pos);
@@ -154,12 +706,201 @@ impl World {
     ///
     /// ```
     pub fn dispatch_system<S: System>(&mut self, sys: &mut S) {
-        for entity in self.entities.values_mut() {
-            if let Some(data) = S::Data::fetch(entity) {
-                let mut new_data = data.clone();
-                sys.run(&mut new_data);
-                println!("Manipulated Data: {:?}", new_data);
-                entity.set::<S>(new_data);
+        self.dispatch_labeled_system(type_name::<S>(), sys);
+    }
+
+    /// Runs a system on the `World` under a label, which `Changed` and `Added` filters
+    /// and the `Diagnostics` know it by. Every label gets its own view of which changes
+    /// were already seen, so that each of several systems of the same type sees them
+    /// all. `Schedule` runs its systems under their labels this way.
+    ///
+    /// # Example
+    /// ```
+    /// extern crate ecsnap;
+    /// use ecsnap::{Changed, Component, System, World};
+    ///
+    /// #[derive(Debug, Clone, Copy, PartialEq)]
+    /// struct Health(u8);
+    /// impl Component for Health {}
+    ///
+    /// struct Hurt(usize);
+    /// impl System for Hurt {
+    ///     type Data = (Health, Changed<Health>);
+    ///     fn run(&mut self, _: (&mut Health, ())) {
+    ///         self.0 += 1;
+    ///     }
+    /// }
+    ///
+    /// let mut world = World::default();
+    /// world.create_entity().with(Health(3)).build();
+    /// let (mut sound, mut screen) = (Hurt(0), Hurt(0));
+    /// world.dispatch_labeled_system("sound", &mut sound);
+    /// world.dispatch_labeled_system("screen", &mut screen);
+    /// assert_eq!((sound.0, screen.0), (1, 1));
+    /// ```
+    pub fn dispatch_labeled_system<S: System>(&mut self, system: &'static str, sys: &mut S) {
+        let (since, tick) = self.start_run(system);
+        let start = Instant::now();
+        let access = Access::of::<S::Data>();
+        let observe = self.diagnostics.observes_entities() && access.fetches_entities();
+        let mut commands = Commands::default();
+        let view = View {
+            rows: self
+                .storage
+                .borrow(&self.entities, &access, S::Data::matches),
+            resources: slots(&mut self.resources)
+                .into_iter()
+                .filter(|(type_id, _)| access.uses_resource(type_id))
+                .collect(),
+            ticks: Some(&self.ticks),
+            removals: Some(&self.removals),
+        };
+        let diagnostics = &mut self.diagnostics;
+        let mut ran = view.run::<S::Data, _>(&mut commands, since, |eid, data| {
+            if observe {
+                diagnostics.before_entity(system, eid);
+            }
+            sys.run(data);
+            if observe {
+                diagnostics.after_entity(system, eid);
+            }
+        });
+        ran.elapsed = start.elapsed();
+        self.finish_run(system, tick, ran);
+        self.increment_change_tick();
+        self.apply_commands(commands);
+    }
+
+    /// Runs a `ParSystem` on the `World`, splitting the entities into one chunk per
+    /// available thread. Commands queued from each chunk are applied in chunk order once
+    /// every chunk is done, so they apply in the same order as with
+    /// `World::dispatch_system`.
+    ///
+    /// # Panics
+    /// Panics if the `ParSystem` writes a resource.
+    pub fn par_dispatch_system<S: ParSystem>(&mut self, sys: &S) {
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        self.par_dispatch_system_in_chunks(sys, threads);
+    }
+
+    /// Runs a `ParSystem` on the `World`, splitting the entities into at most `chunks`
+    /// chunks of about the same size which each run on their own thread.
+    ///
+    /// # Panics
+    /// Panics if the `ParSystem` writes a resource.
+    pub fn par_dispatch_system_in_chunks<S: ParSystem>(&mut self, sys: &S, chunks: usize) {
+        let access = Access::of::<S::Data>();
+        assert!(
+            access.resource_writes().next().is_none(),
+            "a ParSystem can't write resources"
+        );
+        let (since, tick) = self.start_run(type_name::<S>());
+        let start = Instant::now();
+        let rows = self
+            .storage
+            .borrow(&self.entities, &access, S::Data::matches);
+        let total: usize = rows.iter().map(|rows| rows.eids.len()).sum();
+        let size = total.div_ceil(chunks.max(1)).max(1);
+        let mut views = Vec::new();
+        for chunk in chunks_of(rows, size) {
+            let resources = self
+                .resources
+                .iter()
+                .filter(|(type_id, _)| access.uses_resource(type_id))
+                .map(|(type_id, r)| (*type_id, Slot::Shared(&**r)))
+                .collect();
+            views.push(View {
+                rows: chunk,
+                resources,
+                ticks: Some(&self.ticks),
+                removals: Some(&self.removals),
+            });
+        }
+
+        let mut commands: Vec<Commands> = views.iter().map(|_| Commands::default()).collect();
+        let mut ran = Ran::default();
+        thread::scope(|scope| {
+            let threads: Vec<_> = views
+                .into_iter()
+                .zip(commands.iter_mut())
+                .map(|(view, commands)| {
+                    scope.spawn(move || {
+                        view.run::<S::Data, _>(commands, since, |_, data| sys.run(data))
+                    })
+                })
+                .collect();
+            for thread in threads {
+                ran.extend(thread.join().expect("system panicked"));
+            }
+        });
+        ran.elapsed = start.elapsed();
+        self.finish_run(type_name::<S>(), tick, ran);
+        self.increment_change_tick();
+        for commands in commands {
+            self.apply_commands(commands);
+        }
+    }
+
+    /// Splits the entities and resources of the `World` between systems which don't
+    /// conflict, so that they can run at the same time. Returns a `View` for each
+    /// `Access`.
+    pub(crate) fn views(&mut self, accesses: &[Access]) -> Vec<View<'_>> {
+        let (ticks, removals) = (&self.ticks, &self.removals);
+        let mut views: Vec<View<'_>> = accesses
+            .iter()
+            .map(|_| View {
+                ticks: Some(ticks),
+                removals: Some(removals),
+                ..View::default()
+            })
+            .collect();
+        let mut union = Access::default();
+        for access in accesses {
+            union.extend(access);
+        }
+        for rows in self.storage.borrow(&self.entities, &union, |_| true) {
+            for (view, rows) in views.iter_mut().zip(rows.split(accesses)) {
+                view.rows.push(rows);
+            }
+        }
+        for (view, access) in views.iter_mut().zip(accesses) {
+            if !access.fetches_entities() {
+                view.rows = once();
+            }
+        }
+        let resources = split(
+            slots(&mut self.resources),
+            accesses,
+            Access::writes_resource,
+        );
+        for ((view, access), mut slots) in views.iter_mut().zip(accesses).zip(resources) {
+            slots.retain(|(type_id, _)| access.uses_resource(type_id));
+            view.resources = slots;
+        }
+        views
+    }
+
+    /// Applies queued `Commands` to the `World` in the order they were queued. Commands
+    /// on entities which don't exist (anymore) are skipped. Called by
+    /// `World::dispatch_system` once the `System` has visited every `Entity`.
+    pub fn apply_commands(&mut self, commands: Commands) {
+        for command in commands.commands {
+            match command {
+                Command::Spawn(entity) => {
+                    self.insert_entity(entity);
+                }
+                Command::Despawn(eid) => {
+                    let _ = self.destroy_entity(&eid);
+                }
+                Command::Insert(eid, type_id, component) => {
+                    if self.entities.contains(&eid) {
+                        self.ticks.insert(type_id, eid.index(), self.change_tick);
+                        self.storage.insert_boxed(eid.index(), type_id, component);
+                    }
+                }
+                Command::Remove(eid, type_id) => {
+                    self.remove_boxed(&eid, type_id);
+                }
             }
         }
     }
@@ -168,7 +909,7 @@ impl World {
 #[cfg(test)]
 mod test_world {
 
-    use crate::{Component, World};
+    use crate::{Component, EcsError, World};
     #[test]
     fn test_register_component() {
         #[derive(Debug, Clone, Copy)]
@@ -180,9 +921,14 @@ mod test_world {
         impl Component for Pos {}
 
         let mut world: World = Default::default();
+        assert!(matches!(
+            world.component_info::<Pos>(),
+            Err(EcsError::UnregisteredComponent(_))
+        ));
         let val = world.register_component::<Pos>();
 
         assert!(val);
+        assert!(world.component_info::<Pos>().is_ok());
     }
 
     #[test]
@@ -218,19 +964,20 @@ mod test_world {
         let e2_pos = world.get_component_for_entity::<Pos>(&e2);
         let e2_vel = world.get_component_for_entity::<Vel>(&e2);
 
-        assert!(e1_pos.is_some());
+        assert!(e1_pos.is_ok());
         assert!(e1_pos.unwrap().x == 0.0);
         assert!(e1_pos.unwrap().y == 0.0);
-        assert!(e1_vel.is_some());
+        assert!(e1_vel.is_ok());
         assert!(e1_vel.unwrap().x == 0.0);
         assert!(e1_vel.unwrap().y == 0.0);
-        assert!(e2_pos.is_some());
+        assert!(e2_pos.is_ok());
         assert!(e2_pos.unwrap().x == 3.0);
         assert!(e2_pos.unwrap().y == 3.0);
-        assert!(e2_vel.is_none());
+        assert!(e2_vel.is_err());
     }
 
     #[test]
+    #[allow(clippy::explicit_auto_deref)]
     fn test_remove_component_from_entity() {
         #[derive(Debug, Clone, Copy)]
         struct Pos {
@@ -260,31 +1007,33 @@ mod test_world {
         let e_pos = world.get_component_for_entity::<Pos>(&e);
         let e_vel = world.get_component_for_entity::<Vel>(&e);
 
-        assert!(e_pos.is_some());
+        assert!(e_pos.is_ok());
         assert!(e_pos.unwrap().x == 0.0);
         assert!(e_pos.unwrap().y == 0.0);
-        assert!(e_vel.is_some());
+        assert!(e_vel.is_ok());
         assert!(e_vel.unwrap().x == 0.0);
         assert!(e_vel.unwrap().y == 0.0);
 
         let val = world.remove_component_from_entity::<Vel>(&e);
-        assert!(val.is_some());
+        assert!(val.is_ok());
         let val = val.unwrap();
         assert_eq!((*val).x, 0.0);
         assert_eq!((*val).y, 0.0);
 
         let val = world.remove_component_from_entity::<Vel>(&e);
-        assert!(val.is_none());
+        assert!(matches!(val, Err(EcsError::MissingComponent(_))));
     }
 
     #[test]
     fn test_destroy_entity() {
+        #[allow(dead_code)]
         #[derive(Debug, Clone, Copy)]
         struct Pos {
             x: f64,
             y: f64,
         }
 
+        #[allow(dead_code)]
         #[derive(Debug, Clone, Copy)]
         struct Vel {
             x: f64,
@@ -305,11 +1054,149 @@ mod test_world {
             .build();
         let e2 = world.create_entity().with(Pos { x: 0.0, y: 0.0 }).build();
 
-        world.destroy_entity(&e1);
+        world.destroy_entity(&e1).unwrap();
 
-        let dead_e = world.entities.get(&e1);
-        assert!(dead_e.is_none());
-        let alive_e = world.entities.get(&e2);
-        assert!(alive_e.is_some());
+        assert!(!world.entities.contains(&e1));
+        assert!(world.entities.contains(&e2));
+        assert!(world.storage.get::<Pos>(e1.index()).is_none());
+    }
+
+    #[test]
+    fn test_stale_entity_ids() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Pos {
+            x: f64,
+        }
+
+        impl Component for Pos {}
+
+        let mut world: World = Default::default();
+        let e1 = world.create_entity().with(Pos { x: 1.0 }).build();
+        assert!(world.destroy_entity(&e1).is_ok());
+        assert_eq!(
+            world.destroy_entity(&e1).unwrap_err(),
+            EcsError::NoSuchEntity(e1)
+        );
+
+        let e2 = world.create_entity().with(Pos { x: 2.0 }).build();
+        assert_eq!(e1.index(), e2.index());
+        assert_ne!(e1, e2);
+
+        assert!(!world.is_alive(&e1));
+        assert_eq!(
+            world.get_component_for_entity::<Pos>(&e1),
+            Err(EcsError::NoSuchEntity(e1))
+        );
+        assert_eq!(
+            world.add_component_to_entity(&e1, Pos { x: 3.0 }),
+            Err(EcsError::NoSuchEntity(e1))
+        );
+        assert!(world.remove_component_from_entity::<Pos>(&e1).is_err());
+        assert_eq!(
+            world.get_component_for_entity::<Pos>(&e2),
+            Ok(&Pos { x: 2.0 })
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_world_backends {
+
+    use crate::{Backend, Commands, Component, Eid, Read, System, With, Without, World, Write};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos(i32);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Vel(i32);
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Frozen;
+
+    impl Component for Pos {}
+    impl Component for Vel {}
+    impl Component for Frozen {}
+
+    struct Move;
+    impl System for Move {
+        type Data = (Write<Pos>, Read<Vel>, Without<Frozen>);
+        fn run(&mut self, (pos, vel, _): (&mut Pos, &Vel, ())) {
+            pos.0 += vel.0;
+        }
+    }
+
+    struct Freeze;
+    impl System for Freeze {
+        type Data = (Eid, Read<Pos>, With<Vel>, Commands);
+        fn run(&mut self, (eid, pos, _, commands): (Eid, &Pos, (), &mut Commands)) {
+            if pos.0 > 20 {
+                commands.insert(eid, Frozen);
+                commands.remove::<Vel>(eid);
+            }
+            if pos.0 % 5 == 0 {
+                commands.spawn().with(Pos(pos.0 * 2));
+            }
+        }
+    }
+
+    type State = (Option<Pos>, Option<Vel>, bool);
+
+    fn state(world: &World, e: &Eid) -> State {
+        (
+            world.get_component_for_entity::<Pos>(e).ok().cloned(),
+            world.get_component_for_entity::<Vel>(e).ok().cloned(),
+            world.get_component_for_entity::<Frozen>(e).is_ok(),
+        )
+    }
+
+    /// Runs the same changes on a `World` and returns the components of the entities
+    /// created up front, and those of every entity sorted. Entities spawned by commands
+    /// get their `Eid` in the order the system visited the spawners in, which depends on
+    /// the backend.
+    fn run(backend: Backend) -> (Vec<Option<State>>, Vec<State>) {
+        let mut world = World::with_backend(backend);
+        let entities: Vec<Eid> = (0..30)
+            .map(|i| {
+                let builder = world.create_entity().with(Pos(i));
+                match i % 3 {
+                    0 => builder.build(),
+                    1 => builder.with(Vel(i % 4)).build(),
+                    _ => builder.with(Vel(1)).with(Frozen).build(),
+                }
+            })
+            .collect();
+        for e in entities.iter().step_by(4) {
+            world.remove_component_from_entity::<Frozen>(e).ok();
+            world.add_component_to_entity(e, Vel(2)).unwrap();
+        }
+        for e in entities.iter().step_by(7) {
+            world.destroy_entity(e).unwrap();
+        }
+        world.create_entity().build();
+        for _ in 0..5 {
+            world.dispatch_system(&mut Move);
+            world.dispatch_system(&mut Freeze);
+        }
+
+        let created = entities
+            .iter()
+            .map(|e| Some(state(&world, e)).filter(|_| world.is_alive(e)))
+            .collect();
+        let mut all: Vec<State> = world
+            .snapshot(0)
+            .entities()
+            .map(|e| state(&world, &e))
+            .collect();
+        all.sort_by_key(|(pos, vel, frozen)| (pos.map(|p| p.0), vel.map(|v| v.0), *frozen));
+        (created, all)
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let (created, all) = run(Backend::SparseSet);
+        assert_eq!((created.clone(), all.clone()), run(Backend::Archetype));
+        assert_eq!(created.iter().filter(|s| s.is_none()).count(), 5);
+        assert!(all.iter().any(|(_, _, frozen)| *frozen));
+        assert!(all.len() > 30);
     }
 }